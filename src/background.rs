@@ -0,0 +1,215 @@
+use crate::image::Framebuffer;
+use crate::light::{Light, LightSample};
+use crate::linear_algebra::{Ray, Vec3};
+use crate::random::rand;
+use crate::sampling::Distribution2D;
+use crate::{Colour, Point};
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+pub trait Background: Send + Sync {
+    fn colour(&self, ray: &Ray) -> Colour;
+}
+
+pub struct SolidBackground {
+    colour: Colour,
+}
+
+impl SolidBackground {
+    pub fn new(colour: Colour) -> Self {
+        Self { colour }
+    }
+}
+
+impl Background for SolidBackground {
+    fn colour(&self, _ray: &Ray) -> Colour {
+        self.colour
+    }
+}
+
+pub struct GradientBackground {
+    horizon: Colour,
+    zenith: Colour,
+}
+
+impl GradientBackground {
+    pub fn new(horizon: Colour, zenith: Colour) -> Self {
+        Self { horizon, zenith }
+    }
+}
+
+impl Default for GradientBackground {
+    fn default() -> Self {
+        Self::new(Colour::new(1.0, 1.0, 1.0), Colour::new(0.5, 0.7, 1.0))
+    }
+}
+
+impl Background for GradientBackground {
+    fn colour(&self, ray: &Ray) -> Colour {
+        let unit_direction = ray.direction.unit_vector();
+        let t = 0.5 * (unit_direction.y() + 1.0);
+        (1.0 - t) * self.horizon + t * self.zenith
+    }
+}
+
+// An equirectangular image of the surroundings, lit by and seen in every direction that
+// escapes the scene. The top row of the image lies straight up and its centre column along
+// -z; `rotation` turns the map about the y axis, in degrees. As a light it samples
+// directions in proportion to their brightness.
+pub struct EnvironmentMap {
+    image: Arc<Framebuffer>,
+    intensity: f64,
+    sin_rotation: f64,
+    cos_rotation: f64,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    pub fn new(image: Arc<Framebuffer>, rotation: f64, intensity: f64) -> Self {
+        let (width, height) = (image.width(), image.height());
+        // Rows near the poles cover less solid angle than those near the horizon.
+        let weights: Vec<f64> = (0..width * height)
+            .map(|i| {
+                let theta = PI * ((i / width) as f64 + 0.5) / height as f64;
+                let colour = image.get(i % width, i / width);
+                (0.2126 * colour.x() + 0.7152 * colour.y() + 0.0722 * colour.z()) * theta.sin()
+            })
+            .collect();
+        let rotation = rotation.to_radians();
+
+        Self {
+            distribution: Distribution2D::new(&weights, width, height),
+            image,
+            intensity,
+            sin_rotation: rotation.sin(),
+            cos_rotation: rotation.cos(),
+        }
+    }
+
+    // Image coordinates of a unit world direction, in [0, 1)^2 with v running downwards.
+    fn uv(&self, direction: Vec3) -> (f64, f64) {
+        let x = self.cos_rotation * direction.x() - self.sin_rotation * direction.z();
+        let z = self.sin_rotation * direction.x() + self.cos_rotation * direction.z();
+        let u = 0.5 + x.atan2(-z) / (2.0 * PI);
+        let v = direction.y().clamp(-1.0, 1.0).acos() / PI;
+        (u.clamp(0.0, 1.0 - f64::EPSILON), v.clamp(0.0, 1.0 - f64::EPSILON))
+    }
+
+    fn direction(&self, u: f64, v: f64) -> Vec3 {
+        let (phi, theta) = (2.0 * PI * (u - 0.5), PI * v);
+        let (x, y, z) = (theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos());
+        Vec3::new(
+            self.cos_rotation * x + self.sin_rotation * z,
+            y,
+            -self.sin_rotation * x + self.cos_rotation * z,
+        )
+    }
+
+    fn radiance(&self, u: f64, v: f64) -> Colour {
+        let x = ((u * self.image.width() as f64) as usize).min(self.image.width() - 1);
+        let y = ((v * self.image.height() as f64) as usize).min(self.image.height() - 1);
+        self.intensity * self.image.get(x, y)
+    }
+}
+
+impl Background for EnvironmentMap {
+    fn colour(&self, ray: &Ray) -> Colour {
+        let (u, v) = self.uv(ray.direction.unit_vector());
+        self.radiance(u, v)
+    }
+}
+
+impl Light for EnvironmentMap {
+    fn sample(&self, _origin: Point, _time: f64) -> Option<LightSample> {
+        let ((u, v), pdf) = self.distribution.sample(rand(0.0, 1.0), rand(0.0, 1.0));
+        let sin_theta = (PI * v).sin();
+        if pdf <= 0.0 || sin_theta <= 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            direction: self.direction(u, v),
+            distance: f64::INFINITY,
+            radiance: self.radiance(u, v),
+            pdf: pdf / (2.0 * PI * PI * sin_theta),
+            hittable: true,
+        })
+    }
+
+    fn pdf(&self, _origin: Point, direction: Vec3, _time: f64) -> f64 {
+        let (u, v) = self.uv(direction.unit_vector());
+        let sin_theta = (PI * v).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod environment_map_tests {
+        use super::*;
+
+        // A 4x2 map, dark everywhere but one bright texel in the upper half.
+        fn map(rotation: f64) -> EnvironmentMap {
+            let mut image = Framebuffer::new(4, 2);
+            for y in 0..2 {
+                for x in 0..4 {
+                    image.set(x, y, Colour::new(0.1, 0.1, 0.1));
+                }
+            }
+            image.set(2, 0, Colour::new(10.0, 10.0, 10.0));
+            EnvironmentMap::new(Arc::new(image), rotation, 2.0)
+        }
+
+        #[test]
+        fn lookup_and_rotation() {
+            // Column 2 starts at -z and sweeps towards +x.
+            let up_ahead = Ray::new(Point::new(0.0, 0.0, 0.0), Vec3::new(0.3, 1.0, -1.0));
+            assert!(approx_eq(map(0.0).colour(&up_ahead).x(), 20.0));
+            assert!(approx_eq(map(0.0).colour(&Ray::new(up_ahead.origin, -up_ahead.direction)).x(), 0.2));
+
+            // Turning the map a quarter turn about y carries the bright texel from -z to -x.
+            let up_left = Ray::new(Point::new(0.0, 0.0, 0.0), Vec3::new(-1.0, 1.0, -0.3));
+            assert!(approx_eq(map(90.0).colour(&up_left).x(), 20.0));
+            assert!(approx_eq(map(90.0).colour(&up_ahead).x(), 0.2));
+
+            let map = map(30.0);
+            let direction = map.direction(0.3, 0.7);
+            let (u, v) = map.uv(direction);
+            assert!((u - 0.3).abs() < 1e-9 && (v - 0.7).abs() < 1e-9);
+        }
+
+        #[test]
+        fn sampling_matches_pdf() {
+            crate::random::seed(7);
+            let map = map(45.0);
+            let origin = Point::new(0.0, 0.0, 0.0);
+            let mut bright = 0;
+            let mut estimate = 0.0;
+            for _ in 0..20000 {
+                let sample = map.sample(origin, 0.0).unwrap();
+                assert!((sample.pdf - map.pdf(origin, sample.direction, 0.0)).abs() < 1e-9 * sample.pdf);
+                assert!(approx_eq(sample.radiance.x(), map.colour(&Ray::new(origin, sample.direction)).x()));
+                if sample.radiance.x() > 1.0 {
+                    bright += 1;
+                }
+                estimate += sample.radiance.x() / sample.pdf;
+            }
+
+            // The bright texel carries nearly all of the power, and the estimate of the total
+            // radiance over the sphere converges to the one found by integrating the texels,
+            // each of which covers a solid angle of pi / 2.
+            assert!(bright > 18000);
+            let expected = 2.0 * (10.0 + 7.0 * 0.1) * PI / 2.0;
+            assert!((estimate / 20000.0 - expected).abs() < 0.02 * expected);
+        }
+    }
+
+    fn approx_eq(x: f64, y: f64) -> bool {
+        (x - y).abs() < 1e-9
+    }
+}
@@ -0,0 +1,246 @@
+use crate::geometry::{Aabb, HitRecord, Hittable, HittableList};
+use crate::linear_algebra::Ray;
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+const TRAVERSAL_COST: f64 = 0.125;
+
+pub struct BvhNode {
+    left: Arc<dyn Hittable>,
+    right: Arc<dyn Hittable>,
+    bounding_box: Option<Aabb>,
+}
+
+struct BuildEntry {
+    object: Arc<dyn Hittable>,
+    bounding_box: Aabb,
+    centroid: [f64; 3],
+}
+
+impl BvhNode {
+    pub fn new(list: &HittableList) -> Self {
+        let mut entries = Vec::<BuildEntry>::new();
+        let mut unbounded = HittableList::new();
+
+        for object in list.objects() {
+            match object.bounding_box() {
+                Some(bounding_box) => {
+                    let centroid = bounding_box.centroid();
+                    entries.push(BuildEntry {
+                        object: object.clone(),
+                        bounding_box,
+                        centroid: [centroid.x(), centroid.y(), centroid.z()],
+                    });
+                }
+                None => unbounded.add(object.clone()),
+            }
+        }
+
+        let bounded: Arc<dyn Hittable> = if entries.is_empty() {
+            Arc::new(HittableList::new())
+        } else {
+            BvhNode::build(entries)
+        };
+
+        // Objects without a bounding box (e.g. infinite planes) cannot be partitioned, so
+        // they sit beside the tree and are tested on every ray.
+        if unbounded.is_empty() {
+            let bounding_box = bounded.bounding_box();
+            Self {
+                left: bounded,
+                right: Arc::new(HittableList::new()),
+                bounding_box,
+            }
+        } else {
+            Self {
+                left: bounded,
+                right: Arc::new(unbounded),
+                bounding_box: None,
+            }
+        }
+    }
+
+    fn build(mut entries: Vec<BuildEntry>) -> Arc<dyn Hittable> {
+        if entries.len() == 1 {
+            return entries.pop().unwrap().object;
+        }
+
+        let bounding_box = entries
+            .iter()
+            .fold(Aabb::empty(), |acc, entry| acc.union(&entry.bounding_box));
+
+        let (axis, split) = BvhNode::surface_area_split(&mut entries, &bounding_box);
+        BvhNode::sort_along_axis(&mut entries, axis);
+
+        let right_entries = entries.split_off(split);
+        let left = BvhNode::build(entries);
+        let right = BvhNode::build(right_entries);
+
+        Arc::new(Self {
+            left,
+            right,
+            bounding_box: Some(bounding_box),
+        })
+    }
+
+    // Sweeps every candidate partition along each axis and picks the one minimising the
+    // surface area heuristic cost. Returns the axis and the number of entries on the left.
+    fn surface_area_split(entries: &mut [BuildEntry], bounding_box: &Aabb) -> (usize, usize) {
+        let n = entries.len();
+        let parent_area = bounding_box.surface_area();
+        let mut best_axis = 0;
+        let mut best_split = n / 2;
+        let mut best_cost = f64::INFINITY;
+        let mut right_areas = vec![0.0; n];
+
+        for axis in 0..3 {
+            BvhNode::sort_along_axis(entries, axis);
+
+            let mut right_box = Aabb::empty();
+            for i in (1..n).rev() {
+                right_box = right_box.union(&entries[i].bounding_box);
+                right_areas[i] = right_box.surface_area();
+            }
+
+            let mut left_box = Aabb::empty();
+            for i in 1..n {
+                left_box = left_box.union(&entries[i - 1].bounding_box);
+                let cost = TRAVERSAL_COST
+                    + (left_box.surface_area() * i as f64
+                        + right_areas[i] * (n - i) as f64)
+                        / parent_area;
+
+                if cost < best_cost {
+                    best_cost = cost;
+                    best_axis = axis;
+                    best_split = i;
+                }
+            }
+        }
+
+        // Degenerate boxes (zero area) make every cost NaN, so fall back to a median split.
+        if !best_cost.is_finite() {
+            let extent = bounding_box.max - bounding_box.min;
+            best_axis = (0..3)
+                .max_by(|a, b| extent[*a].partial_cmp(&extent[*b]).unwrap_or(Ordering::Equal))
+                .unwrap();
+            best_split = n / 2;
+        }
+
+        (best_axis, best_split)
+    }
+
+    fn sort_along_axis(entries: &mut [BuildEntry], axis: usize) {
+        entries.sort_by(|a, b| {
+            a.centroid[axis]
+                .partial_cmp(&b.centroid[axis])
+                .unwrap_or(Ordering::Equal)
+        });
+    }
+}
+
+impl Hittable for BvhNode {
+    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        if let Some(bounding_box) = &self.bounding_box {
+            if !bounding_box.hit(&ray, t_min, t_max) {
+                return None;
+            }
+        }
+
+        let left_hit = self.left.hit(ray, t_min, t_max);
+        let closest_t = left_hit.as_ref().map_or(t_max, |record| record.t);
+        let right_hit = self.right.hit(ray, t_min, closest_t);
+
+        right_hit.or(left_hit)
+    }
+
+    fn transmittance(&self, ray: Ray, t_min: f64, t_max: f64) -> f64 {
+        if let Some(bounding_box) = &self.bounding_box {
+            if !bounding_box.hit(&ray, t_min, t_max) {
+                return 1.0;
+            }
+        }
+
+        match self.left.transmittance(ray, t_min, t_max) {
+            0.0 => 0.0,
+            left => left * self.right.transmittance(ray, t_min, t_max),
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bounding_box
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::{Plane, Sphere};
+    use crate::linear_algebra::{random_unit_vector, Vec3};
+    use crate::materials::EmptyMaterial;
+    use crate::random::rand;
+
+    mod bvh_node_tests {
+        use super::*;
+
+        #[test]
+        fn matches_linear_traversal() {
+            let mut list = HittableList::new();
+            for _ in 0..200 {
+                let centre = Vec3::new(rand(-10.0, 10.0), rand(-10.0, 10.0), rand(-10.0, 10.0));
+                list.add(Arc::new(Sphere::new(centre, rand(0.1, 1.0), Arc::new(EmptyMaterial))));
+            }
+            let bvh = BvhNode::new(&list);
+
+            for _ in 0..1000 {
+                let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), random_unit_vector());
+                let expected = list.hit(ray, 0.001, f64::INFINITY);
+                let actual = bvh.hit(ray, 0.001, f64::INFINITY);
+
+                assert_eq!(expected.is_some(), actual.is_some());
+                if let (Some(expected), Some(actual)) = (expected, actual) {
+                    assert!(approx_eq(expected.t, actual.t, 1e-12));
+                }
+            }
+        }
+
+        #[test]
+        fn bounding_box() {
+            let mut list = HittableList::new();
+            list.add(Arc::new(Sphere::new(Vec3::new(-2.0, 0.0, 0.0), 1.0, Arc::new(EmptyMaterial))));
+            list.add(Arc::new(Sphere::new(Vec3::new(3.0, 0.0, 0.0), 0.5, Arc::new(EmptyMaterial))));
+            list.add(Arc::new(Sphere::new(Vec3::new(0.0, 4.0, 0.0), 1.0, Arc::new(EmptyMaterial))));
+            let bounding_box = BvhNode::new(&list).bounding_box().unwrap();
+
+            assert!(approx_eq(bounding_box.min.x(), -3.0, f64::EPSILON));
+            assert!(approx_eq(bounding_box.max.x(), 3.5, f64::EPSILON));
+            assert!(approx_eq(bounding_box.max.y(), 5.0, f64::EPSILON));
+        }
+
+        #[test]
+        fn unbounded_objects() {
+            let mut list = HittableList::new();
+            list.add(Arc::new(Sphere::new(Vec3::new(0.0, 0.0, -5.0), 1.0, Arc::new(EmptyMaterial))));
+            list.add(Arc::new(Plane::new(Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Arc::new(EmptyMaterial))));
+            let bvh = BvhNode::new(&list);
+            let sphere_ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+            let plane_ray = Ray::new(Vec3::new(50.0, 0.0, 50.0), Vec3::new(0.0, -1.0, 0.0));
+
+            assert!(bvh.bounding_box().is_none());
+            assert!(approx_eq(bvh.hit(sphere_ray, 0.0, 100.0).unwrap().t, 4.0, 1e-12));
+            assert!(approx_eq(bvh.hit(plane_ray, 0.0, 100.0).unwrap().t, 1.0, 1e-12));
+        }
+
+        #[test]
+        fn empty() {
+            let bvh = BvhNode::new(&HittableList::new());
+            let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+
+            assert!(bvh.hit(ray, 0.0, f64::INFINITY).is_none());
+        }
+    }
+
+    fn approx_eq(x: f64, y: f64, tolerance: f64) -> bool {
+        (x - y).abs() < tolerance
+    }
+}
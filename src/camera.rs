@@ -1,4 +1,5 @@
 use crate::linear_algebra::{random_vec_in_unit_disk, Ray, Vec3};
+use crate::random::rand;
 use std::f64::consts::PI;
 
 pub struct Camera {
@@ -8,8 +9,9 @@ pub struct Camera {
     vertical: Vec3,
     u: Vec3,
     v: Vec3,
-    w: Vec3,
     lens_radius: f64,
+    shutter_open: f64,
+    shutter_close: f64,
 }
 
 impl Camera {
@@ -44,18 +46,46 @@ impl Camera {
             vertical,
             u,
             v,
-            w,
             lens_radius,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
         }
     }
 
+    pub fn aspect_ratio(&self) -> f64 {
+        self.horizontal.length() / self.vertical.length()
+    }
+
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: f64) {
+        let centre = self.lower_left_corner + self.horizontal / 2.0 + self.vertical / 2.0;
+        self.horizontal *= aspect_ratio / self.aspect_ratio();
+        self.lower_left_corner = centre - self.horizontal / 2.0 - self.vertical / 2.0;
+    }
+
+    pub fn shutter(&self) -> (f64, f64) {
+        (self.shutter_open, self.shutter_close)
+    }
+
+    // Rays are spread uniformly over the time the shutter is open; an instantaneous shutter
+    // (the default) disables motion blur.
+    pub fn set_shutter(&mut self, open: f64, close: f64) {
+        self.shutter_open = open;
+        self.shutter_close = close;
+    }
+
     pub fn get_ray(&self, s: f64, t: f64) -> Ray {
         let random_direction = self.lens_radius * random_vec_in_unit_disk();
         let offset = self.u * random_direction.x() + self.v * random_direction.y();
+        let time = if self.shutter_close > self.shutter_open {
+            rand(self.shutter_open, self.shutter_close)
+        } else {
+            self.shutter_open
+        };
 
-        Ray::new(
+        Ray::with_time(
             self.origin + offset,
             self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
+            time,
         )
     }
 }
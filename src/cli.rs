@@ -0,0 +1,162 @@
+use raytracer::image::ImageFormat;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+pub const USAGE: &str = "\
+Usage: raytracer [OPTIONS] [SCENE]
+
+Renders SCENE (a TOML scene description) or, if no scene is given, the built-in
+random spheres scene. Options override the values in the scene file.
+
+Options:
+  -o, --output <PATH>      Write the image to PATH instead of standard output
+  -f, --format <FORMAT>    Image format: ppm, ppm-binary, png, exr or pfm [default:
+                           from the output extension, or ppm]
+  -w, --width <PIXELS>     Image width [default: 1200]
+  -H, --height <PIXELS>    Image height [default: width / 1.5]
+  -s, --samples <N>        Samples per pixel [default: 500]
+  -d, --max-depth <N>      Maximum ray bounce depth [default: 50]
+      --seed <N>           Seed the random number generator for reproducible renders
+  -t, --threads <N>        Number of worker threads [default: available cores]
+  -h, --help               Print this help message
+";
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Options {
+    pub scene: Option<PathBuf>,
+    pub output: Option<PathBuf>,
+    pub format: Option<ImageFormat>,
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub samples_per_pixel: Option<usize>,
+    pub max_depth: Option<usize>,
+    pub seed: Option<u64>,
+    pub threads: Option<usize>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Render(Options),
+    Help,
+}
+
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            return Ok(Command::Help);
+        }
+
+        if !arg.starts_with('-') {
+            if options.scene.is_some() {
+                return Err(format!("unexpected argument '{}'", arg));
+            }
+            options.scene = Some(PathBuf::from(arg));
+            continue;
+        }
+
+        // Long options may carry their value inline as `--name=value`.
+        let (name, inline_value) = match arg.find('=') {
+            Some(index) if arg.starts_with("--") => (arg[..index].to_string(), Some(arg[index + 1..].to_string())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("option '{}' requires a value", name))
+        };
+
+        match name.as_str() {
+            "-o" | "--output" => options.output = Some(PathBuf::from(value()?)),
+            "-f" | "--format" => {
+                let value = value()?;
+                options.format = Some(
+                    ImageFormat::from_name(&value)
+                        .ok_or_else(|| format!("invalid value '{}' for option '{}'", value, name))?,
+                );
+            }
+            "-w" | "--width" => options.width = Some(parse_positive(&name, &value()?)?),
+            "-H" | "--height" => options.height = Some(parse_positive(&name, &value()?)?),
+            "-s" | "--samples" => options.samples_per_pixel = Some(parse_positive(&name, &value()?)?),
+            "-d" | "--max-depth" => options.max_depth = Some(parse_positive(&name, &value()?)?),
+            "--seed" => options.seed = Some(parse_value(&name, &value()?)?),
+            "-t" | "--threads" => options.threads = Some(parse_positive(&name, &value()?)?),
+            _ => return Err(format!("unknown option '{}'", name)),
+        }
+    }
+
+    Ok(Command::Render(options))
+}
+
+fn parse_value<T: FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse::<T>()
+        .map_err(|_| format!("invalid value '{}' for option '{}'", value, name))
+}
+
+fn parse_positive(name: &str, value: &str) -> Result<usize, String> {
+    match parse_value::<usize>(name, value)? {
+        0 => Err(format!("option '{}' must be greater than zero", name)),
+        n => Ok(n),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    mod parse_args_tests {
+        use super::*;
+
+        #[test]
+        fn defaults() {
+            assert_eq!(parse(&[]), Ok(Command::Render(Options::default())));
+        }
+
+        #[test]
+        fn all_options() {
+            let command = parse(&[
+                "scene.toml", "-o", "out.ppm", "-f", "ppm-binary", "--width=640", "-H", "480", "-s", "16", "--max-depth", "8", "--seed",
+                "42", "-t", "4",
+            ]);
+
+            assert_eq!(
+                command,
+                Ok(Command::Render(Options {
+                    scene: Some(PathBuf::from("scene.toml")),
+                    output: Some(PathBuf::from("out.ppm")),
+                    format: Some(ImageFormat::PpmBinary),
+                    width: Some(640),
+                    height: Some(480),
+                    samples_per_pixel: Some(16),
+                    max_depth: Some(8),
+                    seed: Some(42),
+                    threads: Some(4),
+                }))
+            );
+        }
+
+        #[test]
+        fn help() {
+            assert_eq!(parse(&["--width", "10", "--help"]), Ok(Command::Help));
+            assert_eq!(parse(&["-h"]), Ok(Command::Help));
+        }
+
+        #[test]
+        fn errors() {
+            assert_eq!(parse(&["--bogus"]), Err(String::from("unknown option '--bogus'")));
+            assert_eq!(parse(&["--width"]), Err(String::from("option '--width' requires a value")));
+            assert_eq!(parse(&["-w", "wide"]), Err(String::from("invalid value 'wide' for option '-w'")));
+            assert_eq!(parse(&["-f", "gif"]), Err(String::from("invalid value 'gif' for option '-f'")));
+            assert_eq!(parse(&["-t", "0"]), Err(String::from("option '-t' must be greater than zero")));
+            assert_eq!(parse(&["a.toml", "b.toml"]), Err(String::from("unexpected argument 'b.toml'")));
+        }
+    }
+}
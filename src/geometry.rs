@@ -1,11 +1,113 @@
-use crate::linear_algebra::{Ray, Vec3};
-use std::rc::Rc;
-use crate::materials::{Material, EmptyMaterial};
+use crate::linear_algebra::{random_in_cone, random_unit_vector, Onb, Ray, Vec3};
+use crate::random::rand;
+use std::f64::consts::PI;
+use std::sync::Arc;
+use crate::materials::Material;
 
 type Point = Vec3;
 
-pub trait Hittable {
+pub trait Hittable: Send + Sync {
     fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+    fn bounding_box(&self) -> Option<Aabb>;
+
+    // The fraction of light that passes along the ray between t_min and t_max. Surfaces are
+    // opaque; participating media override this with an unbiased, possibly random, estimate.
+    fn transmittance(&self, ray: Ray, t_min: f64, t_max: f64) -> f64 {
+        if self.hit(ray, t_min, t_max).is_some() {
+            0.0
+        } else {
+            1.0
+        }
+    }
+
+    // For objects that can be sampled as lights: a direction from `origin` towards a random
+    // point on the object as it stands at `time`, and the density over solid angle with which
+    // that sampling picks `direction`. Other objects give None and zero.
+    fn random_direction(&self, _origin: Point, _time: f64) -> Option<Vec3> {
+        None
+    }
+
+    fn pdf_value(&self, _origin: Point, _direction: Vec3, _time: f64) -> f64 {
+        0.0
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Aabb {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl Aabb {
+    pub fn new(min: Point, max: Point) -> Self {
+        Self { min, max }
+    }
+
+    pub fn empty() -> Self {
+        Self {
+            min: Point::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            max: Point::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x() > self.max.x() || self.min.y() > self.max.y() || self.min.z() > self.max.z()
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb::new(
+            Point::new(
+                f64::min(self.min.x(), other.min.x()),
+                f64::min(self.min.y(), other.min.y()),
+                f64::min(self.min.z(), other.min.z()),
+            ),
+            Point::new(
+                f64::max(self.max.x(), other.max.x()),
+                f64::max(self.max.y(), other.max.y()),
+                f64::max(self.max.z(), other.max.z()),
+            ),
+        )
+    }
+
+    pub fn include(&self, point: Point) -> Aabb {
+        self.union(&Aabb::new(point, point))
+    }
+
+    pub fn centroid(&self) -> Point {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let d = self.max - self.min;
+        2.0 * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x())
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> bool {
+        let mut t_min = t_min;
+        let mut t_max = t_max;
+
+        for axis in 0..3 {
+            let inverse_direction = 1.0 / ray.direction[axis];
+            let mut t0 = (self.min[axis] - ray.origin[axis]) * inverse_direction;
+            let mut t1 = (self.max[axis] - ray.origin[axis]) * inverse_direction;
+
+            if inverse_direction < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+
+            if t_max < t_min {
+                return false;
+            }
+        }
+
+        true
+    }
 }
 
 pub struct HitRecord {
@@ -13,11 +115,23 @@ pub struct HitRecord {
     pub hit_point: Point,
     pub normal: Vec3,
     pub front_face: bool,
-    pub material: Rc<dyn Material>
+    pub material: Arc<dyn Material>,
+    pub barycentric: Option<(f64, f64)>,
+    // The index in the scene's light list of the light this surface belongs to, if any.
+    pub light: Option<usize>,
+    // Surface texture coordinates, both in [0, 1] on bounded primitives.
+    pub u: f64,
+    pub v: f64,
+    // The direction in which u increases across the surface, not necessarily of unit length,
+    // or zero where there is none. Anisotropic materials are oriented along it.
+    pub tangent: Vec3,
+    // The refractive index of whatever lies on the other side of the surface from the
+    // material's interior, filled in by the integrator for nested media.
+    pub surrounding_index: f64,
 }
 
 impl HitRecord {
-    pub fn new(t: f64, ray: Ray, outward_normal: Vec3, material: Rc<dyn Material>) -> Self {
+    pub fn new(t: f64, ray: Ray, outward_normal: Vec3, material: Arc<dyn Material>) -> Self {
         let hit_point = ray.at(t);
         let front_face = ray.direction.dot(&outward_normal) < 0.0;
 
@@ -33,67 +147,236 @@ impl HitRecord {
             normal,
             front_face,
             material,
+            barycentric: None,
+            light: None,
+            u: 0.0,
+            v: 0.0,
+            tangent: Vec3::new(0.0, 0.0, 0.0),
+            surrounding_index: 1.0,
         }
     }
+
+    pub fn set_shading_normal(&mut self, outward_normal: Vec3) {
+        self.normal = if self.front_face {
+            outward_normal
+        } else {
+            -outward_normal
+        };
+    }
 }
 
 pub struct Sphere {
     centre: Point,
     radius: f64,
-    material: Rc<dyn Material>
+    material: Arc<dyn Material>
 }
 
 impl Sphere {
-    pub fn new(centre: Point, radius: f64, material: Rc<dyn Material>) -> Self {
+    pub fn new(centre: Point, radius: f64, material: Arc<dyn Material>) -> Self {
         Self { centre, radius, material }
     }
 }
 
 impl Hittable for Sphere {
     fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let oc = ray.origin - self.centre;
-        let a = ray.direction.length_squared();
-        let half_b = oc.dot(&ray.direction);
-        let c = oc.length_squared() - self.radius * self.radius;
-        let discriminant = half_b * half_b - a * c;
+        sphere_hit(self.centre, self.radius, &self.material, ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(sphere_box(self.centre, self.radius))
+    }
+
+    fn random_direction(&self, origin: Point, _time: f64) -> Option<Vec3> {
+        Some(sphere_random_direction(self.centre, self.radius, origin))
+    }
+
+    fn pdf_value(&self, origin: Point, direction: Vec3, time: f64) -> f64 {
+        sphere_pdf(self, self.centre, self.radius, origin, direction, time)
+    }
+}
+
+// From outside, only the cap facing `origin` is visible, so directions are drawn uniformly
+// from the cone it subtends.
+fn sphere_random_direction(centre: Point, radius: f64, origin: Point) -> Vec3 {
+    let to_centre = centre - origin;
+    let radius_squared = radius * radius;
+    if to_centre.length_squared() <= radius_squared {
+        return centre + radius * random_unit_vector() - origin;
+    }
+
+    let cos_theta_max = (1.0 - radius_squared / to_centre.length_squared()).sqrt();
+    let direction = random_in_cone(cos_theta_max);
+    Onb::new(to_centre).local(direction.x(), direction.y(), direction.z())
+}
+
+fn sphere_pdf(sphere: &dyn Hittable, centre: Point, radius: f64, origin: Point, direction: Vec3, time: f64) -> f64 {
+    let to_centre = centre - origin;
+    let radius_squared = radius * radius;
+    if to_centre.length_squared() <= radius_squared {
+        return area_pdf(sphere, origin, direction, time, 4.0 * PI * radius_squared);
+    }
+    if sphere.hit(Ray::with_time(origin, direction, time), 0.001, f64::INFINITY).is_none() {
+        return 0.0;
+    }
 
-        if discriminant <= 0.0 {
+    let cos_theta_max = (1.0 - radius_squared / to_centre.length_squared()).sqrt();
+    1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+}
+
+// The solid-angle density at `origin` of sampling points uniformly over an object's surface,
+// found by converting 1 / `area` through the distance and angle to the point `direction` hits.
+fn area_pdf(object: &dyn Hittable, origin: Point, direction: Vec3, time: f64, area: f64) -> f64 {
+    let ray = Ray::with_time(origin, direction, time);
+    let Some(record) = object.hit(ray, 0.001, f64::INFINITY) else {
+        return 0.0;
+    };
+
+    let distance_squared = record.t * record.t * direction.length_squared();
+    let cosine = (direction.dot(&record.normal) / direction.length()).abs();
+    if cosine < 1e-9 {
+        return 0.0;
+    }
+    distance_squared / (cosine * area)
+}
+
+fn sphere_hit(
+    centre: Point,
+    radius: f64,
+    material: &Arc<dyn Material>,
+    ray: Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Option<HitRecord> {
+    let oc = ray.origin - centre;
+    let a = ray.direction.length_squared();
+    let half_b = oc.dot(&ray.direction);
+    let c = oc.length_squared() - radius * radius;
+    let discriminant = half_b * half_b - a * c;
+
+    if discriminant <= 0.0 {
+        return None;
+    }
+
+    let mut root = (-half_b - discriminant.sqrt()) / a;
+
+    if root < t_min || root > t_max {
+        root = (-half_b + discriminant.sqrt()) / a;
+        if root < t_min || root > t_max {
             return None;
         }
+    }
 
-        let mut root = (-half_b - discriminant.sqrt()) / a;
+    let outward_normal = (ray.at(root) - centre) / radius;
+    let (u, v) = sphere_uv(outward_normal);
 
-        if root < t_min || root > t_max {
-            root = (-half_b + discriminant.sqrt()) / a;
-            if root < t_min || root > t_max {
-                return None;
-            }
+    let mut record = HitRecord::new(root, ray, outward_normal, material.clone());
+    record.u = u;
+    record.v = v;
+    // Longitude runs round the y axis, vanishing at the poles.
+    record.tangent = Vec3::new(outward_normal.z(), 0.0, -outward_normal.x());
+    Some(record)
+}
+
+// Maps a point on the unit sphere to longitude u (starting at -x and running round through
+// +z) and latitude v (from the south pole at -y to the north pole at +y).
+fn sphere_uv(point: Point) -> (f64, f64) {
+    let theta = (-point.y()).clamp(-1.0, 1.0).acos();
+    let phi = (-point.z()).atan2(point.x()) + PI;
+
+    (phi / (2.0 * PI), theta / PI)
+}
+
+fn sphere_box(centre: Point, radius: f64) -> Aabb {
+    let extent = Vec3::new(radius, radius, radius);
+    Aabb::new(centre - extent, centre + extent)
+}
+
+// A sphere whose centre moves in a straight line from `centre0` at `time0` to `centre1` at
+// `time1`. It rests at the nearer end outside that interval.
+pub struct MovingSphere {
+    centre0: Point,
+    centre1: Point,
+    time0: f64,
+    time1: f64,
+    radius: f64,
+    material: Arc<dyn Material>,
+}
+
+impl MovingSphere {
+    pub fn new(centre0: Point, centre1: Point, time0: f64, time1: f64, radius: f64, material: Arc<dyn Material>) -> Self {
+        Self {
+            centre0,
+            centre1,
+            time0,
+            time1,
+            radius,
+            material,
+        }
+    }
+
+    pub fn centre(&self, time: f64) -> Point {
+        if self.time1 <= self.time0 {
+            return self.centre0;
         }
+        let s = ((time - self.time0) / (self.time1 - self.time0)).clamp(0.0, 1.0);
+        self.centre0 + s * (self.centre1 - self.centre0)
+    }
+}
+
+impl Hittable for MovingSphere {
+    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        sphere_hit(self.centre(ray.time), self.radius, &self.material, ray, t_min, t_max)
+    }
 
-        let outward_normal = (ray.at(root) - self.centre) / self.radius;
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(sphere_box(self.centre0, self.radius).union(&sphere_box(self.centre1, self.radius)))
+    }
+
+    fn random_direction(&self, origin: Point, time: f64) -> Option<Vec3> {
+        Some(sphere_random_direction(self.centre(time), self.radius, origin))
+    }
 
-        Some(HitRecord::new(root, ray, outward_normal, self.material.clone()))
+    fn pdf_value(&self, origin: Point, direction: Vec3, time: f64) -> f64 {
+        sphere_pdf(self, self.centre(time), self.radius, origin, direction, time)
     }
 }
 
 pub struct HittableList {
-    objects: Vec<Rc<dyn Hittable>>,
+    objects: Vec<Arc<dyn Hittable>>,
 }
 
 impl HittableList {
     pub fn new() -> Self {
         Self {
-            objects: Vec::<Rc<dyn Hittable>>::new(),
+            objects: Vec::<Arc<dyn Hittable>>::new(),
         }
     }
 
-    pub fn add(&mut self, item: Rc<dyn Hittable>) {
+    pub fn add(&mut self, item: Arc<dyn Hittable>) {
         self.objects.push(item);
     }
 
     pub fn clear(&mut self) {
         self.objects.clear();
     }
+
+    pub fn objects(&self) -> &[Arc<dyn Hittable>] {
+        &self.objects
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+}
+
+impl Default for HittableList {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Hittable for HittableList {
@@ -112,16 +395,398 @@ impl Hittable for HittableList {
 
         closest_hit
     }
+
+    // Picks an object uniformly, so the density is the average of the objects' densities.
+    fn random_direction(&self, origin: Point, time: f64) -> Option<Vec3> {
+        if self.objects.is_empty() {
+            return None;
+        }
+        let index = (rand(0.0, self.objects.len() as f64) as usize).min(self.objects.len() - 1);
+        self.objects[index].random_direction(origin, time)
+    }
+
+    fn pdf_value(&self, origin: Point, direction: Vec3, time: f64) -> f64 {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
+        let total: f64 = self
+            .objects
+            .iter()
+            .map(|object| object.pdf_value(origin, direction, time))
+            .sum();
+        total / self.objects.len() as f64
+    }
+
+    fn transmittance(&self, ray: Ray, t_min: f64, t_max: f64) -> f64 {
+        let mut transmittance = 1.0;
+
+        for object in &self.objects {
+            transmittance *= object.transmittance(ray, t_min, t_max);
+            if transmittance == 0.0 {
+                break;
+            }
+        }
+
+        transmittance
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let mut bounding_box = Aabb::empty();
+
+        for object in &self.objects {
+            bounding_box = bounding_box.union(&object.bounding_box()?);
+        }
+
+        Some(bounding_box)
+    }
+}
+
+// Shared by the axis-aligned rectangles: the rectangle spans [a0, a1] x [b0, b1] on the
+// `a` and `b` axes and sits at `k` on the remaining `c` axis.
+fn axis_rect_hit(
+    ray: Ray,
+    t_min: f64,
+    t_max: f64,
+    (a, b, c): (usize, usize, usize),
+    [a0, a1, b0, b1]: [f64; 4],
+    k: f64,
+    material: &Arc<dyn Material>,
+) -> Option<HitRecord> {
+    let t = (k - ray.origin[c]) / ray.direction[c];
+    if !(t >= t_min && t <= t_max) {
+        return None;
+    }
+
+    let hit_point = ray.at(t);
+    if hit_point[a] < a0 || hit_point[a] > a1 || hit_point[b] < b0 || hit_point[b] > b1 {
+        return None;
+    }
+
+    let mut outward_normal = [0.0; 3];
+    outward_normal[c] = 1.0;
+    let outward_normal = Vec3::new(outward_normal[0], outward_normal[1], outward_normal[2]);
+
+    let mut record = HitRecord::new(t, ray, outward_normal, material.clone());
+    record.u = (hit_point[a] - a0) / (a1 - a0);
+    record.v = (hit_point[b] - b0) / (b1 - b0);
+    let mut tangent = [0.0; 3];
+    tangent[a] = 1.0;
+    record.tangent = Vec3::new(tangent[0], tangent[1], tangent[2]);
+    Some(record)
+}
+
+// A uniformly distributed point on an axis-aligned rectangle, laid out as for `axis_rect_hit`.
+fn axis_rect_point((a, b, c): (usize, usize, usize), [a0, a1, b0, b1]: [f64; 4], k: f64) -> Point {
+    let mut point = [0.0; 3];
+    point[a] = rand(a0, a1);
+    point[b] = rand(b0, b1);
+    point[c] = k;
+    Point::new(point[0], point[1], point[2])
+}
+
+// Rectangles have no thickness, so their boxes are padded slightly along the normal axis.
+const RECT_PADDING: f64 = 1e-4;
+
+pub struct XYRect {
+    x0: f64,
+    x1: f64,
+    y0: f64,
+    y1: f64,
+    k: f64,
+    material: Arc<dyn Material>,
+}
+
+impl XYRect {
+    pub fn new(x0: f64, x1: f64, y0: f64, y1: f64, k: f64, material: Arc<dyn Material>) -> Self {
+        Self { x0, x1, y0, y1, k, material }
+    }
+}
+
+impl Hittable for XYRect {
+    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        axis_rect_hit(ray, t_min, t_max, (0, 1, 2), [self.x0, self.x1, self.y0, self.y1], self.k, &self.material)
+    }
+
+    fn random_direction(&self, origin: Point, _time: f64) -> Option<Vec3> {
+        Some(axis_rect_point((0, 1, 2), [self.x0, self.x1, self.y0, self.y1], self.k) - origin)
+    }
+
+    fn pdf_value(&self, origin: Point, direction: Vec3, time: f64) -> f64 {
+        area_pdf(self, origin, direction, time, (self.x1 - self.x0) * (self.y1 - self.y0))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::new(
+            Point::new(self.x0, self.y0, self.k - RECT_PADDING),
+            Point::new(self.x1, self.y1, self.k + RECT_PADDING),
+        ))
+    }
+}
+
+pub struct XZRect {
+    x0: f64,
+    x1: f64,
+    z0: f64,
+    z1: f64,
+    k: f64,
+    material: Arc<dyn Material>,
+}
+
+impl XZRect {
+    pub fn new(x0: f64, x1: f64, z0: f64, z1: f64, k: f64, material: Arc<dyn Material>) -> Self {
+        Self { x0, x1, z0, z1, k, material }
+    }
+}
+
+impl Hittable for XZRect {
+    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        axis_rect_hit(ray, t_min, t_max, (0, 2, 1), [self.x0, self.x1, self.z0, self.z1], self.k, &self.material)
+    }
+
+    fn random_direction(&self, origin: Point, _time: f64) -> Option<Vec3> {
+        Some(axis_rect_point((0, 2, 1), [self.x0, self.x1, self.z0, self.z1], self.k) - origin)
+    }
+
+    fn pdf_value(&self, origin: Point, direction: Vec3, time: f64) -> f64 {
+        area_pdf(self, origin, direction, time, (self.x1 - self.x0) * (self.z1 - self.z0))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::new(
+            Point::new(self.x0, self.k - RECT_PADDING, self.z0),
+            Point::new(self.x1, self.k + RECT_PADDING, self.z1),
+        ))
+    }
+}
+
+pub struct YZRect {
+    y0: f64,
+    y1: f64,
+    z0: f64,
+    z1: f64,
+    k: f64,
+    material: Arc<dyn Material>,
+}
+
+impl YZRect {
+    pub fn new(y0: f64, y1: f64, z0: f64, z1: f64, k: f64, material: Arc<dyn Material>) -> Self {
+        Self { y0, y1, z0, z1, k, material }
+    }
+}
+
+impl Hittable for YZRect {
+    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        axis_rect_hit(ray, t_min, t_max, (1, 2, 0), [self.y0, self.y1, self.z0, self.z1], self.k, &self.material)
+    }
+
+    fn random_direction(&self, origin: Point, _time: f64) -> Option<Vec3> {
+        Some(axis_rect_point((1, 2, 0), [self.y0, self.y1, self.z0, self.z1], self.k) - origin)
+    }
+
+    fn pdf_value(&self, origin: Point, direction: Vec3, time: f64) -> f64 {
+        area_pdf(self, origin, direction, time, (self.y1 - self.y0) * (self.z1 - self.z0))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::new(
+            Point::new(self.k - RECT_PADDING, self.y0, self.z0),
+            Point::new(self.k + RECT_PADDING, self.y1, self.z1),
+        ))
+    }
+}
+
+// Reverses which side of a surface counts as the outside, e.g. for the faces of a box whose
+// outward normal points down an axis.
+pub struct FlipFace {
+    object: Arc<dyn Hittable>,
+}
+
+impl FlipFace {
+    pub fn new(object: Arc<dyn Hittable>) -> Self {
+        Self { object }
+    }
+}
+
+impl Hittable for FlipFace {
+    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let mut record = self.object.hit(ray, t_min, t_max)?;
+        record.front_face = !record.front_face;
+        Some(record)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.object.bounding_box()
+    }
+
+    fn random_direction(&self, origin: Point, time: f64) -> Option<Vec3> {
+        self.object.random_direction(origin, time)
+    }
+
+    fn pdf_value(&self, origin: Point, direction: Vec3, time: f64) -> f64 {
+        self.object.pdf_value(origin, direction, time)
+    }
+}
+
+// An axis-aligned box built from six rectangles. Named to avoid shadowing `std::boxed::Box`.
+pub struct Cuboid {
+    min: Point,
+    max: Point,
+    sides: HittableList,
+}
+
+impl Cuboid {
+    pub fn new(min: Point, max: Point, material: Arc<dyn Material>) -> Self {
+        let mut sides = HittableList::new();
+
+        sides.add(Arc::new(XYRect::new(min.x(), max.x(), min.y(), max.y(), max.z(), material.clone())));
+        sides.add(Arc::new(FlipFace::new(Arc::new(XYRect::new(
+            min.x(),
+            max.x(),
+            min.y(),
+            max.y(),
+            min.z(),
+            material.clone(),
+        )))));
+
+        sides.add(Arc::new(XZRect::new(min.x(), max.x(), min.z(), max.z(), max.y(), material.clone())));
+        sides.add(Arc::new(FlipFace::new(Arc::new(XZRect::new(
+            min.x(),
+            max.x(),
+            min.z(),
+            max.z(),
+            min.y(),
+            material.clone(),
+        )))));
+
+        sides.add(Arc::new(YZRect::new(min.y(), max.y(), min.z(), max.z(), max.x(), material.clone())));
+        sides.add(Arc::new(FlipFace::new(Arc::new(YZRect::new(
+            min.y(),
+            max.y(),
+            min.z(),
+            max.z(),
+            min.x(),
+            material,
+        )))));
+
+        Self { min, max, sides }
+    }
+}
+
+impl Hittable for Cuboid {
+    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.sides.hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::new(self.min, self.max))
+    }
+
+    fn random_direction(&self, origin: Point, time: f64) -> Option<Vec3> {
+        self.sides.random_direction(origin, time)
+    }
+
+    fn pdf_value(&self, origin: Point, direction: Vec3, time: f64) -> f64 {
+        self.sides.pdf_value(origin, direction, time)
+    }
+}
+
+pub struct Plane {
+    point: Point,
+    normal: Vec3,
+    tangent: Vec3,
+    bitangent: Vec3,
+    material: Arc<dyn Material>,
+}
+
+impl Plane {
+    pub fn new(point: Point, normal: Vec3, material: Arc<dyn Material>) -> Self {
+        let normal = normal.unit_vector();
+        // Any axis not too close to the normal gives a stable tangent frame.
+        let axis = if normal.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let bitangent = normal.cross(&axis).unit_vector();
+        let tangent = bitangent.cross(&normal);
+
+        Self {
+            point,
+            normal,
+            tangent,
+            bitangent,
+            material,
+        }
+    }
+}
+
+impl Hittable for Plane {
+    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let denominator = self.normal.dot(&ray.direction);
+        if denominator.abs() < 1e-12 {
+            return None;
+        }
+
+        let t = (self.point - ray.origin).dot(&self.normal) / denominator;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        // The plane is unbounded, so u and v are distances along the tangent frame from
+        // `point` rather than values in [0, 1].
+        let mut record = HitRecord::new(t, ray, self.normal, self.material.clone());
+        let offset = record.hit_point - self.point;
+        record.u = offset.dot(&self.tangent);
+        record.v = offset.dot(&self.bitangent);
+        record.tangent = self.tangent;
+        Some(record)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::materials::EmptyMaterial;
+
+    mod aabb_tests {
+        use super::*;
+
+        #[test]
+        fn hit() {
+            let aabb = Aabb::new(Point::new(-1.0, -1.0, -3.0), Point::new(1.0, 1.0, -1.0));
+            let hit_ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+            let miss_ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, -0.1));
+            let axis_parallel_ray = Ray::new(Vec3::new(0.5, 0.5, 0.0), Vec3::new(0.0, 0.0, -1.0));
+
+            assert!(aabb.hit(&hit_ray, 0.0, 100.0));
+            assert!(!aabb.hit(&hit_ray, 0.0, 0.5));
+            assert!(!aabb.hit(&miss_ray, 0.0, 100.0));
+            assert!(aabb.hit(&axis_parallel_ray, 0.0, 100.0));
+        }
+
+        #[test]
+        fn union() {
+            let a = Aabb::new(Point::new(0.0, 0.0, 0.0), Point::new(1.0, 1.0, 1.0));
+            let b = Aabb::new(Point::new(-1.0, 0.5, 0.5), Point::new(0.5, 2.0, 0.5));
+            let c = a.union(&b);
+
+            assert!(approx_eq(c.min.x(), -1.0, f64::EPSILON));
+            assert!(approx_eq(c.max.y(), 2.0, f64::EPSILON));
+            assert!(approx_eq(c.surface_area(), 2.0 * (4.0 + 2.0 + 2.0), f64::EPSILON));
+            assert!(Aabb::empty().is_empty());
+            assert!(approx_eq(Aabb::empty().union(&a).surface_area(), 6.0, f64::EPSILON));
+        }
+    }
+
     mod sphere_tests {
         use super::*;
         #[test]
         fn hit() {
-            let sphere = Sphere::new(Point::new(0.0, 0.0, -2.0), 1.0, Rc::new(EmptyMaterial));
+            let sphere = Sphere::new(Point::new(0.0, 0.0, -2.0), 1.0, Arc::new(EmptyMaterial));
             let hit_ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
             let miss_ray = Ray::new(hit_ray.origin, -hit_ray.origin);
             let internal_ray = Ray::new(sphere.centre, hit_ray.direction);
@@ -136,6 +801,163 @@ mod tests {
             assert!(hit_record.front_face);
             assert!(!internal_hit_record.front_face);
         }
+
+        #[test]
+        fn texture_coordinates() {
+            let sphere = Sphere::new(Point::new(0.0, 0.0, 0.0), 2.0, Arc::new(EmptyMaterial));
+            let cases = [
+                (Vec3::new(1.0, 0.0, 0.0), 0.5, 0.5),
+                (Vec3::new(0.0, 1.0, 0.0), 0.5, 1.0),
+                (Vec3::new(0.0, -1.0, 0.0), 0.5, 0.0),
+                (Vec3::new(0.0, 0.0, 1.0), 0.25, 0.5),
+                (Vec3::new(0.0, 0.0, -1.0), 0.75, 0.5),
+            ];
+
+            for (direction, u, v) in cases {
+                let ray = Ray::new(5.0 * direction, -direction);
+                let record = sphere.hit(ray, 0.0, 100.0).unwrap();
+                assert!(approx_eq(record.u, u, 1e-12));
+                assert!(approx_eq(record.v, v, 1e-12));
+            }
+        }
+
+        #[test]
+        fn tangent() {
+            let sphere = Sphere::new(Point::new(0.0, 0.0, 0.0), 2.0, Arc::new(EmptyMaterial));
+            let direction = Vec3::new(0.6, 0.3, 0.4).unit_vector();
+            let record = sphere.hit(Ray::new(5.0 * direction, -direction), 0.0, 100.0).unwrap();
+            assert!(approx_eq(record.tangent.dot(&record.normal), 0.0, 1e-12));
+
+            // Stepping along the tangent moves round the sphere in the direction of increasing u.
+            let stepped = (direction + 0.01 * record.tangent.unit_vector()).unit_vector();
+            let next = sphere.hit(Ray::new(5.0 * stepped, -stepped), 0.0, 100.0).unwrap();
+            assert!(next.u > record.u);
+            assert!(approx_eq(next.v, record.v, 1e-4));
+        }
+    }
+
+    mod moving_sphere_tests {
+        use super::*;
+
+        #[test]
+        fn hit_at_time() {
+            let sphere = MovingSphere::new(
+                Point::new(0.0, 0.0, -2.0),
+                Point::new(4.0, 0.0, -2.0),
+                0.0,
+                1.0,
+                1.0,
+                Arc::new(EmptyMaterial),
+            );
+            let early_ray = Ray::with_time(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+            let late_ray = Ray::with_time(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0), 1.0);
+            let middle_ray = Ray::with_time(Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0), 0.5);
+
+            assert!(approx_eq(sphere.hit(early_ray, 0.0, 100.0).unwrap().t, 1.0, 1e-12));
+            assert!(sphere.hit(late_ray, 0.0, 100.0).is_none());
+            assert!(approx_eq(sphere.hit(middle_ray, 0.0, 100.0).unwrap().t, 1.0, 1e-12));
+
+            // Outside its time interval the sphere stays at the nearer end.
+            assert!(approx_eq(sphere.centre(2.0).x(), 4.0, f64::EPSILON));
+
+            let bounding_box = sphere.bounding_box().unwrap();
+            assert!(approx_eq(bounding_box.min.x(), -1.0, f64::EPSILON));
+            assert!(approx_eq(bounding_box.max.x(), 5.0, f64::EPSILON));
+        }
+    }
+
+    mod rect_tests {
+        use super::*;
+
+        #[test]
+        fn hit() {
+            let rect = XYRect::new(-1.0, 1.0, -1.0, 1.0, -2.0, Arc::new(EmptyMaterial));
+            let hit_ray = Ray::new(Vec3::new(0.5, 0.5, 0.0), Vec3::new(0.0, 0.0, -1.0));
+            let miss_ray = Ray::new(Vec3::new(1.5, 0.5, 0.0), Vec3::new(0.0, 0.0, -1.0));
+            let parallel_ray = Ray::new(Vec3::new(0.0, 0.0, -2.0), Vec3::new(1.0, 0.0, 0.0));
+            let back_ray = Ray::new(Vec3::new(0.0, 0.0, -4.0), Vec3::new(0.0, 0.0, 1.0));
+
+            assert!(rect.hit(miss_ray, 0.0, 100.0).is_none());
+            assert!(rect.hit(parallel_ray, 0.0, 100.0).is_none());
+
+            let record = rect.hit(hit_ray, 0.0, 100.0).unwrap();
+            assert!(approx_eq(record.t, 2.0, f64::EPSILON));
+            assert!(record.front_face);
+
+            let back_record = rect.hit(back_ray, 0.0, 100.0).unwrap();
+            assert!(!back_record.front_face);
+            assert!(approx_eq(back_record.normal.z(), -1.0, f64::EPSILON));
+        }
+
+        #[test]
+        fn texture_coordinates() {
+            let rect = XZRect::new(0.0, 4.0, -1.0, 1.0, 0.0, Arc::new(EmptyMaterial));
+            let ray = Ray::new(Vec3::new(1.0, 1.0, 0.5), Vec3::new(0.0, -1.0, 0.0));
+            let record = rect.hit(ray, 0.0, 100.0).unwrap();
+
+            assert!(approx_eq(record.u, 0.25, f64::EPSILON));
+            assert!(approx_eq(record.v, 0.75, f64::EPSILON));
+        }
+
+        #[test]
+        fn orientation() {
+            let xz = XZRect::new(-1.0, 1.0, -1.0, 1.0, 0.0, Arc::new(EmptyMaterial));
+            let yz = YZRect::new(-1.0, 1.0, -1.0, 1.0, 0.0, Arc::new(EmptyMaterial));
+            let down_ray = Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+            let left_ray = Ray::new(Vec3::new(1.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+
+            assert!(xz.hit(down_ray, 0.0, 100.0).unwrap().front_face);
+            assert!(yz.hit(left_ray, 0.0, 100.0).unwrap().front_face);
+        }
+    }
+
+    mod cuboid_tests {
+        use super::*;
+
+        #[test]
+        fn outward_normals() {
+            let cuboid = Cuboid::new(Point::new(-1.0, -1.0, -1.0), Point::new(1.0, 1.0, 1.0), Arc::new(EmptyMaterial));
+            let directions = [
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::new(-1.0, 0.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                Vec3::new(0.0, -1.0, 0.0),
+                Vec3::new(0.0, 0.0, 1.0),
+                Vec3::new(0.0, 0.0, -1.0),
+            ];
+
+            for direction in directions.iter() {
+                let outside_ray = Ray::new(-3.0 * *direction, *direction);
+                let record = cuboid.hit(outside_ray, 0.0, 100.0).unwrap();
+                assert!(approx_eq(record.t, 2.0, f64::EPSILON));
+                assert!(record.front_face);
+
+                let inside_ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), *direction);
+                let record = cuboid.hit(inside_ray, 0.0, 100.0).unwrap();
+                assert!(approx_eq(record.t, 1.0, f64::EPSILON));
+                assert!(!record.front_face);
+                assert!(approx_eq(record.normal.dot(direction), -1.0, f64::EPSILON));
+            }
+        }
+    }
+
+    mod plane_tests {
+        use super::*;
+
+        #[test]
+        fn hit() {
+            let plane = Plane::new(Point::new(0.0, -1.0, 0.0), Vec3::new(0.0, 2.0, 0.0), Arc::new(EmptyMaterial));
+            let down_ray = Ray::new(Vec3::new(100.0, 0.0, -50.0), Vec3::new(0.0, -1.0, 0.0));
+            let up_ray = Ray::new(Vec3::new(0.0, -3.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+            let parallel_ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+
+            let record = plane.hit(down_ray, 0.0, 100.0).unwrap();
+            assert!(approx_eq(record.t, 1.0, f64::EPSILON));
+            assert!(record.front_face);
+            assert!(!plane.hit(up_ray, 0.0, 100.0).unwrap().front_face);
+            assert!(plane.hit(parallel_ray, 0.0, 100.0).is_none());
+            assert!(plane.bounding_box().is_none());
+        }
     }
 
     mod hittable_list_tests {
@@ -144,12 +966,12 @@ mod tests {
         #[test]
         fn hit() {
             let mut list = HittableList::new();
-            let sphere1 = Sphere::new(Point::new(0.0, 0.0, -2.0), 1.0, Rc::new(EmptyMaterial));
-            let sphere2 = Sphere::new(Point::new(0.0, 10.0, 0.0), 1.0, Rc::new(EmptyMaterial));
-            let sphere3 = Sphere::new(Point::new(0.0, 0.0, -20.0), 1.0, Rc::new(EmptyMaterial));
-            list.add(Rc::new(sphere1));
-            list.add(Rc::new(sphere2));
-            list.add(Rc::new(sphere3));
+            let sphere1 = Sphere::new(Point::new(0.0, 0.0, -2.0), 1.0, Arc::new(EmptyMaterial));
+            let sphere2 = Sphere::new(Point::new(0.0, 10.0, 0.0), 1.0, Arc::new(EmptyMaterial));
+            let sphere3 = Sphere::new(Point::new(0.0, 0.0, -20.0), 1.0, Arc::new(EmptyMaterial));
+            list.add(Arc::new(sphere1));
+            list.add(Arc::new(sphere2));
+            list.add(Arc::new(sphere3));
 
             let miss_ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
             let ray1 = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
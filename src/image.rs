@@ -0,0 +1,885 @@
+use crate::Colour;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+pub struct Channel {
+    pub name: String,
+    pub values: Vec<f32>,
+}
+
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<Colour>,
+    extra_channels: Vec<Channel>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Colour::new(0.0, 0.0, 0.0); width * height],
+            extra_channels: Vec::new(),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[Colour] {
+        &self.pixels
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Colour {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, colour: Colour) {
+        self.pixels[y * self.width + x] = colour;
+    }
+
+    // Extra channels (depth, alpha, ...) are only written by formats that can carry them.
+    pub fn add_channel(&mut self, name: &str, values: Vec<f32>) {
+        assert_eq!(values.len(), self.width * self.height, "channel '{}' has the wrong size", name);
+        assert!(
+            !["R", "G", "B"].contains(&name) && self.extra_channels.iter().all(|c| c.name != name),
+            "channel '{}' already exists",
+            name
+        );
+        self.extra_channels.push(Channel {
+            name: name.to_string(),
+            values,
+        });
+    }
+
+    pub fn extra_channels(&self) -> &[Channel] {
+        &self.extra_channels
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ImageFormat {
+    PpmAscii,
+    PpmBinary,
+    Png,
+    Exr,
+    Pfm,
+}
+
+impl ImageFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ppm" => Some(ImageFormat::PpmAscii),
+            "ppm-binary" => Some(ImageFormat::PpmBinary),
+            "png" => Some(ImageFormat::Png),
+            "exr" => Some(ImageFormat::Exr),
+            "pfm" => Some(ImageFormat::Pfm),
+            _ => None,
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "ppm" | "pnm" => Some(ImageFormat::PpmAscii),
+            "png" => Some(ImageFormat::Png),
+            "exr" => Some(ImageFormat::Exr),
+            "pfm" => Some(ImageFormat::Pfm),
+            _ => None,
+        }
+    }
+}
+
+fn clamp(x: f64, min: f64, max: f64) -> f64 {
+    if x < min {
+        min
+    } else if x > max {
+        max
+    } else {
+        x
+    }
+}
+
+pub fn linear_to_srgb(x: f64) -> f64 {
+    if x <= 0.0031308 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+pub fn srgb_to_linear(x: f64) -> f64 {
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn encode_channel(x: f64) -> u8 {
+    // NaN from a degenerate sample would otherwise poison the clamp.
+    let x = if x.is_nan() { 0.0 } else { x };
+    (256.0 * clamp(linear_to_srgb(clamp(x, 0.0, 1.0)), 0.0, 0.999)) as u8
+}
+
+pub fn encode_srgb8(pixel_colour: &Colour) -> [u8; 3] {
+    [
+        encode_channel(pixel_colour.x()),
+        encode_channel(pixel_colour.y()),
+        encode_channel(pixel_colour.z()),
+    ]
+}
+
+pub fn write_ppm_ascii(out: &mut dyn Write, framebuffer: &Framebuffer) -> io::Result<()> {
+    write!(out, "P3\n{} {}\n255\n", framebuffer.width, framebuffer.height)?;
+    for pixel_colour in &framebuffer.pixels {
+        let [red, green, blue] = encode_srgb8(pixel_colour);
+        writeln!(out, "{} {} {}", red, green, blue)?;
+    }
+    out.flush()
+}
+
+pub fn write_ppm_binary(out: &mut dyn Write, framebuffer: &Framebuffer) -> io::Result<()> {
+    write!(out, "P6\n{} {}\n255\n", framebuffer.width, framebuffer.height)?;
+    let bytes: Vec<u8> = framebuffer.pixels.iter().flat_map(encode_srgb8).collect();
+    out.write_all(&bytes)?;
+    out.flush()
+}
+
+pub fn write_png(out: &mut dyn Write, framebuffer: &Framebuffer) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(out, framebuffer.width as u32, framebuffer.height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+
+    let bytes: Vec<u8> = framebuffer.pixels.iter().flat_map(encode_srgb8).collect();
+    let mut writer = encoder.write_header().map_err(png_error)?;
+    writer.write_image_data(&bytes).map_err(png_error)?;
+    writer.finish().map_err(png_error)
+}
+
+fn png_error(error: png::EncodingError) -> io::Error {
+    match error {
+        png::EncodingError::IoError(error) => error,
+        error => io::Error::other(error),
+    }
+}
+
+// Portable Float Map: little-endian RGB floats, rows stored bottom to top.
+pub fn write_pfm(out: &mut dyn Write, framebuffer: &Framebuffer) -> io::Result<()> {
+    write!(out, "PF\n{} {}\n-1.0\n", framebuffer.width, framebuffer.height)?;
+    let mut bytes = Vec::with_capacity(framebuffer.pixels.len() * 12);
+    for row in framebuffer.pixels.chunks(framebuffer.width).rev() {
+        for pixel_colour in row {
+            for channel in 0..3 {
+                bytes.extend_from_slice(&(pixel_colour[channel] as f32).to_le_bytes());
+            }
+        }
+    }
+    out.write_all(&bytes)?;
+    out.flush()
+}
+
+fn exr_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+// Single-part scanline OpenEXR with 32-bit float channels and no compression. Channels are
+// stored in alphabetical order, as the format requires.
+pub fn write_exr(out: &mut dyn Write, framebuffer: &Framebuffer) -> io::Result<()> {
+    let width = framebuffer.width;
+    let height = framebuffer.height;
+
+    let rgb: Vec<(String, Vec<f32>)> = (0..3)
+        .map(|channel| {
+            let name = ["R", "G", "B"][channel].to_string();
+            let values = framebuffer.pixels.iter().map(|pixel| pixel[channel] as f32).collect();
+            (name, values)
+        })
+        .collect();
+    let mut channels: Vec<(&str, &[f32])> = rgb
+        .iter()
+        .map(|(name, values)| (name.as_str(), values.as_slice()))
+        .chain(framebuffer.extra_channels.iter().map(|c| (c.name.as_str(), c.values.as_slice())))
+        .collect();
+    channels.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut channel_list = Vec::new();
+    for (name, _) in &channels {
+        channel_list.extend_from_slice(name.as_bytes());
+        channel_list.push(0);
+        channel_list.extend_from_slice(&2i32.to_le_bytes()); // FLOAT
+        channel_list.extend_from_slice(&[0, 0, 0, 0]); // pLinear and reserved
+        channel_list.extend_from_slice(&1i32.to_le_bytes()); // xSampling
+        channel_list.extend_from_slice(&1i32.to_le_bytes()); // ySampling
+    }
+    channel_list.push(0);
+
+    let mut window = Vec::new();
+    for value in &[0, 0, width as i32 - 1, height as i32 - 1] {
+        window.extend_from_slice(&value.to_le_bytes());
+    }
+
+    let mut header = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+    exr_attribute(&mut header, "channels", "chlist", &channel_list);
+    exr_attribute(&mut header, "compression", "compression", &[0]);
+    exr_attribute(&mut header, "dataWindow", "box2i", &window);
+    exr_attribute(&mut header, "displayWindow", "box2i", &window);
+    exr_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    exr_attribute(&mut header, "pixelAspectRatio", "float", &1.0f32.to_le_bytes());
+    exr_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    exr_attribute(&mut header, "screenWindowWidth", "float", &1.0f32.to_le_bytes());
+    header.push(0);
+
+    let line_size = channels.len() * width * 4;
+    let block_size = 8 + line_size;
+    let table_end = header.len() + 8 * height;
+
+    let mut bytes = header;
+    bytes.reserve(8 * height + block_size * height);
+    for y in 0..height {
+        bytes.extend_from_slice(&((table_end + y * block_size) as u64).to_le_bytes());
+    }
+    for y in 0..height {
+        bytes.extend_from_slice(&(y as i32).to_le_bytes());
+        bytes.extend_from_slice(&(line_size as i32).to_le_bytes());
+        for (_, values) in &channels {
+            for value in &values[y * width..(y + 1) * width] {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+    }
+
+    out.write_all(&bytes)?;
+    out.flush()
+}
+
+pub fn write_image(out: &mut dyn Write, framebuffer: &Framebuffer, format: ImageFormat) -> io::Result<()> {
+    match format {
+        ImageFormat::PpmAscii => write_ppm_ascii(out, framebuffer),
+        ImageFormat::PpmBinary => write_ppm_binary(out, framebuffer),
+        ImageFormat::Png => write_png(out, framebuffer),
+        ImageFormat::Exr => write_exr(out, framebuffer),
+        ImageFormat::Pfm => write_pfm(out, framebuffer),
+    }
+}
+
+pub fn save_image(path: &Path, framebuffer: &Framebuffer, format: ImageFormat) -> io::Result<()> {
+    let file = File::create(path)?;
+    write_image(&mut BufWriter::new(file), framebuffer, format)
+}
+
+#[derive(Debug)]
+pub struct ImageError {
+    pub path: PathBuf,
+    pub message: String,
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.message)
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+// Loads a PNG, PPM, Radiance HDR or PFM image, recognised by its signature rather than its
+// extension. PNG and PPM store sRGB-encoded values, which are converted to linear colour;
+// the two high dynamic range formats are linear already.
+pub fn load_image(path: &Path) -> Result<Framebuffer, ImageError> {
+    let error = |message: String| ImageError {
+        path: path.to_path_buf(),
+        message,
+    };
+    let bytes = fs::read(path).map_err(|e| error(e.to_string()))?;
+
+    if bytes.starts_with(b"\x89PNG") {
+        decode_png(&bytes).map_err(error)
+    } else if bytes.starts_with(b"P3") || bytes.starts_with(b"P6") {
+        decode_ppm(&bytes).map_err(error)
+    } else if bytes.starts_with(b"PF") || bytes.starts_with(b"Pf") {
+        decode_pfm(&bytes).map_err(error)
+    } else if bytes.starts_with(b"#?") {
+        decode_hdr(&bytes).map_err(error)
+    } else {
+        Err(error(String::from("unsupported image format (expected PNG, PPM, HDR or PFM)")))
+    }
+}
+
+pub fn decode_png(bytes: &[u8]) -> Result<Framebuffer, String> {
+    let mut decoder = png::Decoder::new(bytes);
+    // Expands palettes and sub-byte grey levels so every sample is 8 or 16 bits.
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(|e| e.to_string())?;
+
+    let samples = info.color_type.samples();
+    let sixteen_bit = info.bit_depth == png::BitDepth::Sixteen;
+    let sample_size = if sixteen_bit { 2 } else { 1 };
+    let max_value = if sixteen_bit { 65535.0 } else { 255.0 };
+    let (width, height) = (info.width as usize, info.height as usize);
+
+    let mut framebuffer = Framebuffer::new(width, height);
+    for (y, row) in buffer.chunks(info.line_size).take(height).enumerate() {
+        for x in 0..width {
+            let sample = |channel: usize| {
+                let offset = (x * samples + channel) * sample_size;
+                let value = if sixteen_bit {
+                    u16::from_be_bytes([row[offset], row[offset + 1]]) as f64
+                } else {
+                    row[offset] as f64
+                };
+                srgb_to_linear(value / max_value)
+            };
+            // Alpha, if present, is ignored; grey images replicate their single channel.
+            let colour = if samples >= 3 {
+                Colour::new(sample(0), sample(1), sample(2))
+            } else {
+                let grey = sample(0);
+                Colour::new(grey, grey, grey)
+            };
+            framebuffer.set(x, y, colour);
+        }
+    }
+
+    Ok(framebuffer)
+}
+
+// Reads the whitespace-separated header fields of a PPM, skipping `#` comments, and returns
+// them with the offset just past the last one.
+fn ppm_header(bytes: &[u8], count: usize) -> Result<(Vec<String>, usize), String> {
+    let mut fields = Vec::new();
+    let mut position = 0;
+
+    while fields.len() < count {
+        match bytes.get(position) {
+            None => return Err(String::from("unexpected end of PPM header")),
+            Some(b'#') => {
+                while position < bytes.len() && bytes[position] != b'\n' {
+                    position += 1;
+                }
+            }
+            Some(byte) if byte.is_ascii_whitespace() => position += 1,
+            Some(_) => {
+                let start = position;
+                while position < bytes.len() && !bytes[position].is_ascii_whitespace() {
+                    position += 1;
+                }
+                fields.push(String::from_utf8_lossy(&bytes[start..position]).into_owned());
+            }
+        }
+    }
+
+    Ok((fields, position))
+}
+
+pub fn decode_ppm(bytes: &[u8]) -> Result<Framebuffer, String> {
+    let (header, position) = ppm_header(bytes, 4)?;
+    let number = |field: &str| {
+        field
+            .parse::<usize>()
+            .map_err(|_| format!("invalid PPM header value '{}'", field))
+    };
+    let (width, height, max_value) = (number(&header[1])?, number(&header[2])?, number(&header[3])?);
+    if !(1..=65535).contains(&max_value) {
+        return Err(format!("invalid PPM maximum value {}", max_value));
+    }
+
+    let count = width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(3))
+        .ok_or("PPM image size is too large")?;
+    let samples: Vec<usize> = match header[0].as_str() {
+        "P3" => {
+            let text = String::from_utf8_lossy(&bytes[position..]);
+            let samples = text
+                .split_ascii_whitespace()
+                .take(count)
+                .map(number)
+                .collect::<Result<Vec<_>, _>>()?;
+            samples
+        }
+        "P6" => {
+            // A single whitespace byte separates the header from the raster.
+            let raster = bytes.get(position + 1..).unwrap_or_default();
+            if max_value < 256 {
+                raster.iter().take(count).map(|&b| b as usize).collect()
+            } else {
+                raster
+                    .chunks_exact(2)
+                    .take(count)
+                    .map(|pair| u16::from_be_bytes([pair[0], pair[1]]) as usize)
+                    .collect()
+            }
+        }
+        magic => return Err(format!("unsupported PPM variant '{}'", magic)),
+    };
+    if samples.len() < count {
+        return Err(String::from("PPM pixel data is truncated"));
+    }
+
+    let mut framebuffer = Framebuffer::new(width, height);
+    for (i, rgb) in samples.chunks(3).enumerate() {
+        let channel = |value: usize| srgb_to_linear(value.min(max_value) as f64 / max_value as f64);
+        framebuffer.set(i % width, i / width, Colour::new(channel(rgb[0]), channel(rgb[1]), channel(rgb[2])));
+    }
+
+    Ok(framebuffer)
+}
+
+// Portable Float Map, in colour (PF) or greyscale (Pf). A negative scale marks
+// little-endian floats; rows are stored bottom to top.
+pub fn decode_pfm(bytes: &[u8]) -> Result<Framebuffer, String> {
+    let (header, position) = ppm_header(bytes, 4)?;
+    let channels = match header[0].as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        magic => return Err(format!("unsupported PFM variant '{}'", magic)),
+    };
+    let number = |field: &str| {
+        field
+            .parse::<usize>()
+            .map_err(|_| format!("invalid PFM header value '{}'", field))
+    };
+    let (width, height) = (number(&header[1])?, number(&header[2])?);
+    let scale = header[3]
+        .parse::<f64>()
+        .map_err(|_| format!("invalid PFM header value '{}'", header[3]))?;
+    if scale == 0.0 || !scale.is_finite() {
+        return Err(format!("invalid PFM scale {}", header[3]));
+    }
+
+    let raster = bytes.get(position + 1..).unwrap_or_default();
+    let count = width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(channels))
+        .ok_or("PFM image size is too large")?;
+    if count > raster.len() / 4 {
+        return Err(String::from("PFM pixel data is truncated"));
+    }
+    let values: Vec<f64> = raster
+        .chunks_exact(4)
+        .take(count)
+        .map(|b| {
+            let b = [b[0], b[1], b[2], b[3]];
+            if scale < 0.0 {
+                f32::from_le_bytes(b) as f64
+            } else {
+                f32::from_be_bytes(b) as f64
+            }
+        })
+        .collect();
+
+    let mut framebuffer = Framebuffer::new(width, height);
+    for (i, pixel) in values.chunks(channels).enumerate() {
+        let colour = if channels == 3 {
+            Colour::new(pixel[0], pixel[1], pixel[2])
+        } else {
+            Colour::new(pixel[0], pixel[0], pixel[0])
+        };
+        framebuffer.set(i % width, height - 1 - i / width, colour);
+    }
+
+    Ok(framebuffer)
+}
+
+// Radiance RGBE, with a text header ending in a blank line, a resolution line and then
+// scanlines that are either flat or run-length encoded one channel at a time.
+pub fn decode_hdr(bytes: &[u8]) -> Result<Framebuffer, String> {
+    let mut position = 0;
+    let mut next_line = || {
+        let start = position;
+        let end = bytes[start..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map(|offset| start + offset)
+            .ok_or("unexpected end of HDR header")?;
+        position = end + 1;
+        Ok::<_, String>(String::from_utf8_lossy(&bytes[start..end]).into_owned())
+    };
+
+    loop {
+        let line = next_line()?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(format!("unsupported HDR format '{}'", format));
+            }
+        }
+    }
+
+    let resolution = next_line()?;
+    let fields: Vec<&str> = resolution.split_whitespace().collect();
+    let (height, width, bottom_up) = match fields.as_slice() {
+        [y, height, "+X", width] if *y == "-Y" || *y == "+Y" => {
+            let number = |field: &str| {
+                field
+                    .parse::<usize>()
+                    .map_err(|_| format!("invalid HDR resolution '{}'", resolution))
+            };
+            (number(height)?, number(width)?, *y == "+Y")
+        }
+        _ => return Err(format!("unsupported HDR resolution '{}'", resolution)),
+    };
+    if width == 0 || height == 0 {
+        return Err(format!("invalid HDR resolution '{}'", resolution));
+    }
+
+    // Even run-length encoded, each scanline takes a four byte marker and then two bytes for
+    // every run of up to 127 pixels in each channel, so a header promising more scanlines
+    // than the data could hold is rejected before anything is allocated.
+    let mut data = &bytes[position..];
+    let scanline_bytes = if (8..0x8000).contains(&width) {
+        4 + 8 * width.div_ceil(127)
+    } else {
+        width.checked_mul(4).ok_or("HDR image size is too large")?
+    };
+    let minimum_size = height
+        .checked_mul(scanline_bytes)
+        .filter(|_| width.checked_mul(height).is_some())
+        .ok_or("HDR image size is too large")?;
+    if minimum_size > data.len() {
+        return Err(String::from("HDR pixel data is truncated"));
+    }
+    let mut framebuffer = Framebuffer::new(width, height);
+    let mut scanline = vec![[0u8; 4]; width];
+    for row in 0..height {
+        data = read_hdr_scanline(data, &mut scanline)?;
+        let y = if bottom_up { height - 1 - row } else { row };
+        for (x, rgbe) in scanline.iter().enumerate() {
+            let colour = if rgbe[3] == 0 {
+                Colour::new(0.0, 0.0, 0.0)
+            } else {
+                let factor = 2f64.powi(rgbe[3] as i32 - 136);
+                Colour::new(rgbe[0] as f64 * factor, rgbe[1] as f64 * factor, rgbe[2] as f64 * factor)
+            };
+            framebuffer.set(x, y, colour);
+        }
+    }
+
+    Ok(framebuffer)
+}
+
+// Reads one scanline into `scanline` and returns the data that follows it.
+fn read_hdr_scanline<'a>(data: &'a [u8], scanline: &mut [[u8; 4]]) -> Result<&'a [u8], String> {
+    let width = scanline.len();
+    let truncated = || String::from("HDR pixel data is truncated");
+    let run_length_encoded =
+        (8..0x8000).contains(&width) && data.len() >= 4 && data[0] == 2 && data[1] == 2 && data[2] < 0x80;
+
+    if !run_length_encoded {
+        let flat = data.get(..4 * width).ok_or_else(truncated)?;
+        for (pixel, rgbe) in scanline.iter_mut().zip(flat.chunks_exact(4)) {
+            pixel.copy_from_slice(rgbe);
+        }
+        return Ok(&data[4 * width..]);
+    }
+
+    if ((data[2] as usize) << 8 | data[3] as usize) != width {
+        return Err(String::from("HDR scanline width does not match the image"));
+    }
+    let mut position = 4;
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = *data.get(position).ok_or_else(truncated)? as usize;
+            position += 1;
+            if count > 128 {
+                let count = count - 128;
+                let value = *data.get(position).ok_or_else(truncated)?;
+                position += 1;
+                if x + count > width {
+                    return Err(String::from("HDR run overflows its scanline"));
+                }
+                for pixel in &mut scanline[x..x + count] {
+                    pixel[channel] = value;
+                }
+                x += count;
+            } else {
+                if count == 0 || x + count > width {
+                    return Err(String::from("HDR run overflows its scanline"));
+                }
+                let values = data.get(position..position + count).ok_or_else(truncated)?;
+                position += count;
+                for (pixel, &value) in scanline[x..x + count].iter_mut().zip(values) {
+                    pixel[channel] = value;
+                }
+                x += count;
+            }
+        }
+    }
+
+    Ok(&data[position..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient() -> Framebuffer {
+        let mut framebuffer = Framebuffer::new(3, 2);
+        framebuffer.set(0, 0, Colour::new(0.0, 0.0, 0.0));
+        framebuffer.set(1, 0, Colour::new(1.0, 1.0, 1.0));
+        framebuffer.set(2, 0, Colour::new(2.0, -1.0, 0.5));
+        framebuffer.set(0, 1, Colour::new(0.2, 0.4, 0.6));
+        framebuffer
+    }
+
+    mod encode_tests {
+        use super::*;
+
+        #[test]
+        fn encode_srgb8() {
+            assert_eq!(super::encode_srgb8(&Colour::new(0.0, 1.0, 5.0)), [0, 255, 255]);
+            assert_eq!(super::encode_srgb8(&Colour::new(-1.0, f64::NAN, 0.5)), [0, 0, 188]);
+        }
+
+        #[test]
+        fn srgb_round_trip() {
+            for i in 0..=100 {
+                let x = i as f64 / 100.0;
+                assert!((srgb_to_linear(linear_to_srgb(x)) - x).abs() < 1e-12);
+            }
+        }
+    }
+
+    mod writer_tests {
+        use super::*;
+
+        #[test]
+        fn ppm_ascii() {
+            let mut out = Vec::new();
+            write_ppm_ascii(&mut out, &gradient()).unwrap();
+            let text = String::from_utf8(out).unwrap();
+            let lines: Vec<&str> = text.lines().collect();
+
+            assert_eq!(lines[..3], ["P3", "3 2", "255"]);
+            assert_eq!(lines.len(), 3 + 6);
+            assert_eq!(lines[4], "255 255 255");
+        }
+
+        #[test]
+        fn ppm_binary() {
+            let mut out = Vec::new();
+            write_ppm_binary(&mut out, &gradient()).unwrap();
+            let header = b"P6\n3 2\n255\n";
+
+            assert_eq!(&out[..header.len()], header);
+            assert_eq!(out.len(), header.len() + 3 * 6);
+            assert_eq!(&out[header.len() + 3..header.len() + 6], [255, 255, 255]);
+        }
+
+        #[test]
+        fn png_round_trip() {
+            let mut out = Vec::new();
+            write_png(&mut out, &gradient()).unwrap();
+
+            let decoder = png::Decoder::new(out.as_slice());
+            let mut reader = decoder.read_info().unwrap();
+            let mut bytes = vec![0; reader.output_buffer_size()];
+            let info = reader.next_frame(&mut bytes).unwrap();
+
+            assert_eq!((info.width, info.height), (3, 2));
+            assert_eq!(info.color_type, png::ColorType::Rgb);
+            assert_eq!(&bytes[3..6], [255, 255, 255]);
+        }
+    }
+
+    mod hdr_writer_tests {
+        use super::*;
+
+        fn f32_at(bytes: &[u8], offset: usize) -> f32 {
+            f32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+        }
+
+        #[test]
+        fn pfm() {
+            let mut out = Vec::new();
+            write_pfm(&mut out, &gradient()).unwrap();
+            let header = b"PF\n3 2\n-1.0\n";
+
+            assert_eq!(&out[..header.len()], header);
+            assert_eq!(out.len(), header.len() + 4 * 3 * 6);
+            // The bottom row comes first, so the top row's unclamped pixel is in the second row.
+            let top_right = header.len() + 4 * 3 * (3 + 2);
+            assert_eq!(f32_at(&out, top_right), 2.0);
+            assert_eq!(f32_at(&out, top_right + 4), -1.0);
+        }
+
+        #[test]
+        fn exr() {
+            let mut framebuffer = gradient();
+            framebuffer.add_channel("Z", vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+            let mut out = Vec::new();
+            write_exr(&mut out, &framebuffer).unwrap();
+
+            assert_eq!(&out[..8], [0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+
+            let channels = b"channels\0chlist\0";
+            let start = 8 + channels.len() + 4;
+            assert_eq!(&out[8..8 + channels.len()], channels);
+            assert_eq!(&out[start..start + 2], b"B\0");
+            assert_eq!(&out[start + 18..start + 20], b"G\0");
+            assert_eq!(&out[start + 36..start + 38], b"R\0");
+            assert_eq!(&out[start + 54..start + 56], b"Z\0");
+
+            let header_end = out.len() - 2 * (8 + 4 * 3 * 4) - 2 * 8;
+            let offset = |y: usize| {
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(&out[header_end + 8 * y..header_end + 8 * y + 8]);
+                u64::from_le_bytes(bytes) as usize
+            };
+            assert_eq!(out[header_end - 1], 0);
+            assert_eq!(offset(1) - offset(0), 8 + 4 * 3 * 4);
+
+            // Top row: B, G, R and Z planes follow the scanline's y and size fields.
+            let block = offset(0);
+            assert_eq!(&out[block..block + 8], [0, 0, 0, 0, 48, 0, 0, 0]);
+            assert_eq!(f32_at(&out, block + 8 + 8), 0.5);
+            assert_eq!(f32_at(&out, block + 8 + 12 + 8), -1.0);
+            assert_eq!(f32_at(&out, block + 8 + 24 + 8), 2.0);
+            assert_eq!(f32_at(&out, block + 8 + 36), 1.0);
+            assert_eq!(f32_at(&out, offset(1) + 8 + 36), 4.0);
+        }
+    }
+
+    mod format_tests {
+        use super::*;
+
+        #[test]
+        fn from_path() {
+            assert_eq!(ImageFormat::from_path(Path::new("out.PNG")), Some(ImageFormat::Png));
+            assert_eq!(ImageFormat::from_path(Path::new("out.ppm")), Some(ImageFormat::PpmAscii));
+            assert_eq!(ImageFormat::from_path(Path::new("out")), None);
+            assert_eq!(ImageFormat::from_path(Path::new("out.exr")), Some(ImageFormat::Exr));
+            assert_eq!(ImageFormat::from_path(Path::new("out.pfm")), Some(ImageFormat::Pfm));
+            assert_eq!(ImageFormat::from_name("ppm-binary"), Some(ImageFormat::PpmBinary));
+        }
+    }
+
+    mod reader_tests {
+        use super::*;
+
+        // Each decoded texel should match what the writer encoded, back in linear space.
+        fn assert_round_trip(decoded: &Framebuffer) {
+            let original = gradient();
+            assert_eq!((decoded.width(), decoded.height()), (3, 2));
+            for (expected, actual) in original.pixels().iter().zip(decoded.pixels()) {
+                let encoded = encode_srgb8(expected);
+                for channel in 0..3 {
+                    assert!((actual[channel] - srgb_to_linear(encoded[channel] as f64 / 255.0)).abs() < 1e-12);
+                }
+            }
+        }
+
+        #[test]
+        fn round_trips() {
+            let mut out = Vec::new();
+            write_ppm_ascii(&mut out, &gradient()).unwrap();
+            assert_round_trip(&decode_ppm(&out).unwrap());
+
+            let mut out = Vec::new();
+            write_ppm_binary(&mut out, &gradient()).unwrap();
+            assert_round_trip(&decode_ppm(&out).unwrap());
+
+            let mut out = Vec::new();
+            write_png(&mut out, &gradient()).unwrap();
+            assert_round_trip(&decode_png(&out).unwrap());
+        }
+
+        #[test]
+        fn ppm_comments_and_depth() {
+            let image = decode_ppm(b"P3 # comment\n2 1\n# another\n1000\n1000 0 0  500 500 500\n").unwrap();
+            assert!((image.get(0, 0).x() - 1.0).abs() < 1e-12);
+            assert!((image.get(1, 0).y() - srgb_to_linear(0.5)).abs() < 1e-12);
+
+            let image = decode_ppm(&[b"P6 1 1 65535\n".as_slice(), &[255, 255, 0, 0, 128, 0]].concat()).unwrap();
+            assert!((image.get(0, 0).x() - 1.0).abs() < 1e-12);
+            assert!((image.get(0, 0).z() - srgb_to_linear(32768.0 / 65535.0)).abs() < 1e-12);
+        }
+
+        #[test]
+        fn errors() {
+            assert_eq!(decode_ppm(b"P6 2 2 255\n\0\0\0").err().unwrap(), "PPM pixel data is truncated");
+            assert_eq!(decode_ppm(b"P3 2 x 255\n").err().unwrap(), "invalid PPM header value 'x'");
+            let huge = b"P6 99999999999 99999999999 255\n\0\0\0";
+            assert_eq!(decode_ppm(huge).err().unwrap(), "PPM image size is too large");
+            let wide = b"P6 1000000 1000000 255\n\0\0\0";
+            assert_eq!(decode_ppm(wide).err().unwrap(), "PPM pixel data is truncated");
+            assert_eq!(decode_ppm(b"P3 2").err().unwrap(), "unexpected end of PPM header");
+            assert!(decode_png(b"\x89PNG\r\n\x1a\nrubbish").is_err());
+
+            let error = load_image(Path::new("missing.png")).err().unwrap();
+            assert!(error.to_string().starts_with("missing.png: "));
+        }
+
+        #[test]
+        fn pfm() {
+            let mut out = Vec::new();
+            write_pfm(&mut out, &gradient()).unwrap();
+            let decoded = decode_pfm(&out).unwrap();
+            for (expected, actual) in gradient().pixels().iter().zip(decoded.pixels()) {
+                assert!((*expected - *actual).length() < 1e-6);
+            }
+
+            // Big-endian greyscale, bottom row first.
+            let bytes = [b"Pf\n1 2\n1.0\n".as_slice(), &2.5f32.to_be_bytes(), &0.5f32.to_be_bytes()].concat();
+            let decoded = decode_pfm(&bytes).unwrap();
+            assert_eq!(decoded.get(0, 1).x(), 2.5);
+            assert_eq!(decoded.get(0, 0).z(), 0.5);
+            assert_eq!(decode_pfm(&bytes[..bytes.len() - 1]).err().unwrap(), "PFM pixel data is truncated");
+            let huge = b"PF\n99999999999 99999999999\n-1.0\n\0\0\0\0";
+            assert_eq!(decode_pfm(huge).err().unwrap(), "PFM image size is too large");
+            let wide = b"PF\n1000000 1000000\n-1.0\n\0\0\0\0";
+            assert_eq!(decode_pfm(wide).err().unwrap(), "PFM pixel data is truncated");
+        }
+
+        #[test]
+        fn hdr() {
+            // A flat 2x1 image: mantissas of 128 with exponent 129 give 1.0, and exponent 0
+            // gives black.
+            let header = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 2\n".as_slice();
+            let decoded = decode_hdr(&[header, &[128, 64, 32, 129, 9, 9, 9, 0]].concat()).unwrap();
+            assert_eq!((decoded.width(), decoded.height()), (2, 1));
+            assert_eq!((decoded.get(0, 0).x(), decoded.get(0, 0).y(), decoded.get(0, 0).z()), (1.0, 0.5, 0.25));
+            assert!(decoded.get(1, 0).near_zero());
+
+            // The same colour in a run-length encoded scanline of 8 pixels: one run per
+            // channel, except the last channel, which is spelt out literally.
+            let header = b"#?RADIANCE\n\n+Y 1 +X 8\n".as_slice();
+            let scanline = [&[2, 2, 0, 8, 136, 128, 136, 64, 136, 32, 8][..], &[129; 8]].concat();
+            let decoded = decode_hdr(&[header, &scanline].concat()).unwrap();
+            for x in 0..8 {
+                assert_eq!(decoded.get(x, 0).y(), 0.5);
+            }
+
+            assert!(decode_hdr(&[header, &scanline[..12]].concat()).is_err());
+            let xyze = b"#?RADIANCE\nFORMAT=32-bit_rle_xyze\n\n-Y 1 +X 1\n\0\0\0\0";
+            assert_eq!(decode_hdr(xyze).err().unwrap(), "unsupported HDR format '32-bit_rle_xyze'");
+            assert!(decode_hdr(b"#?RADIANCE\n\n-X 1 +Y 1\n\0\0\0\0").is_err());
+
+            // Sizes are checked against the data before anything is allocated.
+            let huge = b"#?RADIANCE\n\n-Y 4000000000 +X 4000000000\n\0\0\0\0\0\0\0\0";
+            assert_eq!(decode_hdr(huge).err().unwrap(), "HDR image size is too large");
+            let tall = b"#?RADIANCE\n\n-Y 4000000000 +X 8\n\0\0\0\0\0\0\0\0";
+            assert_eq!(decode_hdr(tall).err().unwrap(), "HDR pixel data is truncated");
+            assert!(decode_hdr(b"#?RADIANCE\n\n-Y 0 +X 4\n").is_err());
+        }
+    }
+}
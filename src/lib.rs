@@ -0,0 +1,24 @@
+pub mod background;
+pub mod bvh;
+pub mod camera;
+pub mod geometry;
+pub mod image;
+pub mod light;
+pub mod linear_algebra;
+pub mod materials;
+pub mod mesh;
+pub mod noise;
+pub mod obj;
+pub mod random;
+pub mod render;
+pub mod sampling;
+pub mod scene;
+pub mod sky;
+pub mod texture;
+pub mod transform;
+pub mod volume;
+
+use linear_algebra::Vec3;
+
+pub type Colour = Vec3;
+pub type Point = Vec3;
@@ -0,0 +1,417 @@
+use crate::geometry::{Aabb, HitRecord, Hittable};
+use crate::linear_algebra::{random_in_cone, Onb, Ray, Vec3};
+use crate::random::rand;
+use crate::{Colour, Point};
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+// Light reaching a point from one sampled direction on a light.
+#[derive(Debug, Copy, Clone)]
+pub struct LightSample {
+    // Unit direction from the lit point towards the light.
+    pub direction: Vec3,
+    // How far along `direction` the light is; shadow rays stop short of it.
+    pub distance: f64,
+    pub radiance: Colour,
+    // The density over solid angle with which the direction was picked.
+    pub pdf: f64,
+    // Whether scattered rays can reach the light too, in which case the two ways of finding
+    // it are weighted against each other. Lights that are not geometry are only ever found
+    // by sampling them.
+    pub hittable: bool,
+}
+
+pub trait Light: Send + Sync {
+    fn sample(&self, origin: Point, time: f64) -> Option<LightSample>;
+
+    // The density over solid angle with which `sample` picks `direction` from `origin` at
+    // `time`.
+    fn pdf(&self, origin: Point, direction: Vec3, time: f64) -> f64;
+}
+
+// An emissive object that can also be sampled directly. It stands in for the object in the
+// world, tagging its hits with its index in the light list.
+pub struct AreaLight {
+    shape: Arc<dyn Hittable>,
+    index: usize,
+}
+
+impl AreaLight {
+    pub fn new(shape: Arc<dyn Hittable>, index: usize) -> Self {
+        Self { shape, index }
+    }
+}
+
+impl Light for AreaLight {
+    fn sample(&self, origin: Point, time: f64) -> Option<LightSample> {
+        let direction = self.shape.random_direction(origin, time)?.unit_vector();
+        let record = self
+            .shape
+            .hit(Ray::with_time(origin, direction, time), 0.001, f64::INFINITY)?;
+        let pdf = self.shape.pdf_value(origin, direction, time);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            direction,
+            distance: record.t,
+            radiance: record.material.emitted(&record),
+            pdf,
+            hittable: true,
+        })
+    }
+
+    fn pdf(&self, origin: Point, direction: Vec3, time: f64) -> f64 {
+        self.shape.pdf_value(origin, direction, time)
+    }
+}
+
+impl Hittable for AreaLight {
+    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let mut record = self.shape.hit(ray, t_min, t_max)?;
+        record.light = Some(self.index);
+        Some(record)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.shape.bounding_box()
+    }
+
+    fn random_direction(&self, origin: Point, time: f64) -> Option<Vec3> {
+        self.shape.random_direction(origin, time)
+    }
+
+    fn pdf_value(&self, origin: Point, direction: Vec3, time: f64) -> f64 {
+        self.shape.pdf_value(origin, direction, time)
+    }
+}
+
+// Radiates `intensity` equally in all directions from a single point.
+pub struct PointLight {
+    position: Point,
+    intensity: Colour,
+}
+
+impl PointLight {
+    pub fn new(position: Point, intensity: Colour) -> Self {
+        Self { position, intensity }
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, origin: Point, _time: f64) -> Option<LightSample> {
+        point_sample(self.position, origin, self.intensity)
+    }
+
+    fn pdf(&self, _origin: Point, _direction: Vec3, _time: f64) -> f64 {
+        0.0
+    }
+}
+
+// A point light's single direction carries all of its light, so it is given a density of 1
+// and radiance falling off with the square of the distance.
+fn point_sample(position: Point, origin: Point, intensity: Colour) -> Option<LightSample> {
+    let offset = position - origin;
+    let distance_squared = offset.length_squared();
+    if distance_squared == 0.0 {
+        return None;
+    }
+
+    Some(LightSample {
+        direction: offset.unit_vector(),
+        distance: distance_squared.sqrt(),
+        radiance: intensity / distance_squared,
+        pdf: 1.0,
+        hittable: false,
+    })
+}
+
+// A point light shining along `direction` in a cone of half-angle `cone_angle` degrees. Its
+// intensity fades smoothly to zero over the outermost `falloff` degrees of the cone.
+pub struct SpotLight {
+    position: Point,
+    direction: Vec3,
+    intensity: Colour,
+    cos_cone: f64,
+    cos_falloff_start: f64,
+}
+
+impl SpotLight {
+    pub fn new(position: Point, direction: Vec3, intensity: Colour, cone_angle: f64, falloff: f64) -> Self {
+        let falloff = falloff.clamp(0.0, cone_angle);
+        Self {
+            position,
+            direction: direction.unit_vector(),
+            intensity,
+            cos_cone: cone_angle.to_radians().cos(),
+            cos_falloff_start: (cone_angle - falloff).to_radians().cos(),
+        }
+    }
+
+    // The fraction of the full intensity sent out at an angle with cosine `cosine` to the
+    // spot's axis.
+    fn falloff(&self, cosine: f64) -> f64 {
+        if cosine >= self.cos_falloff_start {
+            return 1.0;
+        }
+        if cosine <= self.cos_cone {
+            return 0.0;
+        }
+        let x = (cosine - self.cos_cone) / (self.cos_falloff_start - self.cos_cone);
+        x * x * (3.0 - 2.0 * x)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, origin: Point, _time: f64) -> Option<LightSample> {
+        let mut sample = point_sample(self.position, origin, self.intensity)?;
+        let falloff = self.falloff(-sample.direction.dot(&self.direction));
+        if falloff == 0.0 {
+            return None;
+        }
+        sample.radiance *= falloff;
+        Some(sample)
+    }
+
+    fn pdf(&self, _origin: Point, _direction: Vec3, _time: f64) -> f64 {
+        0.0
+    }
+}
+
+// Light from a distant source such as the sun, travelling along `direction` and giving
+// `irradiance` to a surface facing it. A source with a non-zero angular diameter, in
+// degrees, is sampled over the disc it covers in the sky, which softens shadows.
+pub struct DirectionalLight {
+    direction: Vec3,
+    irradiance: Colour,
+    cos_theta_max: f64,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, irradiance: Colour, angular_diameter: f64) -> Self {
+        Self {
+            direction: direction.unit_vector(),
+            irradiance,
+            cos_theta_max: (0.5 * angular_diameter).to_radians().cos(),
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _origin: Point, _time: f64) -> Option<LightSample> {
+        let solid_angle = 2.0 * PI * (1.0 - self.cos_theta_max);
+        let to_light = -self.direction;
+        let (direction, radiance, pdf) = if solid_angle > 0.0 {
+            let local = random_in_cone(self.cos_theta_max);
+            let direction = Onb::new(to_light).local(local.x(), local.y(), local.z());
+            (direction, self.irradiance / solid_angle, 1.0 / solid_angle)
+        } else {
+            (to_light, self.irradiance, 1.0)
+        };
+
+        Some(LightSample {
+            direction,
+            distance: f64::INFINITY,
+            radiance,
+            pdf,
+            hittable: false,
+        })
+    }
+
+    fn pdf(&self, _origin: Point, _direction: Vec3, _time: f64) -> f64 {
+        0.0
+    }
+}
+
+// The lights a scene samples directly, one chosen uniformly per lit point.
+pub struct LightList {
+    lights: Vec<Arc<dyn Light>>,
+    environment: Option<usize>,
+}
+
+impl LightList {
+    pub fn new() -> Self {
+        Self {
+            lights: Vec::new(),
+            environment: None,
+        }
+    }
+
+    pub fn add(&mut self, light: Arc<dyn Light>) {
+        self.lights.push(light);
+    }
+
+    // Adds the light seen by rays that escape the scene.
+    pub fn add_environment(&mut self, light: Arc<dyn Light>) {
+        self.environment = Some(self.lights.len());
+        self.lights.push(light);
+    }
+
+    // The index of the environment light, if there is one.
+    pub fn environment(&self) -> Option<usize> {
+        self.environment
+    }
+
+    pub fn len(&self) -> usize {
+        self.lights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    pub fn sample(&self, origin: Point, time: f64) -> Option<LightSample> {
+        if self.lights.is_empty() {
+            return None;
+        }
+        let count = self.lights.len();
+        let index = (rand(0.0, count as f64) as usize).min(count - 1);
+        let mut sample = self.lights[index].sample(origin, time)?;
+        sample.pdf /= count as f64;
+        Some(sample)
+    }
+
+    // The density with which `sample` picks `direction` by way of the light at `index`.
+    pub fn pdf(&self, index: usize, origin: Point, direction: Vec3, time: f64) -> f64 {
+        match self.lights.get(index) {
+            Some(light) => light.pdf(origin, direction, time) / self.lights.len() as f64,
+            None => 0.0,
+        }
+    }
+}
+
+impl Default for LightList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::{Sphere, XZRect};
+    use crate::materials::DiffuseLight;
+
+    mod area_light_tests {
+        use super::*;
+
+        // Averaging radiance * cos / pdf over light samples estimates the irradiance at a
+        // point, which for these shapes is known in closed form.
+        fn irradiance(light: &AreaLight, origin: Point, normal: Vec3) -> f64 {
+            crate::random::seed(42);
+            let total: f64 = (0..20000)
+                .filter_map(|_| light.sample(origin, 0.0))
+                .map(|sample| sample.radiance.x() * sample.direction.dot(&normal).max(0.0) / sample.pdf)
+                .sum();
+            total / 20000.0
+        }
+
+        #[test]
+        fn sphere() {
+            // A sphere of unit radiance subtending half-angle theta gives pi sin^2(theta) to a
+            // surface facing it.
+            let emitter = Arc::new(DiffuseLight::new(Colour::new(1.0, 1.0, 1.0)));
+            let light = AreaLight::new(Arc::new(Sphere::new(Point::new(0.0, 4.0, 0.0), 1.0, emitter)), 0);
+            let expected = std::f64::consts::PI / 16.0;
+
+            let estimate = irradiance(&light, Point::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+            assert!((estimate - expected).abs() < 0.01 * expected);
+        }
+
+        #[test]
+        fn rectangle() {
+            // A small square far away acts like a point source: E = L * A / d^2.
+            let emitter = Arc::new(DiffuseLight::new(Colour::new(1.0, 1.0, 1.0)));
+            let light = AreaLight::new(Arc::new(XZRect::new(-0.1, 0.1, -0.1, 0.1, 10.0, emitter)), 3);
+            let expected = 0.04 / 100.0;
+
+            let estimate = irradiance(&light, Point::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+            assert!((estimate - expected).abs() < 0.01 * expected);
+
+            let ray = Ray::new(Point::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+            assert_eq!(light.hit(ray, 0.001, f64::INFINITY).unwrap().light, Some(3));
+        }
+
+        #[test]
+        fn pdf_matches_sampling() {
+            // Each sampled direction must be given the density the light reports for it, and
+            // directions that miss the light have none.
+            let emitter = Arc::new(DiffuseLight::new(Colour::new(1.0, 1.0, 1.0)));
+            let light = AreaLight::new(Arc::new(Sphere::new(Point::new(2.0, 3.0, 0.0), 0.5, emitter)), 0);
+            let origin = Point::new(0.0, 0.0, 0.0);
+
+            for _ in 0..100 {
+                let sample = light.sample(origin, 0.0).unwrap();
+                assert!((light.pdf_value(origin, sample.direction, 0.0) - sample.pdf).abs() < 1e-9);
+            }
+            assert_eq!(light.pdf_value(origin, Vec3::new(0.0, -1.0, 0.0), 0.0), 0.0);
+        }
+    }
+
+    mod analytic_light_tests {
+        use super::*;
+
+        #[test]
+        fn inverse_square() {
+            let light = PointLight::new(Point::new(0.0, 2.0, 0.0), Colour::new(8.0, 8.0, 8.0));
+            let sample = light.sample(Point::new(0.0, 0.0, 0.0), 0.0).unwrap();
+
+            assert!(approx_eq(sample.direction.y(), 1.0));
+            assert!(approx_eq(sample.distance, 2.0));
+            assert!(approx_eq(sample.radiance.x() / sample.pdf, 2.0));
+            assert!(!sample.hittable);
+        }
+
+        #[test]
+        fn spot_falloff() {
+            // A cone of 30 degrees, fading over its outer 10.
+            let light = SpotLight::new(
+                Point::new(0.0, 1.0, 0.0),
+                Vec3::new(0.0, -1.0, 0.0),
+                Colour::new(1.0, 1.0, 1.0),
+                30.0,
+                10.0,
+            );
+            let at_angle = |degrees: f64| {
+                let offset = degrees.to_radians().tan();
+                light
+                    .sample(Point::new(offset, 0.0, 0.0), 0.0)
+                    .map_or(0.0, |sample| sample.radiance.x() * (1.0 + offset * offset))
+            };
+
+            assert!(approx_eq(at_angle(0.0), 1.0));
+            assert!(approx_eq(at_angle(19.0), 1.0));
+            assert!(at_angle(25.0) > 0.0 && at_angle(25.0) < 1.0);
+            assert!(at_angle(22.0) > at_angle(28.0));
+            assert_eq!(at_angle(31.0), 0.0);
+            assert!(light.sample(Point::new(0.0, 2.0, 0.0), 0.0).is_none());
+        }
+
+        #[test]
+        fn directional() {
+            let direction = Vec3::new(0.0, -1.0, -1.0);
+            let irradiance = Colour::new(3.0, 3.0, 3.0);
+
+            let sharp = DirectionalLight::new(direction, irradiance, 0.0).sample(Point::new(0.0, 0.0, 0.0), 0.0);
+            let sharp = sharp.unwrap();
+            assert!(approx_eq(sharp.direction.dot(&-direction.unit_vector()), 1.0));
+            assert!(approx_eq(sharp.radiance.x() / sharp.pdf, 3.0));
+            assert_eq!(sharp.distance, f64::INFINITY);
+
+            // A sun-sized disc: directions stay within a quarter degree of the centre, while
+            // radiance / pdf still gives the full irradiance.
+            let sun = DirectionalLight::new(direction, irradiance, 0.53);
+            for _ in 0..100 {
+                let sample = sun.sample(Point::new(0.0, 0.0, 0.0), 0.0).unwrap();
+                let angle = sample.direction.dot(&-direction.unit_vector()).min(1.0).acos().to_degrees();
+                assert!(angle <= 0.265 + 1e-9);
+                assert!(approx_eq(sample.radiance.x() / sample.pdf, 3.0));
+            }
+        }
+    }
+
+    fn approx_eq(x: f64, y: f64) -> bool {
+        (x - y).abs() < 1e-9
+    }
+}
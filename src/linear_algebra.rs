@@ -1,7 +1,8 @@
 use crate::random::rand;
-use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub};
+use std::f64::consts::PI;
+use std::ops::{Add, AddAssign, Div, DivAssign, Index, Mul, MulAssign, Neg, Sub};
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Vec3 {
     v: [f64; 3],
 }
@@ -54,6 +55,13 @@ impl Vec3 {
     }
 }
 
+impl Index<usize> for Vec3 {
+    type Output = f64;
+    fn index(&self, index: usize) -> &f64 {
+        &self.v[index]
+    }
+}
+
 impl Neg for Vec3 {
     type Output = Self;
     fn neg(self) -> Self {
@@ -139,6 +147,144 @@ impl DivAssign<f64> for Vec3 {
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Mat4 {
+    m: [[f64; 4]; 4],
+}
+
+impl Mat4 {
+    pub fn new(m: [[f64; 4]; 4]) -> Self {
+        Self { m }
+    }
+
+    pub fn identity() -> Self {
+        Self::new([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn translation(offset: Vec3) -> Self {
+        Self::new([
+            [1.0, 0.0, 0.0, offset.x()],
+            [0.0, 1.0, 0.0, offset.y()],
+            [0.0, 0.0, 1.0, offset.z()],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn scaling(factors: Vec3) -> Self {
+        Self::new([
+            [factors.x(), 0.0, 0.0, 0.0],
+            [0.0, factors.y(), 0.0, 0.0],
+            [0.0, 0.0, factors.z(), 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    // Right-handed rotation by `degrees` about `axis` (Rodrigues' formula).
+    pub fn rotation(axis: Vec3, degrees: f64) -> Self {
+        let a = axis.unit_vector();
+        let (sin, cos) = (degrees.to_radians()).sin_cos();
+        let t = 1.0 - cos;
+        let (x, y, z) = (a.x(), a.y(), a.z());
+
+        Self::new([
+            [t * x * x + cos, t * x * y - sin * z, t * x * z + sin * y, 0.0],
+            [t * x * y + sin * z, t * y * y + cos, t * y * z - sin * x, 0.0],
+            [t * x * z - sin * y, t * y * z + sin * x, t * z * z + cos, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> f64 {
+        self.m[row][col]
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (row, values) in m.iter_mut().enumerate() {
+            for (col, value) in values.iter_mut().enumerate() {
+                *value = self.m[col][row];
+            }
+        }
+        Self::new(m)
+    }
+
+    // Gauss-Jordan elimination with partial pivoting. Returns None for singular matrices.
+    pub fn inverse(&self) -> Option<Self> {
+        let mut a = self.m;
+        let mut inverse = Mat4::identity().m;
+
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&i, &j| a[i][col].abs().partial_cmp(&a[j][col].abs()).unwrap())
+                .unwrap();
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inverse.swap(col, pivot);
+
+            let scale = 1.0 / a[col][col];
+            for k in 0..4 {
+                a[col][k] *= scale;
+                inverse[col][k] *= scale;
+            }
+
+            for row in 0..4 {
+                if row != col {
+                    let factor = a[row][col];
+                    for k in 0..4 {
+                        a[row][k] -= factor * a[col][k];
+                        inverse[row][k] -= factor * inverse[col][k];
+                    }
+                }
+            }
+        }
+
+        Some(Self::new(inverse))
+    }
+
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        let m = &self.m;
+        let x = m[0][0] * p.x() + m[0][1] * p.y() + m[0][2] * p.z() + m[0][3];
+        let y = m[1][0] * p.x() + m[1][1] * p.y() + m[1][2] * p.z() + m[1][3];
+        let z = m[2][0] * p.x() + m[2][1] * p.y() + m[2][2] * p.z() + m[2][3];
+        let w = m[3][0] * p.x() + m[3][1] * p.y() + m[3][2] * p.z() + m[3][3];
+
+        if w == 1.0 {
+            Vec3::new(x, y, z)
+        } else {
+            Vec3::new(x / w, y / w, z / w)
+        }
+    }
+
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+            m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+            m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z(),
+        )
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Self;
+    fn mul(self, other: Self) -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (row, values) in m.iter_mut().enumerate() {
+            for (col, value) in values.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[row][k] * other.m[k][col]).sum();
+            }
+        }
+        Self::new(m)
+    }
+}
+
 pub fn reflect(v: Vec3, n: Vec3) -> Vec3 {
     v - 2.0 * v.dot(&n) * n
 }
@@ -179,15 +325,74 @@ pub fn random_vec_in_unit_disk() -> Vec3 {
     }
 }
 
+// An orthonormal basis with `w` along a given direction, for turning directions sampled
+// around the z axis into world space.
+#[derive(Debug, Copy, Clone)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    pub fn new(w: Vec3) -> Self {
+        let w = w.unit_vector();
+        let axis = if w.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let v = w.cross(&axis).unit_vector();
+        let u = w.cross(&v);
+        Self { u, v, w }
+    }
+
+    // A basis around `w` whose u axis follows `tangent` as closely as it can, falling back to
+    // an arbitrary one where the tangent is missing or parallel to `w`.
+    pub fn with_tangent(w: Vec3, tangent: Vec3) -> Self {
+        let w = w.unit_vector();
+        let u = tangent - tangent.dot(&w) * w;
+        if u.length_squared() <= 1e-12 * tangent.length_squared() || u.near_zero() {
+            return Self::new(w);
+        }
+        let u = u.unit_vector();
+        let v = w.cross(&u);
+        Self { u, v, w }
+    }
+
+    pub fn local(&self, a: f64, b: f64, c: f64) -> Vec3 {
+        a * self.u + b * self.v + c * self.w
+    }
+
+    // The coordinates of `a` in this basis; the inverse of `local`.
+    pub fn project(&self, a: Vec3) -> Vec3 {
+        Vec3::new(a.dot(&self.u), a.dot(&self.v), a.dot(&self.w))
+    }
+}
+
+// A direction in the cone of half-angle acos(`cos_theta_max`) around the z axis, uniformly
+// distributed over solid angle.
+pub fn random_in_cone(cos_theta_max: f64) -> Vec3 {
+    let z = 1.0 + rand(0.0, 1.0) * (cos_theta_max - 1.0);
+    let phi = 2.0 * PI * rand(0.0, 1.0);
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    pub time: f64,
 }
 
 impl Ray {
     pub fn new(origin: Vec3, direction: Vec3) -> Self {
-        Ray { origin, direction }
+        Ray::with_time(origin, direction, 0.0)
+    }
+
+    pub fn with_time(origin: Vec3, direction: Vec3, time: f64) -> Self {
+        Ray { origin, direction, time }
     }
 
     pub fn at(&self, t: f64) -> Vec3 {
@@ -304,6 +509,50 @@ mod tests {
         }
     }
 
+    mod mat4_tests {
+        use super::*;
+
+        fn vec_approx_eq(a: Vec3, b: Vec3, tolerance: f64) -> bool {
+            approx_eq(a.x(), b.x(), tolerance) && approx_eq(a.y(), b.y(), tolerance) && approx_eq(a.z(), b.z(), tolerance)
+        }
+
+        #[test]
+        fn transform_point() {
+            let translation = Mat4::translation(Vec3::new(1.0, 2.0, 3.0));
+            let scaling = Mat4::scaling(Vec3::new(2.0, 2.0, 2.0));
+            let p = Vec3::new(1.0, 1.0, 1.0);
+
+            assert!(vec_approx_eq(translation.transform_point(p), Vec3::new(2.0, 3.0, 4.0), f64::EPSILON));
+            assert!(vec_approx_eq(translation.transform_vector(p), p, f64::EPSILON));
+            assert!(vec_approx_eq((translation * scaling).transform_point(p), Vec3::new(3.0, 4.0, 5.0), f64::EPSILON));
+            assert!(vec_approx_eq((scaling * translation).transform_point(p), Vec3::new(4.0, 6.0, 8.0), f64::EPSILON));
+        }
+
+        #[test]
+        fn rotation() {
+            let rotation = Mat4::rotation(Vec3::new(0.0, 0.0, 1.0), 90.0);
+            let v = rotation.transform_vector(Vec3::new(1.0, 0.0, 0.0));
+
+            assert!(vec_approx_eq(v, Vec3::new(0.0, 1.0, 0.0), 1e-12));
+        }
+
+        #[test]
+        fn inverse() {
+            let m = Mat4::translation(Vec3::new(1.0, -2.0, 3.0))
+                * Mat4::rotation(Vec3::new(1.0, 1.0, 0.0), 30.0)
+                * Mat4::scaling(Vec3::new(2.0, 0.5, 3.0));
+            let product = m * m.inverse().unwrap();
+
+            for row in 0..4 {
+                for col in 0..4 {
+                    let expected = if row == col { 1.0 } else { 0.0 };
+                    assert!(approx_eq(product.get(row, col), expected, 1e-12));
+                }
+            }
+            assert!(Mat4::scaling(Vec3::new(1.0, 0.0, 1.0)).inverse().is_none());
+        }
+    }
+
     mod ray_tests {
         use super::*;
 
@@ -325,6 +574,52 @@ mod tests {
         }
     }
 
+    mod onb_tests {
+        use super::*;
+
+        #[test]
+        fn orthonormal() {
+            for w in [Vec3::new(0.0, 0.0, 2.0), Vec3::new(1.0, 0.1, 0.0), Vec3::new(-1.0, 2.0, 3.0)] {
+                let onb = Onb::new(w);
+                assert!(approx_eq(onb.u.length(), 1.0, 1e-12));
+                assert!(approx_eq(onb.v.length(), 1.0, 1e-12));
+                assert!(approx_eq(onb.u.dot(&onb.v), 0.0, 1e-12));
+                assert!(approx_eq(onb.u.dot(&onb.w), 0.0, 1e-12));
+                assert!(approx_eq(onb.v.dot(&onb.w), 0.0, 1e-12));
+                assert!(approx_eq(onb.local(0.0, 0.0, 1.0).dot(&w.unit_vector()), 1.0, 1e-12));
+
+                let a = Vec3::new(0.3, -1.2, 0.7);
+                let projected = onb.project(a);
+                assert!((onb.local(projected.x(), projected.y(), projected.z()) - a).near_zero());
+            }
+        }
+
+        #[test]
+        fn follows_tangent() {
+            let w = Vec3::new(0.0, 0.0, 1.0);
+            let onb = Onb::with_tangent(w, Vec3::new(2.0, 2.0, 1.0));
+            let expected = Vec3::new(1.0, 1.0, 0.0).unit_vector();
+            assert!((onb.u - expected).near_zero());
+            assert!((onb.u.cross(&onb.v) - w).near_zero());
+
+            // Without a usable tangent any basis around w will do.
+            for tangent in [Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -3.0)] {
+                let onb = Onb::with_tangent(w, tangent);
+                assert!(approx_eq(onb.w.z(), 1.0, 1e-12));
+                assert!(approx_eq(onb.u.dot(&onb.v), 0.0, 1e-12));
+            }
+        }
+
+        #[test]
+        fn cone() {
+            for _ in 0..100 {
+                let direction = random_in_cone(0.8);
+                assert!(approx_eq(direction.length(), 1.0, 1e-12));
+                assert!(direction.z() >= 0.8 - 1e-12);
+            }
+        }
+    }
+
     fn approx_eq(x: f64, y: f64, tolerance: f64) -> bool {
         (x - y).abs() < tolerance
     }
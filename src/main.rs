@@ -1,62 +1,23 @@
-use std::io::stderr;
-use std::io::Write;
-use std::rc::Rc;
-mod random;
-use random::rand;
-mod linear_algebra;
-use linear_algebra::{Ray, Vec3, random_unit_vector, random_vector};
-mod geometry;
-use geometry::{Hittable, HittableList, Sphere};
-mod camera;
-use camera::Camera;
-mod materials;
-use materials::{Lambertian, Metal, Dielectric};
-use std::f64::consts::PI;
-
-type Colour = Vec3;
-type Point = Vec3;
-
-fn clamp(x: f64, min: f64, max: f64) -> f64 {
-    if x < min {
-        min
-    } else if x > max {
-        max
-    } else {
-        x
-    }
-}
-
-fn ray_colour(ray: Ray, world: &dyn Hittable, depth: usize) -> Colour {
-    if depth == 0{
-        return Colour::new(0.0, 0.0, 0.0);
-    }
-
-    if let Some(record) = world.hit(ray, 0.001, f64::INFINITY) {
-
-        if let Some(scattered_ray) = record.material.scatter(&ray, &record){
-            return scattered_ray.attenuation * ray_colour(scattered_ray.ray, world, depth - 1);
-        }
-
-        return Colour::new(0.0, 0.0, 0.0);
-    }
-
-    let unit_direction = ray.direction.unit_vector();
-    let t = 0.5 * (unit_direction.y() + 1.0);
-    (1.0 - t) * Colour::new(1.0, 1.0, 1.0) + t * Colour::new(0.5, 0.7, 1.0)
-}
-
-fn write_colour(pixel_colour: &Colour, samples_per_pixel: usize) {
-    let r = (pixel_colour.x()/(samples_per_pixel as f64)).sqrt();
-    let g = (pixel_colour.y()/(samples_per_pixel as f64)).sqrt();
-    let b = (pixel_colour.z()/(samples_per_pixel as f64)).sqrt();
-
-    let red = (256.0*clamp(r,0.0,0.999)) as usize;
-    let green = (256.0*clamp(g,0.0,0.999)) as usize;
-    let blue = (256.0*clamp(b,0.0,0.999)) as usize;
-    println!("{} {} {}", red, green, blue);
-}
-
-fn main() {
+use std::env;
+use std::io::{self, BufWriter};
+use std::process;
+use std::sync::Arc;
+mod cli;
+use cli::{Command, Options};
+use raytracer::random::{self, rand};
+use raytracer::linear_algebra::{Vec3, random_vector};
+use raytracer::geometry::{HittableList, Sphere};
+use raytracer::image::{save_image, write_image, ImageFormat};
+use raytracer::light::LightList;
+use raytracer::render::{render, RenderSettings};
+use raytracer::scene::{load_scene, Scene};
+use raytracer::background::GradientBackground;
+use raytracer::bvh::BvhNode;
+use raytracer::camera::Camera;
+use raytracer::materials::{Lambertian, Metal, Dielectric};
+use raytracer::Colour;
+
+fn random_spheres_scene() -> Scene {
     // Image
     let aspect_ratio = 3.0 / 2.0;
     let image_width = 1200;
@@ -67,11 +28,11 @@ fn main() {
     // World
     let mut world = HittableList::new();
 
-    let material_ground = Rc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
-    world.add(Rc::new(Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 1000.0, material_ground.clone())));
+    let material_ground = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+    world.add(Arc::new(Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 1000.0, material_ground.clone())));
 
     let offset_point = Vec3::new(4.0, 0.2, 0.0);
-    let glass_material = Rc::new(Dielectric::new(1.5));
+    let glass_material = Arc::new(Dielectric::new(1.5));
 
     for a in -11..11{
         for b in -11..11{
@@ -81,29 +42,28 @@ fn main() {
             if (centre - offset_point).length() > 0.9 {
                 if choose_mat < 0.8 {
                     let albedo = random_vector(0.0, 1.0) * random_vector(0.0, 1.0);
-                    let sphere_material = Rc::new(Lambertian::new(albedo));
-                    world.add(Rc::new(Sphere::new(centre, 0.2, sphere_material)));
+                    let sphere_material = Arc::new(Lambertian::new(albedo));
+                    world.add(Arc::new(Sphere::new(centre, 0.2, sphere_material)));
                 } else if choose_mat < 0.95 {
                     let albedo = random_vector(0.5, 1.0);
                     let fuzz = rand(0.0, 0.5);
-                    let sphere_material = Rc::new(Metal::new(albedo, fuzz));
-                    world.add(Rc::new(Sphere::new(centre, 0.2, sphere_material)));
+                    let sphere_material = Arc::new(Metal::new(albedo, fuzz));
+                    world.add(Arc::new(Sphere::new(centre, 0.2, sphere_material)));
                 } else {
                     let sphere_material = glass_material.clone();
-                    world.add(Rc::new(Sphere::new(centre, 0.2, sphere_material)));
+                    world.add(Arc::new(Sphere::new(centre, 0.2, sphere_material)));
                 }
             }
         }
     }
 
-    world.add(Rc::new(Sphere::new(Vec3::new(0.0, 1.0, 0.0), 1.0, glass_material.clone())));
+    world.add(Arc::new(Sphere::new(Vec3::new(0.0, 1.0, 0.0), 1.0, glass_material.clone())));
 
-    let brown_material = Rc::new(Lambertian::new(Colour::new(0.4, 0.2, 0.1)));
-    world.add(Rc::new(Sphere::new(Vec3::new(-4.0, 1.0, 0.0), 1.0, brown_material)));
+    let brown_material = Arc::new(Lambertian::new(Colour::new(0.4, 0.2, 0.1)));
+    world.add(Arc::new(Sphere::new(Vec3::new(-4.0, 1.0, 0.0), 1.0, brown_material)));
 
-    let shiny_steel_material = Rc::new(Metal::new(Colour::new(0.7, 0.6, 0.5), 0.0));
-    world.add(Rc::new(Sphere::new(Vec3::new(4.0, 1.0, 0.0), 1.0, shiny_steel_material)));
-    
+    let shiny_steel_material = Arc::new(Metal::new(Colour::new(0.7, 0.6, 0.5), 0.0));
+    world.add(Arc::new(Sphere::new(Vec3::new(4.0, 1.0, 0.0), 1.0, shiny_steel_material)));
 
     // Camera
     let look_from = Vec3::new(13.0, 2.0, 3.0);
@@ -113,22 +73,113 @@ fn main() {
     let aperture = 0.1;
     let camera = Camera::new(look_from, look_at, vec_up, 20.0, aspect_ratio, aperture, focus_dist);
 
-    // Render
+    let settings = RenderSettings::new(image_width, image_height, samples_per_pixel, max_depth);
+
+    Scene {
+        settings,
+        camera,
+        world,
+        lights: LightList::new(),
+        background: Arc::new(GradientBackground::default()),
+    }
+}
+
+fn apply_options(scene: &mut Scene, options: &Options) {
+    let settings = &mut scene.settings;
+    let aspect_ratio = scene.camera.aspect_ratio();
 
-    print!("P3\n{} {}\n255\n", image_width, image_height);
-
-    for row in (0..image_height).rev() {
-        eprint!("\rScanlines remaining: {}", row);
-        stderr().flush().unwrap();
-        for col in 0..image_width {
-            let mut pixel_colour = Colour::new(0.0, 0.0, 0.0);
-            for _ in 0..samples_per_pixel{
-                let u = (col as f64 + rand(0.0, 1.0)) / (image_width - 1) as f64;
-                let v = (row as f64 + rand(0.0, 1.0)) / (image_height - 1) as f64;
-                let ray = camera.get_ray(u, v);
-                pixel_colour += ray_colour(ray, &world, max_depth);
+    match (options.width, options.height) {
+        (Some(width), Some(height)) => {
+            settings.image_width = width;
+            settings.image_height = height;
+            scene.camera.set_aspect_ratio(width as f64 / height as f64);
+        }
+        (Some(width), None) => {
+            settings.image_width = width;
+            settings.image_height = ((width as f64 / aspect_ratio).round() as usize).max(1);
+        }
+        (None, Some(height)) => {
+            settings.image_height = height;
+            settings.image_width = ((height as f64 * aspect_ratio).round() as usize).max(1);
+        }
+        (None, None) => {}
+    }
+
+    if let Some(samples_per_pixel) = options.samples_per_pixel {
+        settings.samples_per_pixel = samples_per_pixel;
+    }
+    if let Some(max_depth) = options.max_depth {
+        settings.max_depth = max_depth;
+    }
+    if let Some(threads) = options.threads {
+        settings.threads = threads;
+    }
+    settings.seed = options.seed;
+}
+
+fn main() {
+    let options = match cli::parse_args(env::args().skip(1)) {
+        Ok(Command::Render(options)) => options,
+        Ok(Command::Help) => {
+            print!("{}", cli::USAGE);
+            return;
+        }
+        Err(message) => {
+            eprintln!("error: {}\n\nFor more information, try '--help'.", message);
+            process::exit(2);
+        }
+    };
+
+    let format = match (options.format, &options.output) {
+        (Some(format), _) => format,
+        (None, Some(path)) => match ImageFormat::from_path(path) {
+            Some(format) => format,
+            None => {
+                eprintln!(
+                    "error: cannot infer an image format from '{}', use --format\n\nFor more information, try '--help'.",
+                    path.display()
+                );
+                process::exit(2);
+            }
+        },
+        (None, None) => ImageFormat::PpmAscii,
+    };
+
+    if let Some(seed) = options.seed {
+        random::seed(seed);
+    }
+
+    let mut scene = match &options.scene {
+        Some(path) => match load_scene(path) {
+            Ok(scene) => scene,
+            Err(error) => {
+                eprintln!("error: {}", error);
+                process::exit(1);
             }
-            write_colour(&pixel_colour, samples_per_pixel);
+        },
+        None => random_spheres_scene(),
+    };
+    apply_options(&mut scene, &options);
+
+    if scene.settings.image_width < 2 || scene.settings.image_height < 2 {
+        eprintln!("error: image must be at least 2 pixels wide and high");
+        process::exit(2);
+    }
+
+    // Render
+    let world = BvhNode::new(&scene.world);
+    let framebuffer = render(&world, &scene.lights, scene.background.as_ref(), &scene.camera, &scene.settings);
+
+    let result = match &options.output {
+        Some(path) => save_image(path, &framebuffer, format),
+        None => write_image(&mut BufWriter::new(io::stdout().lock()), &framebuffer, format),
+    };
+
+    if let Err(error) = result {
+        match &options.output {
+            Some(path) => eprintln!("error: could not write {}: {}", path.display(), error),
+            None => eprintln!("error: could not write image: {}", error),
         }
+        process::exit(1);
     }
 }
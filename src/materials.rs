@@ -1,15 +1,59 @@
 use crate::geometry::HitRecord;
-use crate::linear_algebra::{random_unit_vector, reflect, refract, Ray};
+use crate::linear_algebra::{random_unit_vector, reflect, refract, Onb, Ray, Vec3};
 use crate::random::rand;
+use crate::texture::{SolidColour, Texture};
 use crate::Colour;
+use std::f64::consts::PI;
+use std::sync::Arc;
 
 pub struct ScatteredRay {
+    // The BSDF times the cosine term, divided by `pdf`.
     pub attenuation: Colour,
     pub ray: Ray,
+    // The density over solid angle with which the ray's direction was picked, or None for
+    // specular directions that light sampling could never pick.
+    pub pdf: Option<f64>,
 }
 
-pub trait Material {
+// `scatter` samples a direction for the incident ray to continue in. `eval` and `pdf` give,
+// for any other direction, the BSDF times the cosine term and the density with which
+// `scatter` would pick it, so that lights can be sampled directly and the two strategies
+// weighted against each other. Specular materials have nothing to evaluate and keep the
+// defaults of zero.
+pub trait Material: Send + Sync {
     fn scatter(&self, incident_ray: &Ray, hit_record: &HitRecord) -> Option<ScatteredRay>;
+
+    fn eval(&self, _incident_ray: &Ray, _hit_record: &HitRecord, _direction: Vec3) -> Colour {
+        Colour::new(0.0, 0.0, 0.0)
+    }
+
+    fn pdf(&self, _incident_ray: &Ray, _hit_record: &HitRecord, _direction: Vec3) -> f64 {
+        0.0
+    }
+
+    fn emitted(&self, _hit_record: &HitRecord) -> Colour {
+        Colour::new(0.0, 0.0, 0.0)
+    }
+
+    // Whether surfaces made of this material should be registered as lights.
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    // The medium enclosed by closed surfaces of this material, for refractive materials.
+    fn medium(&self) -> Option<Medium> {
+        None
+    }
+}
+
+// The inside of a closed refractive surface. Light travelling through it is absorbed at the
+// rate `absorption` per unit distance. Where media overlap, as where a liquid meets the walls
+// of its glass, the one with the highest priority fills the overlap.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Medium {
+    pub refraction_index: f64,
+    pub absorption: Colour,
+    pub priority: u32,
 }
 
 pub struct EmptyMaterial;
@@ -21,72 +65,154 @@ impl Material for EmptyMaterial {
 }
 
 pub struct Lambertian {
-    albedo: Colour,
+    albedo: Arc<dyn Texture>,
 }
 
 impl Lambertian {
     pub fn new(albedo: Colour) -> Self {
+        Self::with_texture(Arc::new(SolidColour::new(albedo)))
+    }
+
+    pub fn with_texture(albedo: Arc<dyn Texture>) -> Self {
         Self { albedo }
     }
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _incident_ray: &Ray, hit_record: &HitRecord) -> Option<ScatteredRay> {
+    fn scatter(&self, incident_ray: &Ray, hit_record: &HitRecord) -> Option<ScatteredRay> {
         let mut scatter_direction = hit_record.normal + random_unit_vector();
 
         if scatter_direction.near_zero() {
             scatter_direction = hit_record.normal;
         }
 
+        // Offsetting the normal by a unit vector gives directions distributed by cosine.
         let scattered_ray = ScatteredRay {
-            attenuation: self.albedo,
-            ray: Ray::new(hit_record.hit_point, scatter_direction),
+            attenuation: self.albedo.value(hit_record.u, hit_record.v, hit_record.hit_point),
+            ray: Ray::with_time(hit_record.hit_point, scatter_direction, incident_ray.time),
+            pdf: Some(self.pdf(incident_ray, hit_record, scatter_direction)),
         };
 
         Some(scattered_ray)
     }
+
+    fn eval(&self, incident_ray: &Ray, hit_record: &HitRecord, direction: Vec3) -> Colour {
+        let albedo = self.albedo.value(hit_record.u, hit_record.v, hit_record.hit_point);
+        self.pdf(incident_ray, hit_record, direction) * albedo
+    }
+
+    fn pdf(&self, _incident_ray: &Ray, hit_record: &HitRecord, direction: Vec3) -> f64 {
+        hit_record.normal.dot(&direction.unit_vector()).max(0.0) / PI
+    }
 }
 
 pub struct Metal {
-    albedo: Colour,
-    fuzz: f64,
+    albedo: Arc<dyn Texture>,
+    fuzz: Arc<dyn Texture>,
 }
 
 impl Metal {
     pub fn new(albedo: Colour, fuzz: f64) -> Self {
+        Self::with_texture(Arc::new(SolidColour::new(albedo)), fuzz)
+    }
+
+    pub fn with_texture(albedo: Arc<dyn Texture>, fuzz: f64) -> Self {
+        Self::with_textures(albedo, Arc::new(SolidColour::new(Colour::new(fuzz, fuzz, fuzz))))
+    }
+
+    // The fuzz texture is read as a scalar and clamped to [0, 1].
+    pub fn with_textures(albedo: Arc<dyn Texture>, fuzz: Arc<dyn Texture>) -> Self {
         Self { albedo, fuzz }
     }
 }
 
+impl Metal {
+    fn fuzz(&self, hit_record: &HitRecord) -> f64 {
+        self.fuzz
+            .scalar(hit_record.u, hit_record.v, hit_record.hit_point)
+            .clamp(0.0, 1.0)
+    }
+}
+
+// Fuzzy reflection picks a point uniformly on the sphere of radius `fuzz` around the tip of
+// the unit mirror direction. The density of `direction` is that of the points where it
+// crosses the sphere, projected onto the unit sphere of directions.
+fn fuzzy_reflection_pdf(reflected: Vec3, fuzz: f64, direction: Vec3) -> f64 {
+    let direction = direction.unit_vector();
+    let b = direction.dot(&reflected);
+    let discriminant = b * b - (1.0 - fuzz * fuzz);
+    if discriminant < 0.0 {
+        return 0.0;
+    }
+
+    [b - discriminant.sqrt(), b + discriminant.sqrt()]
+        .iter()
+        .filter(|&&t| t > 0.0)
+        .map(|&t| {
+            let sphere_normal = (t * direction - reflected) / fuzz;
+            let cosine = sphere_normal.dot(&direction).abs().max(1e-6);
+            t * t / (4.0 * PI * fuzz * fuzz * cosine)
+        })
+        .sum()
+}
+
 impl Material for Metal {
     fn scatter(&self, incident_ray: &Ray, hit_record: &HitRecord) -> Option<ScatteredRay> {
-        let reflected = reflect(incident_ray.direction, hit_record.normal);
-        let scattered = Ray::new(
+        let reflected = reflect(incident_ray.direction.unit_vector(), hit_record.normal);
+        let fuzz = self.fuzz(hit_record);
+        let scattered = Ray::with_time(
             hit_record.hit_point,
-            reflected + self.fuzz * random_unit_vector(),
+            reflected + fuzz * random_unit_vector(),
+            incident_ray.time,
         );
 
         if scattered.direction.dot(&hit_record.normal) > 0.0 {
-            return Some(ScatteredRay {
-                attenuation: self.albedo,
+            Some(ScatteredRay {
+                attenuation: self.albedo.value(hit_record.u, hit_record.v, hit_record.hit_point),
                 ray: scattered,
-            });
+                pdf: (fuzz > 0.0).then(|| fuzzy_reflection_pdf(reflected, fuzz, scattered.direction)),
+            })
         } else {
-            return None;
+            None
+        }
+    }
+
+    // Directions scattered below the surface are absorbed, so the rest have the albedo as
+    // their weight and the BSDF follows the sampling density.
+    fn eval(&self, incident_ray: &Ray, hit_record: &HitRecord, direction: Vec3) -> Colour {
+        if direction.dot(&hit_record.normal) <= 0.0 {
+            return Colour::new(0.0, 0.0, 0.0);
         }
+        let albedo = self.albedo.value(hit_record.u, hit_record.v, hit_record.hit_point);
+        self.pdf(incident_ray, hit_record, direction) * albedo
+    }
+
+    fn pdf(&self, incident_ray: &Ray, hit_record: &HitRecord, direction: Vec3) -> f64 {
+        let fuzz = self.fuzz(hit_record);
+        if fuzz <= 0.0 {
+            return 0.0;
+        }
+        let reflected = reflect(incident_ray.direction.unit_vector(), hit_record.normal);
+        fuzzy_reflection_pdf(reflected, fuzz, direction)
     }
 }
 
 pub struct Dielectric {
-    refraction_index: f64,
-    attenuation: Colour,
+    medium: Medium,
 }
 
 impl Dielectric {
     pub fn new(refraction_index: f64) -> Self {
+        Self::with_absorption(refraction_index, Colour::new(0.0, 0.0, 0.0), 0)
+    }
+
+    pub fn with_absorption(refraction_index: f64, absorption: Colour, priority: u32) -> Self {
         Self {
-            refraction_index,
-            attenuation: Colour::new(1.0, 1.0, 1.0),
+            medium: Medium {
+                refraction_index,
+                absorption,
+                priority,
+            },
         }
     }
 
@@ -98,11 +224,7 @@ impl Dielectric {
 
 impl Material for Dielectric {
     fn scatter(&self, incident_ray: &Ray, hit_record: &HitRecord) -> Option<ScatteredRay> {
-        let refraction_ratio = if hit_record.front_face {
-            1.0 / self.refraction_index
-        } else {
-            self.refraction_index
-        };
+        let refraction_ratio = 1.0 / relative_eta(self.medium.refraction_index, hit_record);
 
         let unit_direction = incident_ray.direction.unit_vector();
 
@@ -116,11 +238,999 @@ impl Material for Dielectric {
         } else {
             refract(unit_direction, hit_record.normal, refraction_ratio)
         };
-        let scattered_ray = Ray::new(hit_record.hit_point, direction);
+        let scattered_ray = Ray::with_time(hit_record.hit_point, direction, incident_ray.time);
 
+        // Absorption inside is left to the integrator, which knows how far the ray travels.
         Some(ScatteredRay {
-            attenuation: self.attenuation,
+            attenuation: Colour::new(1.0, 1.0, 1.0),
             ray: scattered_ray,
+            pdf: None,
         })
     }
+
+    fn medium(&self) -> Option<Medium> {
+        Some(self.medium)
+    }
+}
+
+fn constant(value: f64) -> Arc<dyn Texture> {
+    Arc::new(SolidColour::new(Colour::new(value, value, value)))
+}
+
+// Below this alpha a microfacet surface is treated as perfectly smooth.
+const MIN_ALPHA: f64 = 1e-3;
+
+// The GGX (Trowbridge-Reitz) distribution of microfacet normals with Smith masking, in a
+// shading frame with the normal along z. Roughness is squared to give alpha, and anisotropy
+// stretches the highlight along the frame's u axis.
+#[derive(Debug, Copy, Clone)]
+struct Microfacet {
+    alpha_x: f64,
+    alpha_y: f64,
+}
+
+impl Microfacet {
+    fn new(roughness: f64, anisotropy: f64) -> Self {
+        let alpha = roughness.clamp(0.0, 1.0).powi(2);
+        let aspect = (1.0 - 0.9 * anisotropy.clamp(0.0, 1.0)).sqrt();
+        Self {
+            alpha_x: (alpha / aspect).max(MIN_ALPHA),
+            alpha_y: (alpha * aspect).max(MIN_ALPHA),
+        }
+    }
+
+    fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) <= MIN_ALPHA
+    }
+
+    // The density of microfacet normal `m` per unit projected area.
+    fn distribution(&self, m: Vec3) -> f64 {
+        if m.z() <= 0.0 {
+            return 0.0;
+        }
+        let (x, y) = (m.x() / self.alpha_x, m.y() / self.alpha_y);
+        let t = x * x + y * y + m.z() * m.z();
+        1.0 / (PI * self.alpha_x * self.alpha_y * t * t)
+    }
+
+    fn lambda(&self, w: Vec3) -> f64 {
+        let (x, y) = (self.alpha_x * w.x(), self.alpha_y * w.y());
+        let tan2 = (x * x + y * y) / (w.z() * w.z());
+        0.5 * (-1.0 + (1.0 + tan2).sqrt())
+    }
+
+    // The fraction of microfacets visible from `w`.
+    fn masking(&self, w: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // The fraction visible from both `wo` and `wi`, allowing for their correlation.
+    fn masking_shadowing(&self, wo: Vec3, wi: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // The density of the normals seen from `wo`, which is what `sample_visible` draws from.
+    fn visible_pdf(&self, wo: Vec3, m: Vec3) -> f64 {
+        self.masking(wo) * self.distribution(m) * wo.dot(&m).max(0.0) / wo.z()
+    }
+
+    // Samples a microfacet normal visible from `wo` (Heitz 2018): the view is stretched
+    // to make the distribution isotropic with unit roughness, where the visible normals
+    // project to a disc, half of which is foreshortened.
+    fn sample_visible(&self, wo: Vec3) -> Vec3 {
+        let view = Vec3::new(self.alpha_x * wo.x(), self.alpha_y * wo.y(), wo.z()).unit_vector();
+        let length_squared = view.x() * view.x() + view.y() * view.y();
+        let t1 = if length_squared > 0.0 {
+            Vec3::new(-view.y(), view.x(), 0.0) / length_squared.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = view.cross(&t1);
+
+        let r = rand(0.0, 1.0).sqrt();
+        let phi = 2.0 * PI * rand(0.0, 1.0);
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + view.z());
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+        let normal = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * view;
+
+        Vec3::new(self.alpha_x * normal.x(), self.alpha_y * normal.y(), normal.z().max(0.0)).unit_vector()
+    }
+}
+
+// The mirror image of `wo` about the microfacet normal `m`.
+fn reflect_about(wo: Vec3, m: Vec3) -> Vec3 {
+    2.0 * wo.dot(&m) * m - wo
+}
+
+// The fraction of light reflected by a dielectric interface, where `eta` is the ratio of
+// the refractive index beyond the surface to that on the incident side.
+fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (parallel * parallel + perpendicular * perpendicular)
+}
+
+// The fraction of light reflected by a conductor with complex refractive index eta + ik,
+// evaluated for each channel.
+fn fresnel_conductor(cos_i: f64, eta: Colour, k: Colour) -> Colour {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let (cos2, sin2) = (cos_i * cos_i, 1.0 - cos_i * cos_i);
+    let channel = |eta: f64, k: f64| {
+        let t0 = eta * eta - k * k - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+        let t1 = a2_plus_b2 + cos2;
+        let t2 = 2.0 * cos_i * a;
+        let s = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let p = s * (t3 - t4) / (t3 + t4);
+        0.5 * (s + p)
+    };
+    Colour::new(
+        channel(eta.x(), k.x()),
+        channel(eta.y(), k.y()),
+        channel(eta.z(), k.z()),
+    )
+}
+
+// A rough metal described by its complex refractive index per channel, which sets both its
+// colour and how that colour shifts towards white at grazing angles.
+pub struct Conductor {
+    eta: Colour,
+    k: Colour,
+    roughness: Arc<dyn Texture>,
+    anisotropy: f64,
+}
+
+impl Conductor {
+    pub fn new(eta: Colour, k: Colour, roughness: f64, anisotropy: f64) -> Self {
+        Self::with_texture(eta, k, constant(roughness), anisotropy)
+    }
+
+    // The roughness texture is read as a scalar and clamped to [0, 1].
+    pub fn with_texture(eta: Colour, k: Colour, roughness: Arc<dyn Texture>, anisotropy: f64) -> Self {
+        Self {
+            eta,
+            k,
+            roughness,
+            anisotropy,
+        }
+    }
+
+    fn microfacet(&self, hit_record: &HitRecord) -> Microfacet {
+        let roughness = self
+            .roughness
+            .scalar(hit_record.u, hit_record.v, hit_record.hit_point);
+        Microfacet::new(roughness, self.anisotropy)
+    }
+
+    // The BSDF times the cosine term and the sampling density of `wi`, in the shading frame.
+    fn evaluate(&self, microfacet: &Microfacet, wo: Vec3, wi: Vec3) -> (Colour, f64) {
+        let m = (wo + wi).unit_vector();
+        if wo.z() <= 0.0 || wi.z() <= 0.0 || wo.dot(&m) <= 0.0 {
+            return (Colour::new(0.0, 0.0, 0.0), 0.0);
+        }
+        let fresnel = fresnel_conductor(wi.dot(&m), self.eta, self.k);
+        let value = microfacet.distribution(m) * microfacet.masking_shadowing(wo, wi) / (4.0 * wo.z());
+        let pdf = microfacet.visible_pdf(wo, m) / (4.0 * wo.dot(&m));
+        (value * fresnel, pdf)
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(&self, incident_ray: &Ray, hit_record: &HitRecord) -> Option<ScatteredRay> {
+        let frame = Onb::with_tangent(hit_record.normal, hit_record.tangent);
+        let wo = frame.project(-incident_ray.direction.unit_vector());
+        let microfacet = self.microfacet(hit_record);
+
+        if microfacet.is_smooth() {
+            let wi = Vec3::new(-wo.x(), -wo.y(), wo.z());
+            return Some(ScatteredRay {
+                attenuation: fresnel_conductor(wo.z(), self.eta, self.k),
+                ray: Ray::with_time(hit_record.hit_point, frame.local(wi.x(), wi.y(), wi.z()), incident_ray.time),
+                pdf: None,
+            });
+        }
+
+        let wi = reflect_about(wo, microfacet.sample_visible(wo));
+        let (value, pdf) = self.evaluate(&microfacet, wo, wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(ScatteredRay {
+            attenuation: value / pdf,
+            ray: Ray::with_time(hit_record.hit_point, frame.local(wi.x(), wi.y(), wi.z()), incident_ray.time),
+            pdf: Some(pdf),
+        })
+    }
+
+    fn eval(&self, incident_ray: &Ray, hit_record: &HitRecord, direction: Vec3) -> Colour {
+        let microfacet = self.microfacet(hit_record);
+        if microfacet.is_smooth() {
+            return Colour::new(0.0, 0.0, 0.0);
+        }
+        let frame = Onb::with_tangent(hit_record.normal, hit_record.tangent);
+        let wo = frame.project(-incident_ray.direction.unit_vector());
+        self.evaluate(&microfacet, wo, frame.project(direction.unit_vector())).0
+    }
+
+    fn pdf(&self, incident_ray: &Ray, hit_record: &HitRecord, direction: Vec3) -> f64 {
+        let microfacet = self.microfacet(hit_record);
+        if microfacet.is_smooth() {
+            return 0.0;
+        }
+        let frame = Onb::with_tangent(hit_record.normal, hit_record.tangent);
+        let wo = frame.project(-incident_ray.direction.unit_vector());
+        self.evaluate(&microfacet, wo, frame.project(direction.unit_vector())).1
+    }
+}
+
+// Frosted glass: a dielectric interface whose microfacets both reflect and refract.
+pub struct RoughDielectric {
+    medium: Medium,
+    roughness: Arc<dyn Texture>,
+    anisotropy: f64,
+}
+
+impl RoughDielectric {
+    pub fn new(refraction_index: f64, roughness: f64, anisotropy: f64) -> Self {
+        Self::with_texture(refraction_index, constant(roughness), anisotropy)
+    }
+
+    // The roughness texture is read as a scalar and clamped to [0, 1].
+    pub fn with_texture(refraction_index: f64, roughness: Arc<dyn Texture>, anisotropy: f64) -> Self {
+        Self::with_absorption(refraction_index, roughness, anisotropy, Colour::new(0.0, 0.0, 0.0), 0)
+    }
+
+    pub fn with_absorption(
+        refraction_index: f64,
+        roughness: Arc<dyn Texture>,
+        anisotropy: f64,
+        absorption: Colour,
+        priority: u32,
+    ) -> Self {
+        Self {
+            medium: Medium {
+                refraction_index,
+                absorption,
+                priority,
+            },
+            roughness,
+            anisotropy,
+        }
+    }
+
+    fn microfacet(&self, hit_record: &HitRecord) -> Microfacet {
+        let roughness = self
+            .roughness
+            .scalar(hit_record.u, hit_record.v, hit_record.hit_point);
+        Microfacet::new(roughness, self.anisotropy)
+    }
+}
+
+// The ratio of the refractive index beyond the surface to that on the incident side, for a
+// material of the given index surrounded by whatever the hit record says lies outside.
+fn relative_eta(refraction_index: f64, hit_record: &HitRecord) -> f64 {
+    if hit_record.front_face {
+        refraction_index / hit_record.surrounding_index
+    } else {
+        hit_record.surrounding_index / refraction_index
+    }
+}
+
+// Picks a direction off a dielectric interface by reflecting or refracting through a
+// microfacet visible from `wo`, chosen by the Fresnel term. Steep microfacets can send
+// light to the wrong side of the surface, in which case it is lost.
+fn sample_dielectric(microfacet: &Microfacet, wo: Vec3, eta: f64) -> Option<Vec3> {
+    let m = if microfacet.is_smooth() {
+        Vec3::new(0.0, 0.0, 1.0)
+    } else {
+        microfacet.sample_visible(wo)
+    };
+    let reflected = fresnel_dielectric(wo.dot(&m), eta) > rand(0.0, 1.0);
+    let wi = if reflected {
+        reflect_about(wo, m)
+    } else {
+        refract(-wo, m, 1.0 / eta).unit_vector()
+    };
+    ((wi.z() > 0.0) == reflected).then_some(wi)
+}
+
+// The BSDF times the cosine term of a rough dielectric interface, and the density with which
+// `sample_dielectric` picks `wi`. Transmitted radiance is scaled by 1 / eta^2 as the beam is
+// compressed or spread out.
+fn evaluate_dielectric(microfacet: &Microfacet, wo: Vec3, wi: Vec3, eta: f64) -> (f64, f64) {
+    if wo.z() <= 0.0 || wi.z() == 0.0 {
+        return (0.0, 0.0);
+    }
+
+    if wi.z() > 0.0 {
+        let m = (wo + wi).unit_vector();
+        if wo.dot(&m) <= 0.0 {
+            return (0.0, 0.0);
+        }
+        let fresnel = fresnel_dielectric(wo.dot(&m), eta);
+        let value = fresnel * microfacet.distribution(m) * microfacet.masking_shadowing(wo, wi) / (4.0 * wo.z());
+        let pdf = fresnel * microfacet.visible_pdf(wo, m) / (4.0 * wo.dot(&m));
+        return (value, pdf);
+    }
+
+    let mut m = (wo + eta * wi).unit_vector();
+    if m.z() < 0.0 {
+        m = -m;
+    }
+    let (cos_o, cos_i) = (wo.dot(&m), wi.dot(&m));
+    if cos_o <= 0.0 || cos_i >= 0.0 {
+        return (0.0, 0.0);
+    }
+    let transmitted = 1.0 - fresnel_dielectric(cos_o, eta);
+    let denominator = (cos_i + cos_o / eta).powi(2);
+    let value = transmitted
+        * microfacet.distribution(m)
+        * microfacet.masking_shadowing(wo, wi)
+        * (cos_i * cos_o).abs()
+        / (wo.z() * denominator * eta * eta);
+    let pdf = transmitted * microfacet.visible_pdf(wo, m) * cos_i.abs() / denominator;
+    (value, pdf)
+}
+
+impl Material for RoughDielectric {
+    fn scatter(&self, incident_ray: &Ray, hit_record: &HitRecord) -> Option<ScatteredRay> {
+        let frame = Onb::with_tangent(hit_record.normal, hit_record.tangent);
+        let wo = frame.project(-incident_ray.direction.unit_vector());
+        let microfacet = self.microfacet(hit_record);
+        let eta = relative_eta(self.medium.refraction_index, hit_record);
+
+        let wi = sample_dielectric(&microfacet, wo, eta)?;
+        let ray = Ray::with_time(hit_record.hit_point, frame.local(wi.x(), wi.y(), wi.z()), incident_ray.time);
+        if microfacet.is_smooth() {
+            let weight = if wi.z() > 0.0 { 1.0 } else { 1.0 / (eta * eta) };
+            return Some(ScatteredRay {
+                attenuation: Colour::new(weight, weight, weight),
+                ray,
+                pdf: None,
+            });
+        }
+
+        let (value, pdf) = evaluate_dielectric(&microfacet, wo, wi, eta);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let attenuation = value / pdf;
+        Some(ScatteredRay {
+            attenuation: Colour::new(attenuation, attenuation, attenuation),
+            ray,
+            pdf: Some(pdf),
+        })
+    }
+
+    fn eval(&self, incident_ray: &Ray, hit_record: &HitRecord, direction: Vec3) -> Colour {
+        let microfacet = self.microfacet(hit_record);
+        if microfacet.is_smooth() {
+            return Colour::new(0.0, 0.0, 0.0);
+        }
+        let frame = Onb::with_tangent(hit_record.normal, hit_record.tangent);
+        let wo = frame.project(-incident_ray.direction.unit_vector());
+        let wi = frame.project(direction.unit_vector());
+        let value = evaluate_dielectric(&microfacet, wo, wi, relative_eta(self.medium.refraction_index, hit_record)).0;
+        Colour::new(value, value, value)
+    }
+
+    fn pdf(&self, incident_ray: &Ray, hit_record: &HitRecord, direction: Vec3) -> f64 {
+        let microfacet = self.microfacet(hit_record);
+        if microfacet.is_smooth() {
+            return 0.0;
+        }
+        let frame = Onb::with_tangent(hit_record.normal, hit_record.tangent);
+        let wo = frame.project(-incident_ray.direction.unit_vector());
+        let wi = frame.project(direction.unit_vector());
+        evaluate_dielectric(&microfacet, wo, wi, relative_eta(self.medium.refraction_index, hit_record)).1
+    }
+
+    fn medium(&self) -> Option<Medium> {
+        Some(self.medium)
+    }
+}
+
+// The roughness of the principled material's clear coat, a thin glossy varnish.
+const CLEARCOAT_ROUGHNESS: f64 = 0.15;
+
+// The inputs of `Principled`. Every parameter but the last two may vary across the surface;
+// the scalar ones are read by luminance and clamped to [0, 1].
+pub struct PrincipledParameters {
+    pub base_colour: Arc<dyn Texture>,
+    // Blends from a dielectric to a metal whose specular colour is the base colour.
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    // The strength of a dielectric's specular reflection; 0.5 is a refractive index of 1.5.
+    pub specular: Arc<dyn Texture>,
+    // A soft rim of light at grazing angles, as on cloth.
+    pub sheen: Arc<dyn Texture>,
+    pub clearcoat: Arc<dyn Texture>,
+    // Blends a dielectric towards glass that takes on the base colour.
+    pub transmission: Arc<dyn Texture>,
+    // Flattens the diffuse lobe to approximate light scattered beneath the surface.
+    pub subsurface: Arc<dyn Texture>,
+    pub anisotropy: f64,
+    // The refractive index of transmitting material.
+    pub refraction_index: f64,
+}
+
+impl Default for PrincipledParameters {
+    fn default() -> Self {
+        Self {
+            base_colour: constant(0.8),
+            metallic: constant(0.0),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            sheen: constant(0.0),
+            clearcoat: constant(0.0),
+            transmission: constant(0.0),
+            subsurface: constant(0.0),
+            anisotropy: 0.0,
+            refraction_index: 1.5,
+        }
+    }
+}
+
+// The parameters looked up at one point on the surface.
+struct PrincipledPoint {
+    base_colour: Colour,
+    metallic: f64,
+    roughness: f64,
+    specular: f64,
+    sheen: f64,
+    clearcoat: f64,
+    transmission: f64,
+    subsurface: f64,
+    microfacet: Microfacet,
+    eta: f64,
+}
+
+impl PrincipledPoint {
+    fn diffuse_weight(&self) -> f64 {
+        (1.0 - self.metallic) * (1.0 - self.transmission)
+    }
+
+    fn transmission_weight(&self) -> f64 {
+        (1.0 - self.metallic) * self.transmission
+    }
+
+    // The chances of sampling the diffuse, specular, transmission and clear coat lobes.
+    fn lobe_probabilities(&self) -> [f64; 4] {
+        let weights = [
+            self.diffuse_weight(),
+            1.0 - self.transmission_weight(),
+            self.transmission_weight(),
+            0.25 * self.clearcoat,
+        ];
+        let total: f64 = weights.iter().sum();
+        [weights[0] / total, weights[1] / total, weights[2] / total, weights[3] / total]
+    }
+}
+
+fn schlick_weight(cosine: f64) -> f64 {
+    (1.0 - cosine.clamp(0.0, 1.0)).powi(5)
+}
+
+// A single material covering most opaque and transparent surfaces, after the principled BSDF
+// Burley presented for Disney (2012, 2015): a diffuse base with retro-reflection, a subsurface
+// approximation and sheen; a GGX specular lobe that turns metallic; a rough dielectric for
+// transmission; and a clear coat on top. Each lobe is sampled in proportion to its rough
+// share of the reflected light, and the density of a direction combines them all.
+pub struct Principled {
+    parameters: PrincipledParameters,
+}
+
+impl Principled {
+    pub fn new(base_colour: Colour) -> Self {
+        Self::with_parameters(PrincipledParameters {
+            base_colour: Arc::new(SolidColour::new(base_colour)),
+            ..PrincipledParameters::default()
+        })
+    }
+
+    pub fn with_parameters(parameters: PrincipledParameters) -> Self {
+        Self { parameters }
+    }
+
+    fn point(&self, hit_record: &HitRecord) -> PrincipledPoint {
+        let (u, v, p) = (hit_record.u, hit_record.v, hit_record.hit_point);
+        let scalar = |texture: &Arc<dyn Texture>| texture.scalar(u, v, p).clamp(0.0, 1.0);
+        let parameters = &self.parameters;
+        let roughness = scalar(&parameters.roughness);
+        PrincipledPoint {
+            base_colour: parameters.base_colour.value(u, v, p),
+            metallic: scalar(&parameters.metallic),
+            roughness,
+            specular: scalar(&parameters.specular),
+            sheen: scalar(&parameters.sheen),
+            clearcoat: scalar(&parameters.clearcoat),
+            transmission: scalar(&parameters.transmission),
+            subsurface: scalar(&parameters.subsurface),
+            microfacet: Microfacet::new(roughness, parameters.anisotropy),
+            eta: relative_eta(parameters.refraction_index, hit_record),
+        }
+    }
+
+    // The BSDF times the cosine term and the sampling density of `wi`, in the shading frame.
+    fn evaluate(&self, point: &PrincipledPoint, wo: Vec3, wi: Vec3) -> (Colour, f64) {
+        let probabilities = point.lobe_probabilities();
+        let mut value = Colour::new(0.0, 0.0, 0.0);
+        let mut pdf = 0.0;
+
+        if wo.z() > 0.0 && wi.z() > 0.0 {
+            let (cos_l, cos_v) = (wi.z(), wo.z());
+            let h = (wo + wi).unit_vector();
+            let cos_d = wi.dot(&h);
+            let (fl, fv, fd) = (schlick_weight(cos_l), schlick_weight(cos_v), schlick_weight(cos_d));
+
+            // Diffuse, brightened at grazing angles on rough surfaces, blended with the
+            // flatter subsurface approximation, plus sheen tinted halfway to the base colour.
+            let fd90 = 0.5 + 2.0 * point.roughness * cos_d * cos_d;
+            let diffuse = (1.0 + (fd90 - 1.0) * fl) * (1.0 + (fd90 - 1.0) * fv);
+            let fss90 = point.roughness * cos_d * cos_d;
+            let fss = (1.0 + (fss90 - 1.0) * fl) * (1.0 + (fss90 - 1.0) * fv);
+            let subsurface = 1.25 * (fss * (1.0 / (cos_l + cos_v) - 0.5) + 0.5);
+            let luminance = 0.2126 * point.base_colour.x() + 0.7152 * point.base_colour.y() + 0.0722 * point.base_colour.z();
+            let tint = if luminance > 0.0 {
+                point.base_colour / luminance
+            } else {
+                Colour::new(1.0, 1.0, 1.0)
+            };
+            let sheen = point.sheen * fd * 0.5 * (Colour::new(1.0, 1.0, 1.0) + tint);
+            let lambert = (1.0 - point.subsurface) * diffuse + point.subsurface * subsurface;
+            value += point.diffuse_weight() * (lambert * point.base_colour / PI + sheen) * cos_l;
+            pdf += probabilities[0] * cos_l / PI;
+
+            if wo.dot(&h) > 0.0 {
+                let microfacet = &point.microfacet;
+                let white = Colour::new(1.0, 1.0, 1.0);
+                let f0 = (1.0 - point.metallic) * 0.08 * point.specular * white + point.metallic * point.base_colour;
+                let fresnel = f0 + fd * (white - f0);
+                let specular = microfacet.distribution(h) * microfacet.masking_shadowing(wo, wi) / (4.0 * cos_v);
+                value += (1.0 - point.transmission_weight()) * specular * fresnel;
+                pdf += probabilities[1] * microfacet.visible_pdf(wo, h) / (4.0 * wo.dot(&h));
+
+                let coat = Microfacet::new(CLEARCOAT_ROUGHNESS, 0.0);
+                let coat_fresnel = 0.04 + 0.96 * fd;
+                let coat_value = coat.distribution(h) * coat.masking_shadowing(wo, wi) / (4.0 * cos_v);
+                value += 0.25 * point.clearcoat * coat_fresnel * coat_value * white;
+                pdf += probabilities[3] * coat.visible_pdf(wo, h) / (4.0 * wo.dot(&h));
+            }
+        }
+
+        if probabilities[2] > 0.0 {
+            let (transmission, transmission_pdf) = evaluate_dielectric(&point.microfacet, wo, wi, point.eta);
+            // Light passing into and back out of a solid crosses the surface twice.
+            let tint = if wi.z() < 0.0 {
+                Colour::new(point.base_colour.x().sqrt(), point.base_colour.y().sqrt(), point.base_colour.z().sqrt())
+            } else {
+                Colour::new(1.0, 1.0, 1.0)
+            };
+            value += point.transmission_weight() * transmission * tint;
+            pdf += probabilities[2] * transmission_pdf;
+        }
+
+        (value, pdf)
+    }
+}
+
+impl Material for Principled {
+    fn scatter(&self, incident_ray: &Ray, hit_record: &HitRecord) -> Option<ScatteredRay> {
+        let frame = Onb::with_tangent(hit_record.normal, hit_record.tangent);
+        let wo = frame.project(-incident_ray.direction.unit_vector());
+        let point = self.point(hit_record);
+
+        let probabilities = point.lobe_probabilities();
+        let choice = rand(0.0, 1.0);
+        let wi = if choice < probabilities[0] {
+            let direction = Vec3::new(0.0, 0.0, 1.0) + random_unit_vector();
+            if direction.near_zero() {
+                Vec3::new(0.0, 0.0, 1.0)
+            } else {
+                direction.unit_vector()
+            }
+        } else if choice < probabilities[0] + probabilities[1] {
+            reflect_about(wo, point.microfacet.sample_visible(wo))
+        } else if choice < probabilities[0] + probabilities[1] + probabilities[2] {
+            sample_dielectric(&point.microfacet, wo, point.eta)?
+        } else {
+            reflect_about(wo, Microfacet::new(CLEARCOAT_ROUGHNESS, 0.0).sample_visible(wo))
+        };
+
+        let (value, pdf) = self.evaluate(&point, wo, wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(ScatteredRay {
+            attenuation: value / pdf,
+            ray: Ray::with_time(hit_record.hit_point, frame.local(wi.x(), wi.y(), wi.z()), incident_ray.time),
+            pdf: Some(pdf),
+        })
+    }
+
+    fn eval(&self, incident_ray: &Ray, hit_record: &HitRecord, direction: Vec3) -> Colour {
+        let frame = Onb::with_tangent(hit_record.normal, hit_record.tangent);
+        let wo = frame.project(-incident_ray.direction.unit_vector());
+        self.evaluate(&self.point(hit_record), wo, frame.project(direction.unit_vector())).0
+    }
+
+    fn pdf(&self, incident_ray: &Ray, hit_record: &HitRecord, direction: Vec3) -> f64 {
+        let frame = Onb::with_tangent(hit_record.normal, hit_record.tangent);
+        let wo = frame.project(-incident_ray.direction.unit_vector());
+        self.evaluate(&self.point(hit_record), wo, frame.project(direction.unit_vector())).1
+    }
+}
+
+pub struct DiffuseLight {
+    emit: Arc<dyn Texture>,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Colour) -> Self {
+        Self::with_texture(Arc::new(SolidColour::new(emit)))
+    }
+
+    pub fn with_texture(emit: Arc<dyn Texture>) -> Self {
+        Self { emit }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, _incident_ray: &Ray, _hit_record: &HitRecord) -> Option<ScatteredRay> {
+        None
+    }
+
+    fn emitted(&self, hit_record: &HitRecord) -> Colour {
+        self.emit.value(hit_record.u, hit_record.v, hit_record.hit_point)
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
+}
+
+// Scatters uniformly in all directions; the phase function of a participating medium.
+pub struct Isotropic {
+    albedo: Arc<dyn Texture>,
+}
+
+impl Isotropic {
+    pub fn new(albedo: Colour) -> Self {
+        Self::with_texture(Arc::new(SolidColour::new(albedo)))
+    }
+
+    pub fn with_texture(albedo: Arc<dyn Texture>) -> Self {
+        Self { albedo }
+    }
+}
+
+impl Material for Isotropic {
+    fn scatter(&self, incident_ray: &Ray, hit_record: &HitRecord) -> Option<ScatteredRay> {
+        Some(ScatteredRay {
+            attenuation: self.albedo.value(hit_record.u, hit_record.v, hit_record.hit_point),
+            ray: Ray::with_time(hit_record.hit_point, random_unit_vector(), incident_ray.time),
+            pdf: Some(1.0 / (4.0 * PI)),
+        })
+    }
+
+    fn eval(&self, _incident_ray: &Ray, hit_record: &HitRecord, _direction: Vec3) -> Colour {
+        self.albedo.value(hit_record.u, hit_record.v, hit_record.hit_point) / (4.0 * PI)
+    }
+
+    fn pdf(&self, _incident_ray: &Ray, _hit_record: &HitRecord, _direction: Vec3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::CheckerTexture;
+    use crate::Point;
+
+    // A hit on the floor at the origin by a ray coming down at 45 degrees.
+    fn floor_hit(material: Arc<dyn Material>) -> (Ray, HitRecord) {
+        let ray = Ray::new(Vec3::new(-1.0, 1.0, 0.0), Vec3::new(1.0, -1.0, 0.0));
+        let record = HitRecord::new(1.0, ray, Vec3::new(0.0, 1.0, 0.0), material);
+        (ray, record)
+    }
+
+    // The same hit, but from beneath the floor, as when leaving a solid.
+    fn floor_hit_from_below(material: Arc<dyn Material>) -> (Ray, HitRecord) {
+        let ray = Ray::new(Vec3::new(-1.0, -1.0, 0.0), Vec3::new(1.0, 1.0, 0.0));
+        let record = HitRecord::new(1.0, ray, Vec3::new(0.0, 1.0, 0.0), material);
+        (ray, record)
+    }
+
+    mod bsdf_tests {
+        use super::*;
+
+        // Every sampled direction must carry the density `pdf` reports for it and the weight
+        // `eval / pdf`, or the integrator would weight light and BSDF samples inconsistently.
+        fn check_consistent(material: Arc<dyn Material>) {
+            crate::random::seed(42);
+            for (ray, record) in [floor_hit(material.clone()), floor_hit_from_below(material.clone())] {
+                for _ in 0..200 {
+                    let Some(scattered) = material.scatter(&ray, &record) else {
+                        continue;
+                    };
+                    let direction = scattered.ray.direction;
+                    let pdf = scattered.pdf.unwrap();
+                    assert!(approx_eq(material.pdf(&ray, &record, direction), pdf, 1e-9 * pdf.max(1.0)));
+                    let expected = material.eval(&ray, &record, direction) / pdf;
+                    assert!(approx_eq(scattered.attenuation.y(), expected.y(), 1e-9));
+                }
+            }
+        }
+
+        #[test]
+        fn sampling_matches_evaluation() {
+            check_consistent(Arc::new(Lambertian::new(Colour::new(0.5, 0.6, 0.7))));
+            check_consistent(Arc::new(Metal::new(Colour::new(0.9, 0.8, 0.7), 0.3)));
+            check_consistent(Arc::new(Metal::new(Colour::new(0.9, 0.8, 0.7), 1.0)));
+            check_consistent(Arc::new(Isotropic::new(Colour::new(0.5, 0.5, 0.5))));
+
+            let gold = (Colour::new(0.18, 0.42, 1.37), Colour::new(3.42, 2.35, 1.77));
+            check_consistent(Arc::new(Conductor::new(gold.0, gold.1, 0.5, 0.0)));
+            check_consistent(Arc::new(Conductor::new(gold.0, gold.1, 0.3, 0.8)));
+            check_consistent(Arc::new(RoughDielectric::new(1.5, 0.4, 0.0)));
+            check_consistent(Arc::new(RoughDielectric::new(1.33, 0.2, 0.6)));
+
+            check_consistent(Arc::new(Principled::new(Colour::new(0.8, 0.3, 0.2))));
+            for principled in principled_variants() {
+                check_consistent(principled);
+            }
+        }
+
+        #[test]
+        fn fuzzy_reflection_density() {
+            // With fuzz below 1 the directions fill a cone of half-angle asin(fuzz) around the
+            // mirror direction, so the mean of 1 / pdf over samples is its solid angle.
+            let material = Metal::new(Colour::new(1.0, 1.0, 1.0), 0.4);
+            let (ray, record) = floor_hit(Arc::new(EmptyMaterial));
+            crate::random::seed(42);
+            let mut total = 0.0;
+            for _ in 0..20000 {
+                // Below-surface samples are absorbed; count them through their density too.
+                let direction = reflect(ray.direction.unit_vector(), record.normal) + 0.4 * random_unit_vector();
+                total += 1.0 / material.pdf(&ray, &record, direction);
+            }
+            let expected = 2.0 * PI * (1.0 - (1.0f64 - 0.16).sqrt());
+
+            assert!(approx_eq(total / 20000.0, expected, 0.01 * expected));
+            assert_eq!(material.pdf(&ray, &record, Vec3::new(0.0, 1.0, 0.0)), 0.0);
+        }
+
+        #[test]
+        fn specular_materials() {
+            for material in [
+                Arc::new(Metal::new(Colour::new(1.0, 1.0, 1.0), 0.0)) as Arc<dyn Material>,
+                Arc::new(Dielectric::new(1.5)),
+                Arc::new(Conductor::new(Colour::new(0.2, 0.9, 1.1), Colour::new(3.9, 2.4, 2.2), 0.0, 0.0)),
+                Arc::new(RoughDielectric::new(1.5, 0.0, 0.0)),
+            ] {
+                let (ray, record) = floor_hit(material.clone());
+                let scattered = material.scatter(&ray, &record).unwrap();
+                assert!(scattered.pdf.is_none());
+                assert!(material.eval(&ray, &record, scattered.ray.direction).near_zero());
+                assert_eq!(material.pdf(&ray, &record, scattered.ray.direction), 0.0);
+            }
+        }
+    }
+
+    // Principled materials exercising every lobe.
+    fn principled_variants() -> Vec<Arc<dyn Material>> {
+        let base_colour = || Arc::new(SolidColour::new(Colour::new(0.9, 0.6, 0.3))) as Arc<dyn Texture>;
+        vec![
+            Arc::new(Principled::with_parameters(PrincipledParameters {
+                base_colour: base_colour(),
+                metallic: constant(1.0),
+                roughness: constant(0.3),
+                anisotropy: 0.6,
+                ..PrincipledParameters::default()
+            })),
+            Arc::new(Principled::with_parameters(PrincipledParameters {
+                base_colour: base_colour(),
+                roughness: constant(0.6),
+                sheen: constant(1.0),
+                subsurface: constant(0.7),
+                clearcoat: constant(1.0),
+                ..PrincipledParameters::default()
+            })),
+            Arc::new(Principled::with_parameters(PrincipledParameters {
+                base_colour: base_colour(),
+                roughness: constant(0.4),
+                metallic: constant(0.2),
+                transmission: constant(0.8),
+                clearcoat: constant(0.5),
+                ..PrincipledParameters::default()
+            })),
+        ]
+    }
+
+    mod microfacet_tests {
+        use super::*;
+
+        // The mean sampling weight over many directions is the fraction of light scattered,
+        // counting absorbed samples as zero.
+        fn albedo(material: &dyn Material, ray: Ray, record: &HitRecord, eta: f64) -> f64 {
+            crate::random::seed(42);
+            let total: f64 = (0..20000)
+                .filter_map(|_| material.scatter(&ray, record))
+                .map(|scattered| {
+                    // Undo the compression of transmitted radiance to count energy.
+                    let transmitted = scattered.ray.direction.dot(&record.normal) < 0.0;
+                    scattered.attenuation.y() * if transmitted { eta * eta } else { 1.0 }
+                })
+                .sum();
+            total / 20000.0
+        }
+
+        #[test]
+        fn conserves_energy() {
+            // A perfect reflector and clear glass lose only the light that single-scattering
+            // microfacet models miss, which stays small at moderate roughness.
+            let mirror = Conductor::new(Colour::new(1.0, 1.0, 1.0), Colour::new(1e4, 1e4, 1e4), 0.4, 0.0);
+            let (ray, record) = floor_hit(Arc::new(EmptyMaterial));
+            let reflected = albedo(&mirror, ray, &record, 1.0);
+            assert!(reflected <= 1.0 && reflected > 0.9, "{}", reflected);
+
+            let glass = RoughDielectric::new(1.5, 0.4, 0.0);
+            let scattered = albedo(&glass, ray, &record, 1.5);
+            assert!(scattered <= 1.0 + 1e-9 && scattered > 0.9, "{}", scattered);
+            let (ray, record) = floor_hit_from_below(Arc::new(EmptyMaterial));
+            let scattered = albedo(&glass, ray, &record, 1.0 / 1.5);
+            assert!(scattered <= 1.0 + 1e-9 && scattered > 0.9, "{}", scattered);
+        }
+
+        #[test]
+        fn rough_glass_is_a_medium() {
+            let absorption = Colour::new(0.2, 0.1, 0.1);
+            let glass = RoughDielectric::with_absorption(1.5, constant(0.3), 0.0, absorption, 2);
+            let expected = Medium {
+                refraction_index: 1.5,
+                absorption,
+                priority: 2,
+            };
+            assert_eq!(glass.medium(), Some(expected));
+            assert_eq!(RoughDielectric::new(1.5, 0.3, 0.0).medium().unwrap().priority, 0);
+        }
+
+        #[test]
+        fn sampled_directions_follow_pdf() {
+            // The share of samples landing in each band of directions must match the integral
+            // of the reported density over the band, found here on a fine grid.
+            let materials = [
+                Arc::new(Conductor::new(Colour::new(1.0, 1.0, 1.0), Colour::new(1e4, 1e4, 1e4), 0.5, 0.7)) as Arc<dyn Material>,
+                Arc::new(RoughDielectric::new(1.5, 0.5, 0.3)),
+                principled_variants().remove(2),
+            ];
+            let band = |direction: Vec3| ((direction.unit_vector().y() + 1.0) * 2.0).min(3.0) as usize;
+            for material in materials {
+                for (ray, record) in [floor_hit(material.clone()), floor_hit_from_below(material.clone())] {
+                    crate::random::seed(42);
+                    let mut sampled = [0.0; 4];
+                    for _ in 0..100000 {
+                        if let Some(scattered) = material.scatter(&ray, &record) {
+                            sampled[band(scattered.ray.direction)] += 1e-5;
+                        }
+                    }
+
+                    // Equal steps in y and in angle around it cover equal solid angles.
+                    let (rings, segments) = (400, 800);
+                    let cell = 4.0 * PI / (rings * segments) as f64;
+                    let mut integrated = [0.0; 4];
+                    for i in 0..rings {
+                        let y = -1.0 + 2.0 * (i as f64 + 0.5) / rings as f64;
+                        for j in 0..segments {
+                            let phi = 2.0 * PI * (j as f64 + 0.5) / segments as f64;
+                            let r = (1.0 - y * y).sqrt();
+                            let direction = Vec3::new(r * phi.cos(), y, r * phi.sin());
+                            integrated[band(direction)] += material.pdf(&ray, &record, direction) * cell;
+                        }
+                    }
+
+                    for (sampled, integrated) in sampled.iter().zip(integrated) {
+                        assert!(approx_eq(*sampled, integrated, 0.01), "{:?} {:?}", sampled, integrated);
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn principled_limits() {
+            // A white metal reflects almost everything, like the equivalent conductor. A rough
+            // white dielectric stays close to unity, though the principled diffuse lobe is not
+            // strictly energy conserving.
+            let (ray, record) = floor_hit(Arc::new(EmptyMaterial));
+            let metal = Principled::with_parameters(PrincipledParameters {
+                base_colour: constant(1.0),
+                metallic: constant(1.0),
+                roughness: constant(0.4),
+                ..PrincipledParameters::default()
+            });
+            let reflected = albedo(&metal, ray, &record, 1.0);
+            assert!(reflected <= 1.0 && reflected > 0.9, "{}", reflected);
+
+            let plastic = Principled::with_parameters(PrincipledParameters {
+                base_colour: constant(1.0),
+                roughness: constant(1.0),
+                ..PrincipledParameters::default()
+            });
+            let reflected = albedo(&plastic, ray, &record, 1.0);
+            assert!(reflected < 1.1 && reflected > 0.9, "{}", reflected);
+
+            // A black dielectric shows only its specular reflection.
+            let black = Principled::new(Colour::new(0.0, 0.0, 0.0));
+            let reflected = albedo(&black, ray, &record, 1.0);
+            assert!(reflected > 0.01 && reflected < 0.1, "{}", reflected);
+        }
+
+        #[test]
+        fn principled_parameters_follow_textures() {
+            // Metallic comes from a checkerboard: metal on even cells, plastic on odd ones.
+            let principled = Principled::with_parameters(PrincipledParameters {
+                base_colour: Arc::new(SolidColour::new(Colour::new(1.0, 0.0, 0.0))),
+                metallic: Arc::new(CheckerTexture::new(constant(1.0), constant(0.0), 1.0)),
+                roughness: constant(0.2),
+                ..PrincipledParameters::default()
+            });
+            let (ray, mut record) = floor_hit(Arc::new(EmptyMaterial));
+            let mirror = Vec3::new(1.0, 1.0, 0.0);
+
+            record.hit_point = Point::new(0.5, 0.5, 0.5);
+            let metal = principled.eval(&ray, &record, mirror);
+            record.hit_point = Point::new(1.5, 0.5, 0.5);
+            let plastic = principled.eval(&ray, &record, mirror);
+
+            // Red metal reflects red light, but for a trace of white at grazing angles; red
+            // plastic has a white highlight.
+            assert!(metal.x() > 1.0 && metal.y() < 0.01 * metal.x());
+            assert!(plastic.y() > 0.1 && plastic.x() > plastic.y());
+        }
+
+        #[test]
+        fn anisotropy_stretches_the_lobe() {
+            // Seen head on, the reflected directions spread further along the shading frame's
+            // u axis, which for an upward normal is world x, than across it.
+            let material = Conductor::new(Colour::new(1.0, 1.0, 1.0), Colour::new(1e4, 1e4, 1e4), 0.3, 0.9);
+            let ray = Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+            let record = HitRecord::new(1.0, ray, Vec3::new(0.0, 1.0, 0.0), Arc::new(EmptyMaterial));
+            crate::random::seed(42);
+            let (mut spread_x, mut spread_z) = (0.0, 0.0);
+            for _ in 0..2000 {
+                if let Some(scattered) = material.scatter(&ray, &record) {
+                    let direction = scattered.ray.direction.unit_vector();
+                    spread_x += direction.x() * direction.x();
+                    spread_z += direction.z() * direction.z();
+                }
+            }
+            assert!(spread_x > 4.0 * spread_z);
+        }
+
+        #[test]
+        fn fresnel() {
+            // A conductor without absorption reflects like a dielectric of the same index.
+            for cosine in [0.1, 0.5, 0.9, 1.0] {
+                let conductor = fresnel_conductor(cosine, Colour::new(1.5, 1.5, 1.5), Colour::new(0.0, 0.0, 0.0));
+                assert!(approx_eq(conductor.x(), fresnel_dielectric(cosine, 1.5), 1e-12));
+            }
+            assert!(approx_eq(fresnel_dielectric(1.0, 1.5), 0.04, 1e-12));
+            assert_eq!(fresnel_dielectric(0.2, 1.0 / 1.5), 1.0);
+        }
+    }
+
+    fn approx_eq(x: f64, y: f64, tolerance: f64) -> bool {
+        (x - y).abs() < tolerance
+    }
 }
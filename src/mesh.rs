@@ -0,0 +1,373 @@
+use crate::geometry::{Aabb, HitRecord, Hittable, HittableList};
+use crate::linear_algebra::{Ray, Vec3};
+use crate::materials::Material;
+use crate::random::rand;
+use crate::Point;
+use std::sync::Arc;
+
+#[derive(Debug, Copy, Clone)]
+pub struct MeshFace {
+    pub positions: [usize; 3],
+    pub normals: Option<[usize; 3]>,
+    pub uvs: Option<[usize; 3]>,
+}
+
+impl MeshFace {
+    pub fn new(positions: [usize; 3]) -> Self {
+        Self {
+            positions,
+            normals: None,
+            uvs: None,
+        }
+    }
+}
+
+pub struct TriangleMesh {
+    positions: Vec<Point>,
+    normals: Vec<Vec3>,
+    uvs: Vec<(f64, f64)>,
+    faces: Vec<MeshFace>,
+    material: Arc<dyn Material>,
+}
+
+impl TriangleMesh {
+    pub fn new(
+        positions: Vec<Point>,
+        normals: Vec<Vec3>,
+        uvs: Vec<(f64, f64)>,
+        faces: Vec<MeshFace>,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        Self {
+            positions,
+            normals,
+            uvs,
+            faces,
+            material,
+        }
+    }
+
+    pub fn positions(&self) -> &[Point] {
+        &self.positions
+    }
+
+    pub fn normals(&self) -> &[Vec3] {
+        &self.normals
+    }
+
+    pub fn uvs(&self) -> &[(f64, f64)] {
+        &self.uvs
+    }
+
+    pub fn faces(&self) -> &[MeshFace] {
+        &self.faces
+    }
+
+    pub fn material(&self) -> &Arc<dyn Material> {
+        &self.material
+    }
+
+    pub fn triangles(self: &Arc<Self>) -> HittableList {
+        let mut list = HittableList::new();
+        for face in 0..self.faces.len() {
+            list.add(Arc::new(Triangle {
+                mesh: self.clone(),
+                face,
+            }));
+        }
+        list
+    }
+
+    fn vertices(&self, face: &MeshFace) -> [Point; 3] {
+        [
+            self.positions[face.positions[0]],
+            self.positions[face.positions[1]],
+            self.positions[face.positions[2]],
+        ]
+    }
+}
+
+pub struct Triangle {
+    mesh: Arc<TriangleMesh>,
+    face: usize,
+}
+
+impl Triangle {
+    pub fn new(a: Point, b: Point, c: Point, material: Arc<dyn Material>) -> Self {
+        let mesh = TriangleMesh::new(
+            vec![a, b, c],
+            Vec::new(),
+            Vec::new(),
+            vec![MeshFace::new([0, 1, 2])],
+            material,
+        );
+        Self {
+            mesh: Arc::new(mesh),
+            face: 0,
+        }
+    }
+
+    pub fn with_normals(a: (Point, Vec3), b: (Point, Vec3), c: (Point, Vec3), material: Arc<dyn Material>) -> Self {
+        let mesh = TriangleMesh::new(
+            vec![a.0, b.0, c.0],
+            vec![a.1, b.1, c.1],
+            Vec::new(),
+            vec![MeshFace {
+                positions: [0, 1, 2],
+                normals: Some([0, 1, 2]),
+                uvs: None,
+            }],
+            material,
+        );
+        Self {
+            mesh: Arc::new(mesh),
+            face: 0,
+        }
+    }
+}
+
+impl Hittable for Triangle {
+    // Moller-Trumbore intersection, solving for t and the barycentric coordinates of the
+    // second and third vertices at once.
+    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let face = &self.mesh.faces[self.face];
+        let [p0, p1, p2] = self.mesh.vertices(face);
+
+        let edge1 = p1 - p0;
+        let edge2 = p2 - p0;
+        let p = ray.direction.cross(&edge2);
+        let determinant = edge1.dot(&p);
+
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+
+        let inverse_determinant = 1.0 / determinant;
+        let s = ray.origin - p0;
+        let b1 = s.dot(&p) * inverse_determinant;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+
+        let q = s.cross(&edge1);
+        let b2 = ray.direction.dot(&q) * inverse_determinant;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+
+        let t = edge2.dot(&q) * inverse_determinant;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        let mut geometric_normal = edge1.cross(&edge2).unit_vector();
+        let shading_normal = face.normals.map(|[n0, n1, n2]| {
+            let b0 = 1.0 - b1 - b2;
+            (b0 * self.mesh.normals[n0] + b1 * self.mesh.normals[n1] + b2 * self.mesh.normals[n2])
+                .unit_vector()
+        });
+
+        // Authored vertex normals are more reliable than winding order for telling outside
+        // from inside, so the geometric normal follows them.
+        if let Some(shading_normal) = shading_normal {
+            if shading_normal.dot(&geometric_normal) < 0.0 {
+                geometric_normal = -geometric_normal;
+            }
+        }
+
+        let mut record = HitRecord::new(t, ray, geometric_normal, self.mesh.material.clone());
+        record.barycentric = Some((b1, b2));
+        // Without authored texture coordinates, the barycentrics stand in for them.
+        (record.u, record.v) = match face.uvs {
+            Some([t0, t1, t2]) => {
+                let b0 = 1.0 - b1 - b2;
+                let [uv0, uv1, uv2] = [self.mesh.uvs[t0], self.mesh.uvs[t1], self.mesh.uvs[t2]];
+                // dp/du, from the edges expressed in texture space.
+                let (du1, dv1) = (uv1.0 - uv0.0, uv1.1 - uv0.1);
+                let (du2, dv2) = (uv2.0 - uv0.0, uv2.1 - uv0.1);
+                let uv_determinant = du1 * dv2 - du2 * dv1;
+                record.tangent = if uv_determinant.abs() < 1e-12 {
+                    edge1
+                } else {
+                    (dv2 * edge1 - dv1 * edge2) / uv_determinant
+                };
+                (
+                    b0 * uv0.0 + b1 * uv1.0 + b2 * uv2.0,
+                    b0 * uv0.1 + b1 * uv1.1 + b2 * uv2.1,
+                )
+            }
+            None => {
+                record.tangent = edge1;
+                (b1, b2)
+            }
+        };
+        if let Some(shading_normal) = shading_normal {
+            record.set_shading_normal(shading_normal);
+        }
+
+        Some(record)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let [p0, p1, p2] = self.mesh.vertices(&self.mesh.faces[self.face]);
+        Some(Aabb::new(p0, p0).include(p1).include(p2))
+    }
+
+    fn random_direction(&self, origin: Point, _time: f64) -> Option<Vec3> {
+        let [p0, p1, p2] = self.mesh.vertices(&self.mesh.faces[self.face]);
+        // Folding the unit square onto the triangle keeps the points uniform over its area.
+        let s = rand(0.0, 1.0).sqrt();
+        let r = rand(0.0, 1.0);
+        let point = (1.0 - s) * p0 + s * (1.0 - r) * p1 + s * r * p2;
+        Some(point - origin)
+    }
+
+    fn pdf_value(&self, origin: Point, direction: Vec3, time: f64) -> f64 {
+        let Some(record) = self.hit(Ray::with_time(origin, direction, time), 0.001, f64::INFINITY) else {
+            return 0.0;
+        };
+        let [p0, p1, p2] = self.mesh.vertices(&self.mesh.faces[self.face]);
+        let normal = (p1 - p0).cross(&(p2 - p0));
+        let area = 0.5 * normal.length();
+
+        // The geometric normal, as smooth shading does not change the triangle's extent.
+        let cosine = (direction.dot(&normal) / (direction.length() * normal.length())).abs();
+        if cosine < 1e-9 {
+            return 0.0;
+        }
+        record.t * record.t * direction.length_squared() / (cosine * area)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::EmptyMaterial;
+
+    mod triangle_tests {
+        use super::*;
+
+        fn unit_triangle() -> Triangle {
+            Triangle::new(
+                Point::new(0.0, 0.0, -1.0),
+                Point::new(1.0, 0.0, -1.0),
+                Point::new(0.0, 1.0, -1.0),
+                Arc::new(EmptyMaterial),
+            )
+        }
+
+        #[test]
+        fn hit() {
+            let triangle = unit_triangle();
+            let hit_ray = Ray::new(Vec3::new(0.25, 0.5, 0.0), Vec3::new(0.0, 0.0, -1.0));
+            let miss_ray = Ray::new(Vec3::new(0.75, 0.75, 0.0), Vec3::new(0.0, 0.0, -1.0));
+            let back_ray = Ray::new(Vec3::new(0.25, 0.5, -2.0), Vec3::new(0.0, 0.0, 1.0));
+
+            assert!(triangle.hit(miss_ray, 0.0, 100.0).is_none());
+            assert!(triangle.hit(hit_ray, 0.0, 0.5).is_none());
+
+            let record = triangle.hit(hit_ray, 0.0, 100.0).unwrap();
+            let (b1, b2) = record.barycentric.unwrap();
+            assert!(approx_eq(record.t, 1.0, 1e-12));
+            assert!(approx_eq(b1, 0.25, 1e-12));
+            assert!(approx_eq(b2, 0.5, 1e-12));
+            assert!(record.front_face);
+            assert!(approx_eq(record.normal.z(), 1.0, 1e-12));
+
+            let back_record = triangle.hit(back_ray, 0.0, 100.0).unwrap();
+            assert!(!back_record.front_face);
+            assert!(approx_eq(back_record.normal.z(), -1.0, 1e-12));
+        }
+
+        #[test]
+        fn smooth_shading() {
+            let n = Vec3::new(0.0, 0.0, 1.0);
+            let tilted = Vec3::new(1.0, 0.0, 1.0).unit_vector();
+            let triangle = Triangle::with_normals(
+                (Point::new(0.0, 0.0, -1.0), n),
+                (Point::new(1.0, 0.0, -1.0), tilted),
+                (Point::new(0.0, 1.0, -1.0), n),
+                Arc::new(EmptyMaterial),
+            );
+            let ray = Ray::new(Vec3::new(0.5, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+            let record = triangle.hit(ray, 0.0, 100.0).unwrap();
+
+            assert!(record.normal.x() > 0.0);
+            assert!(approx_eq(record.normal.length(), 1.0, 1e-12));
+        }
+    }
+
+    mod triangle_mesh_tests {
+        use super::*;
+
+        #[test]
+        fn shares_vertices() {
+            let positions = vec![
+                Point::new(0.0, 0.0, -1.0),
+                Point::new(1.0, 0.0, -1.0),
+                Point::new(1.0, 1.0, -1.0),
+                Point::new(0.0, 1.0, -1.0),
+            ];
+            let faces = vec![MeshFace::new([0, 1, 2]), MeshFace::new([0, 2, 3])];
+            let mesh = Arc::new(TriangleMesh::new(
+                positions,
+                Vec::new(),
+                Vec::new(),
+                faces,
+                Arc::new(EmptyMaterial),
+            ));
+            let triangles = mesh.triangles();
+            let ray = Ray::new(Vec3::new(0.25, 0.75, 0.0), Vec3::new(0.0, 0.0, -1.0));
+
+            assert_eq!(triangles.len(), 2);
+            assert_eq!(mesh.positions().len(), 4);
+            assert!(approx_eq(triangles.hit(ray, 0.0, 100.0).unwrap().t, 1.0, 1e-12));
+
+            let bounding_box = triangles.bounding_box().unwrap();
+            assert!(approx_eq(bounding_box.max.x(), 1.0, f64::EPSILON));
+            assert!(approx_eq(bounding_box.max.y(), 1.0, f64::EPSILON));
+        }
+
+        #[test]
+        fn texture_coordinates() {
+            let positions = vec![
+                Point::new(0.0, 0.0, -1.0),
+                Point::new(1.0, 0.0, -1.0),
+                Point::new(0.0, 1.0, -1.0),
+            ];
+            let uvs = vec![(0.5, 0.5), (1.0, 0.5), (0.5, 1.0)];
+            let mut face = MeshFace::new([0, 1, 2]);
+            face.uvs = Some([0, 1, 2]);
+            let mesh = Arc::new(TriangleMesh::new(positions, Vec::new(), uvs, vec![face], Arc::new(EmptyMaterial)));
+            let ray = Ray::new(Vec3::new(0.25, 0.5, 0.0), Vec3::new(0.0, 0.0, -1.0));
+            let record = mesh.triangles().hit(ray, 0.0, 100.0).unwrap();
+
+            assert!(approx_eq(record.u, 0.625, 1e-12));
+            assert!(approx_eq(record.v, 0.75, 1e-12));
+        }
+
+        #[test]
+        fn tangent_follows_texture_coordinates() {
+            let positions = vec![
+                Point::new(0.0, 0.0, -1.0),
+                Point::new(1.0, 0.0, -1.0),
+                Point::new(0.0, 1.0, -1.0),
+            ];
+            // u increases along y and v along x.
+            let uvs = vec![(0.0, 0.0), (0.0, 1.0), (1.0, 0.0)];
+            let mut face = MeshFace::new([0, 1, 2]);
+            face.uvs = Some([0, 1, 2]);
+            let mesh = Arc::new(TriangleMesh::new(positions, Vec::new(), uvs, vec![face], Arc::new(EmptyMaterial)));
+            let ray = Ray::new(Vec3::new(0.25, 0.25, 0.0), Vec3::new(0.0, 0.0, -1.0));
+            let record = mesh.triangles().hit(ray, 0.0, 100.0).unwrap();
+
+            assert!(approx_eq(record.tangent.x(), 0.0, 1e-12));
+            assert!(approx_eq(record.tangent.y(), 1.0, 1e-12));
+            assert!(approx_eq(record.tangent.z(), 0.0, 1e-12));
+        }
+    }
+
+    fn approx_eq(x: f64, y: f64, tolerance: f64) -> bool {
+        (x - y).abs() < tolerance
+    }
+}
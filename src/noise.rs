@@ -0,0 +1,148 @@
+use crate::linear_algebra::Vec3;
+use crate::Point;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+const POINT_COUNT: usize = 256;
+
+// Perlin gradient noise. The lattice is generated from its own seed rather than the shared
+// render RNG, so procedural textures look the same on every run and on every thread.
+pub struct Perlin {
+    gradients: Vec<Vec3>,
+    permutations: [Vec<usize>; 3],
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let gradients = (0..POINT_COUNT)
+            .map(|_| loop {
+                let v = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+                let length_squared = v.length_squared();
+                if length_squared > 1e-6 && length_squared <= 1.0 {
+                    break v.unit_vector();
+                }
+            })
+            .collect();
+        let mut permutation = || {
+            let mut p: Vec<usize> = (0..POINT_COUNT).collect();
+            for i in (1..POINT_COUNT).rev() {
+                p.swap(i, rng.gen_range(0..=i));
+            }
+            p
+        };
+        let permutations = [permutation(), permutation(), permutation()];
+
+        Self { gradients, permutations }
+    }
+
+    // Smoothly varying noise, roughly in [-1, 1], that is zero at every lattice point.
+    pub fn noise(&self, point: Point) -> f64 {
+        let cell = [point.x().floor(), point.y().floor(), point.z().floor()];
+        let fraction = [point.x() - cell[0], point.y() - cell[1], point.z() - cell[2]];
+        let cell = cell.map(|c| c as i64);
+
+        // Hermite smoothing hides the lattice by making the blend weights' slopes vanish at
+        // cell boundaries.
+        let weight = fraction.map(|f| f * f * (3.0 - 2.0 * f));
+        let mut total = 0.0;
+        for corner in 0..8 {
+            let offset = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+            let hash = (0..3).fold(0, |hash, axis| {
+                let index = (cell[axis] + offset[axis] as i64).rem_euclid(POINT_COUNT as i64) as usize;
+                hash ^ self.permutations[axis][index]
+            });
+            let to_point = Vec3::new(
+                fraction[0] - offset[0] as f64,
+                fraction[1] - offset[1] as f64,
+                fraction[2] - offset[2] as f64,
+            );
+            let blend: f64 = (0..3)
+                .map(|axis| {
+                    if offset[axis] == 1 {
+                        weight[axis]
+                    } else {
+                        1.0 - weight[axis]
+                    }
+                })
+                .product();
+            total += blend * self.gradients[hash].dot(&to_point);
+        }
+
+        total
+    }
+
+    // Sum of `octaves` layers of signed noise, each at `lacunarity` times the frequency and
+    // `gain` times the amplitude of the one before.
+    pub fn fbm(&self, point: Point, octaves: usize, lacunarity: f64, gain: f64) -> f64 {
+        let mut total = 0.0;
+        let mut frequency = 1.0;
+        let mut amplitude = 1.0;
+        for _ in 0..octaves {
+            total += amplitude * self.noise(frequency * point);
+            frequency *= lacunarity;
+            amplitude *= gain;
+        }
+        total
+    }
+
+    // Like fbm with the usual doubling of frequency and halving of amplitude, but summing the
+    // absolute value of each octave, which gives the creased look of turbulent flow.
+    pub fn turbulence(&self, point: Point, octaves: usize) -> f64 {
+        let mut total = 0.0;
+        let mut frequency = 1.0;
+        let mut amplitude = 1.0;
+        for _ in 0..octaves {
+            total += amplitude * self.noise(frequency * point).abs();
+            frequency *= 2.0;
+            amplitude *= 0.5;
+        }
+        total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod perlin_tests {
+        use super::*;
+
+        #[test]
+        fn seeded() {
+            let point = Point::new(1.3, -2.7, 0.4);
+            assert_eq!(Perlin::new(7).noise(point), Perlin::new(7).noise(point));
+            assert_ne!(Perlin::new(7).noise(point), Perlin::new(8).noise(point));
+        }
+
+        #[test]
+        fn range_and_continuity() {
+            let perlin = Perlin::new(1);
+            let mut previous = perlin.noise(Point::new(0.0, 0.5, 0.25));
+            let mut varied = false;
+
+            for i in 1..2000 {
+                let point = Point::new(i as f64 * 0.001, 0.5, 0.25);
+                let value = perlin.noise(point);
+                assert!(value.abs() <= 1.0);
+                assert!((value - previous).abs() < 0.01);
+                varied |= (value - previous).abs() > 1e-6;
+                previous = value;
+            }
+            assert!(varied);
+
+            // Zero at lattice points, including negative ones.
+            assert_eq!(perlin.noise(Point::new(-3.0, 2.0, 5.0)), 0.0);
+        }
+
+        #[test]
+        fn octaves() {
+            let perlin = Perlin::new(3);
+            let point = Point::new(0.3, 0.6, 0.9);
+
+            assert!((perlin.fbm(point, 1, 2.0, 0.5) - perlin.noise(point)).abs() < 1e-12);
+            assert!((perlin.turbulence(point, 1) - perlin.noise(point).abs()).abs() < 1e-12);
+            assert!(perlin.turbulence(point, 4) >= perlin.fbm(point, 4, 2.0, 0.5).abs() - 1e-12);
+        }
+    }
+}
@@ -0,0 +1,556 @@
+use crate::geometry::HittableList;
+use crate::linear_algebra::Vec3;
+use crate::materials::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+use crate::mesh::{MeshFace, TriangleMesh};
+use crate::{Colour, Point};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::SplitWhitespace;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct ObjError {
+    pub path: PathBuf,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl ObjError {
+    fn new(path: &Path, line: usize, message: impl Into<String>) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            line: Some(line),
+            message: message.into(),
+        }
+    }
+
+    fn io(path: &Path, error: std::io::Error) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            line: None,
+            message: error.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.path.display(), line, self.message),
+            None => write!(f, "{}: {}", self.path.display(), self.message),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+#[derive(Debug, Clone)]
+pub struct MtlMaterial {
+    pub name: String,
+    pub diffuse: Colour,
+    pub specular: Colour,
+    pub emission: Colour,
+    pub specular_exponent: f64,
+    pub refraction_index: Option<f64>,
+    pub dissolve: f64,
+}
+
+impl MtlMaterial {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            diffuse: Colour::new(0.8, 0.8, 0.8),
+            specular: Colour::new(0.0, 0.0, 0.0),
+            emission: Colour::new(0.0, 0.0, 0.0),
+            specular_exponent: 0.0,
+            refraction_index: None,
+            dissolve: 1.0,
+        }
+    }
+
+    // MTL describes Phong-style lobes, so pick whichever of our materials best matches the
+    // dominant one: emitters become lights, transparent surfaces glass and specular-dominated
+    // ones metal.
+    pub fn to_material(&self) -> Arc<dyn Material> {
+        if max_component(self.emission) > 0.0 {
+            return Arc::new(DiffuseLight::new(self.emission));
+        }
+
+        if self.dissolve < 1.0 {
+            return Arc::new(Dielectric::new(self.refraction_index.unwrap_or(1.5)));
+        }
+
+        if max_component(self.specular) > max_component(self.diffuse) {
+            let fuzz = (2.0 / (self.specular_exponent + 2.0)).sqrt().min(1.0);
+            return Arc::new(Metal::new(self.specular, fuzz));
+        }
+
+        Arc::new(Lambertian::new(self.diffuse))
+    }
+}
+
+fn max_component(colour: Colour) -> f64 {
+    f64::max(colour.x(), f64::max(colour.y(), colour.z()))
+}
+
+pub struct ObjGroup {
+    pub name: String,
+    pub material_name: Option<String>,
+    pub mesh: Arc<TriangleMesh>,
+}
+
+pub struct ObjModel {
+    pub groups: Vec<ObjGroup>,
+}
+
+impl ObjModel {
+    pub fn to_hittable_list(&self) -> HittableList {
+        let mut list = HittableList::new();
+        for group in &self.groups {
+            for triangle in group.mesh.triangles().objects() {
+                list.add(triangle.clone());
+            }
+        }
+        list
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.groups.iter().map(|group| group.mesh.faces().len()).sum()
+    }
+}
+
+pub fn load_obj(path: impl AsRef<Path>, default_material: Arc<dyn Material>) -> Result<ObjModel, ObjError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|e| ObjError::io(path, e))?;
+    parse_obj(&source, path, default_material)
+}
+
+pub fn load_mtl(path: impl AsRef<Path>) -> Result<HashMap<String, MtlMaterial>, ObjError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|e| ObjError::io(path, e))?;
+    parse_mtl(&source, path)
+}
+
+#[derive(Copy, Clone)]
+struct FaceVertex {
+    position: usize,
+    uv: Option<usize>,
+    normal: Option<usize>,
+}
+
+struct ObjFace {
+    vertices: [FaceVertex; 3],
+}
+
+// Faces are bucketed by group and material so that each bucket becomes one mesh. Buckets
+// keep the order they were first seen in so output is stable.
+struct Bucket {
+    name: String,
+    material_name: Option<String>,
+    faces: Vec<ObjFace>,
+}
+
+pub fn parse_obj(source: &str, path: &Path, default_material: Arc<dyn Material>) -> Result<ObjModel, ObjError> {
+    let mut positions = Vec::<Point>::new();
+    let mut normals = Vec::<Vec3>::new();
+    let mut uvs = Vec::<(f64, f64)>::new();
+    let mut materials = HashMap::<String, MtlMaterial>::new();
+    let mut buckets = Vec::<Bucket>::new();
+    let mut bucket_indices = HashMap::<(String, Option<String>), usize>::new();
+    let mut group = String::from("default");
+    let mut material_name: Option<String> = None;
+
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let line = strip_comment(line);
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+
+        match keyword {
+            "v" => positions.push(parse_vec3(&mut tokens, path, line_number)?),
+            "vn" => normals.push(parse_vec3(&mut tokens, path, line_number)?),
+            "vt" => {
+                let u = parse_float(tokens.next(), path, line_number)?;
+                let v = match tokens.next() {
+                    Some(token) => parse_float(Some(token), path, line_number)?,
+                    None => 0.0,
+                };
+                uvs.push((u, v));
+            }
+            "f" => {
+                let mut vertices = Vec::new();
+                for token in tokens {
+                    vertices.push(parse_face_vertex(
+                        token,
+                        (positions.len(), uvs.len(), normals.len()),
+                        path,
+                        line_number,
+                    )?);
+                }
+                if vertices.len() < 3 {
+                    return Err(ObjError::new(
+                        path,
+                        line_number,
+                        format!("face has {} vertices, at least 3 are required", vertices.len()),
+                    ));
+                }
+
+                let key = (group.clone(), material_name.clone());
+                let bucket_index = *bucket_indices.entry(key).or_insert_with(|| {
+                    buckets.push(Bucket {
+                        name: group.clone(),
+                        material_name: material_name.clone(),
+                        faces: Vec::new(),
+                    });
+                    buckets.len() - 1
+                });
+
+                for i in 1..vertices.len() - 1 {
+                    buckets[bucket_index].faces.push(ObjFace {
+                        vertices: [vertices[0], vertices[i], vertices[i + 1]],
+                    });
+                }
+            }
+            "g" | "o" => {
+                let names: Vec<&str> = tokens.collect();
+                group = if names.is_empty() {
+                    String::from("default")
+                } else {
+                    names.join(" ")
+                };
+            }
+            "usemtl" => {
+                let name = rest_of_line(tokens)
+                    .ok_or_else(|| ObjError::new(path, line_number, "usemtl requires a material name"))?;
+                if !materials.contains_key(&name) {
+                    return Err(ObjError::new(path, line_number, format!("undefined material '{}'", name)));
+                }
+                material_name = Some(name);
+            }
+            "mtllib" => {
+                let file = rest_of_line(tokens)
+                    .ok_or_else(|| ObjError::new(path, line_number, "mtllib requires a file name"))?;
+                let mtl_path = path.parent().unwrap_or_else(|| Path::new("")).join(file);
+                materials.extend(load_mtl(&mtl_path)?);
+            }
+            _ => {}
+        }
+    }
+
+    let material_cache: HashMap<&String, Arc<dyn Material>> = materials
+        .iter()
+        .map(|(name, material)| (name, material.to_material()))
+        .collect();
+
+    let groups = buckets
+        .into_iter()
+        .map(|bucket| {
+            let material = match &bucket.material_name {
+                Some(name) => material_cache[name].clone(),
+                None => default_material.clone(),
+            };
+            let mesh = build_mesh(&bucket.faces, &positions, &normals, &uvs, material);
+            ObjGroup {
+                name: bucket.name,
+                material_name: bucket.material_name,
+                mesh: Arc::new(mesh),
+            }
+        })
+        .collect();
+
+    Ok(ObjModel { groups })
+}
+
+// Copies only the vertices a bucket references into its own buffers, so meshes stay
+// independent while vertices shared within a bucket are still stored once.
+fn build_mesh(
+    faces: &[ObjFace],
+    positions: &[Point],
+    normals: &[Vec3],
+    uvs: &[(f64, f64)],
+    material: Arc<dyn Material>,
+) -> TriangleMesh {
+    let mut position_map = HashMap::new();
+    let mut normal_map = HashMap::new();
+    let mut uv_map = HashMap::new();
+    let mut mesh_positions = Vec::new();
+    let mut mesh_normals = Vec::new();
+    let mut mesh_uvs = Vec::new();
+    let mut mesh_faces = Vec::with_capacity(faces.len());
+
+    for face in faces {
+        let mut mesh_face = MeshFace::new([0; 3]);
+        let mut face_normals = [0; 3];
+        let mut face_uvs = [0; 3];
+        let mut has_normals = true;
+        let mut has_uvs = true;
+
+        for (i, vertex) in face.vertices.iter().enumerate() {
+            mesh_face.positions[i] = remap(vertex.position, &mut position_map, &mut mesh_positions, positions);
+            match vertex.normal {
+                Some(normal) => face_normals[i] = remap(normal, &mut normal_map, &mut mesh_normals, normals),
+                None => has_normals = false,
+            }
+            match vertex.uv {
+                Some(uv) => face_uvs[i] = remap(uv, &mut uv_map, &mut mesh_uvs, uvs),
+                None => has_uvs = false,
+            }
+        }
+
+        if has_normals {
+            mesh_face.normals = Some(face_normals);
+        }
+        if has_uvs {
+            mesh_face.uvs = Some(face_uvs);
+        }
+        mesh_faces.push(mesh_face);
+    }
+
+    TriangleMesh::new(mesh_positions, mesh_normals, mesh_uvs, mesh_faces, material)
+}
+
+fn remap<T: Copy>(index: usize, map: &mut HashMap<usize, usize>, buffer: &mut Vec<T>, source: &[T]) -> usize {
+    *map.entry(index).or_insert_with(|| {
+        buffer.push(source[index]);
+        buffer.len() - 1
+    })
+}
+
+pub fn parse_mtl(source: &str, path: &Path) -> Result<HashMap<String, MtlMaterial>, ObjError> {
+    let mut materials = HashMap::new();
+    let mut current: Option<MtlMaterial> = None;
+
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let line = strip_comment(line);
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+
+        if keyword == "newmtl" {
+            let name = rest_of_line(tokens)
+                .ok_or_else(|| ObjError::new(path, line_number, "newmtl requires a material name"))?;
+            if let Some(material) = current.take() {
+                materials.insert(material.name.clone(), material);
+            }
+            current = Some(MtlMaterial::new(&name));
+            continue;
+        }
+
+        let material = match (&mut current, keyword) {
+            (Some(material), _) => material,
+            (None, "Kd" | "Ks" | "Ke" | "Ns" | "Ni" | "d" | "Tr") => {
+                return Err(ObjError::new(
+                    path,
+                    line_number,
+                    format!("'{}' appears before any newmtl statement", keyword),
+                ));
+            }
+            (None, _) => continue,
+        };
+
+        match keyword {
+            "Kd" => material.diffuse = parse_vec3(&mut tokens, path, line_number)?,
+            "Ks" => material.specular = parse_vec3(&mut tokens, path, line_number)?,
+            "Ke" => material.emission = parse_vec3(&mut tokens, path, line_number)?,
+            "Ns" => material.specular_exponent = parse_float(tokens.next(), path, line_number)?,
+            "Ni" => material.refraction_index = Some(parse_float(tokens.next(), path, line_number)?),
+            "d" => material.dissolve = parse_float(tokens.next(), path, line_number)?,
+            "Tr" => material.dissolve = 1.0 - parse_float(tokens.next(), path, line_number)?,
+            _ => {}
+        }
+    }
+
+    if let Some(material) = current.take() {
+        materials.insert(material.name.clone(), material);
+    }
+
+    Ok(materials)
+}
+
+fn strip_comment(line: &str) -> &str {
+    match line.find('#') {
+        Some(index) => &line[..index],
+        None => line,
+    }
+}
+
+fn rest_of_line(tokens: SplitWhitespace) -> Option<String> {
+    let words: Vec<&str> = tokens.collect();
+    if words.is_empty() {
+        None
+    } else {
+        Some(words.join(" "))
+    }
+}
+
+fn parse_float(token: Option<&str>, path: &Path, line: usize) -> Result<f64, ObjError> {
+    let token = token.ok_or_else(|| ObjError::new(path, line, "expected a number but the line ended"))?;
+    token
+        .parse::<f64>()
+        .map_err(|_| ObjError::new(path, line, format!("'{}' is not a valid number", token)))
+}
+
+fn parse_vec3(tokens: &mut SplitWhitespace, path: &Path, line: usize) -> Result<Vec3, ObjError> {
+    let x = parse_float(tokens.next(), path, line)?;
+    let y = parse_float(tokens.next(), path, line)?;
+    let z = parse_float(tokens.next(), path, line)?;
+    Ok(Vec3::new(x, y, z))
+}
+
+// Resolves a 1-based (or negative, relative to the end) OBJ index against the number of
+// elements defined so far.
+fn resolve_index(token: &str, count: usize, kind: &str, path: &Path, line: usize) -> Result<usize, ObjError> {
+    let index = token
+        .parse::<i64>()
+        .map_err(|_| ObjError::new(path, line, format!("'{}' is not a valid {} index", token, kind)))?;
+
+    let resolved = if index > 0 {
+        index - 1
+    } else if index < 0 {
+        count as i64 + index
+    } else {
+        return Err(ObjError::new(path, line, format!("{} index 0 is invalid, indices start at 1", kind)));
+    };
+
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(ObjError::new(
+            path,
+            line,
+            format!("{} index {} is out of range, {} defined so far", kind, index, count),
+        ));
+    }
+
+    Ok(resolved as usize)
+}
+
+fn parse_face_vertex(
+    token: &str,
+    counts: (usize, usize, usize),
+    path: &Path,
+    line: usize,
+) -> Result<FaceVertex, ObjError> {
+    let (position_count, uv_count, normal_count) = counts;
+    let mut parts = token.split('/');
+
+    let position = resolve_index(parts.next().unwrap_or(""), position_count, "vertex", path, line)?;
+    let uv = match parts.next() {
+        Some("") | None => None,
+        Some(part) => Some(resolve_index(part, uv_count, "texture coordinate", path, line)?),
+    };
+    let normal = match parts.next() {
+        Some("") | None => None,
+        Some(part) => Some(resolve_index(part, normal_count, "normal", path, line)?),
+    };
+
+    if parts.next().is_some() {
+        return Err(ObjError::new(path, line, format!("malformed face vertex '{}'", token)));
+    }
+
+    Ok(FaceVertex { position, uv, normal })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Hittable;
+    use crate::linear_algebra::Ray;
+    use crate::materials::EmptyMaterial;
+
+    fn parse(source: &str) -> Result<ObjModel, ObjError> {
+        parse_obj(source, Path::new("test.obj"), Arc::new(EmptyMaterial))
+    }
+
+    mod obj_tests {
+        use super::*;
+
+        #[test]
+        fn fan_triangulates_polygons() {
+            let model = parse(
+                "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv -1 0.5 0\nf 1 2 3 4 5\n",
+            )
+            .unwrap();
+
+            assert_eq!(model.groups.len(), 1);
+            assert_eq!(model.triangle_count(), 3);
+            assert_eq!(model.groups[0].mesh.positions().len(), 5);
+        }
+
+        #[test]
+        fn negative_indices_and_attributes() {
+            let model = parse(
+                "v 0 0 -1\nv 1 0 -1\nv 0 1 -1\nvt 0 0\nvt 1 0\nvt 0 1\nvn 0 0 1\n\
+                 f -3/-3/-1 -2/-2/-1 -1/-1/-1\n",
+            )
+            .unwrap();
+            let face = model.groups[0].mesh.faces()[0];
+
+            assert_eq!(face.positions, [0, 1, 2]);
+            assert_eq!(face.uvs, Some([0, 1, 2]));
+            assert_eq!(face.normals, Some([0, 0, 0]));
+
+            let list = model.to_hittable_list();
+            let ray = Ray::new(Vec3::new(0.2, 0.2, 0.0), Vec3::new(0.0, 0.0, -1.0));
+            assert!(list.hit(ray, 0.0, 10.0).is_some());
+        }
+
+        #[test]
+        fn groups_split_meshes() {
+            let model = parse("v 0 0 0\nv 1 0 0\nv 0 1 0\ng a\nf 1 2 3\ng b\nf 1 2 3\ng a\nf 3 2 1\n").unwrap();
+            assert_eq!(model.groups.len(), 2);
+            assert_eq!(model.groups[0].name, "a");
+            assert_eq!(model.groups[0].mesh.faces().len(), 2);
+            assert_eq!(model.groups[1].name, "b");
+        }
+
+        #[test]
+        fn errors_report_line() {
+            let error = parse("v 0 0 0\nv 1 0 0\nf 1 2 3\n").err().unwrap();
+            assert_eq!(error.line, Some(3));
+            assert_eq!(error.to_string(), "test.obj:3: vertex index 3 is out of range, 2 defined so far");
+
+            let error = parse("v 0 zero 0\n").err().unwrap();
+            assert_eq!(error.to_string(), "test.obj:1: 'zero' is not a valid number");
+
+            let error = parse("v 0 0 0\nusemtl missing\n").err().unwrap();
+            assert_eq!(error.to_string(), "test.obj:2: undefined material 'missing'");
+
+            let error = parse("v 0 0 0\nv 1 0 0\nf 1 2\n").err().unwrap();
+            assert_eq!(error.line, Some(3));
+        }
+    }
+
+    mod mtl_tests {
+        use super::*;
+
+        #[test]
+        fn parse() {
+            let source = "# comment\nnewmtl red\nKd 1 0 0\n\nnewmtl glass\nNi 1.33\nd 0.2\n\
+                          newmtl chrome\nKd 0.1 0.1 0.1\nKs 0.9 0.9 0.9\nNs 1000\nnewmtl lamp\nKe 4 4 4\n";
+            let materials = parse_mtl(source, Path::new("test.mtl")).unwrap();
+
+            assert_eq!(materials.len(), 4);
+            assert!((materials["red"].diffuse.x() - 1.0).abs() < f64::EPSILON);
+            assert_eq!(materials["glass"].refraction_index, Some(1.33));
+            assert!((materials["glass"].dissolve - 0.2).abs() < f64::EPSILON);
+            assert!((materials["chrome"].specular_exponent - 1000.0).abs() < f64::EPSILON);
+            assert!((materials["lamp"].emission.y() - 4.0).abs() < f64::EPSILON);
+        }
+
+        #[test]
+        fn errors_report_line() {
+            let error = parse_mtl("Kd 1 1 1\n", Path::new("test.mtl")).err().unwrap();
+            assert_eq!(error.to_string(), "test.mtl:1: 'Kd' appears before any newmtl statement");
+
+            let error = parse_mtl("newmtl a\nKd 1 1\n", Path::new("test.mtl")).err().unwrap();
+            assert_eq!(error.to_string(), "test.mtl:2: expected a number but the line ended");
+        }
+    }
+}
@@ -1,5 +1,15 @@
 use rand::prelude::*;
+use rand::rngs::SmallRng;
+use std::cell::RefCell;
+
+thread_local! {
+    static RNG: RefCell<SmallRng> = RefCell::new(SmallRng::from_entropy());
+}
 
 pub fn rand(min: f64, max: f64)-> f64{
-    min+(max-min)*random::<f64>()
-}
\ No newline at end of file
+    RNG.with(|rng| min+(max-min)*rng.borrow_mut().gen::<f64>())
+}
+
+pub fn seed(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = SmallRng::seed_from_u64(seed));
+}
@@ -0,0 +1,531 @@
+use crate::background::Background;
+use crate::camera::Camera;
+use crate::geometry::Hittable;
+use crate::image::Framebuffer;
+use crate::light::LightList;
+use crate::linear_algebra::{Ray, Vec3};
+use crate::materials::Medium;
+use crate::random::{self, rand};
+use crate::{Colour, Point};
+use std::io::{stderr, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+pub struct RenderSettings {
+    pub image_width: usize,
+    pub image_height: usize,
+    pub samples_per_pixel: usize,
+    pub max_depth: usize,
+    pub tile_size: usize,
+    pub threads: usize,
+    pub seed: Option<u64>,
+}
+
+impl RenderSettings {
+    pub fn new(image_width: usize, image_height: usize, samples_per_pixel: usize, max_depth: usize) -> Self {
+        Self {
+            image_width,
+            image_height,
+            samples_per_pixel,
+            max_depth,
+            tile_size: 32,
+            threads: default_thread_count(),
+            seed: None,
+        }
+    }
+}
+
+pub fn default_thread_count() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Tile {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+fn tiles(settings: &RenderSettings) -> Vec<Tile> {
+    let tile_size = settings.tile_size.max(1);
+    let mut tiles = Vec::new();
+
+    for y in (0..settings.image_height).step_by(tile_size) {
+        for x in (0..settings.image_width).step_by(tile_size) {
+            tiles.push(Tile {
+                x,
+                y,
+                width: tile_size.min(settings.image_width - x),
+                height: tile_size.min(settings.image_height - y),
+            });
+        }
+    }
+
+    tiles
+}
+
+pub fn ray_colour(
+    ray: Ray,
+    world: &dyn Hittable,
+    lights: &LightList,
+    background: &dyn Background,
+    depth: usize,
+) -> Colour {
+    trace(ray, world, lights, background, depth, None, &MediumStack::default())
+}
+
+// The media a path is inside, in the order it entered them. Where they overlap, the one with
+// the highest priority fills the space, or the last entered among equals, so a liquid poured
+// into a glass can share the glass's inner surface without the overlap being counted twice.
+#[derive(Debug, Clone, Default)]
+struct MediumStack {
+    media: Vec<Medium>,
+}
+
+impl MediumStack {
+    fn current(&self) -> Option<&Medium> {
+        self.media.iter().max_by_key(|medium| medium.priority)
+    }
+
+    fn refraction_index(&self) -> f64 {
+        self.current().map_or(1.0, |medium| medium.refraction_index)
+    }
+
+    // Whether the boundary of `medium` lies inside a medium of higher priority, and so is no
+    // boundary at all.
+    fn hides(&self, medium: &Medium) -> bool {
+        self.media.iter().any(|other| other.priority > medium.priority)
+    }
+
+    fn entered(&self, medium: Medium) -> Self {
+        let mut media = self.media.clone();
+        media.push(medium);
+        Self { media }
+    }
+
+    fn exited(&self, medium: Medium) -> Self {
+        let mut media = self.media.clone();
+        if let Some(index) = media.iter().rposition(|&other| other == medium) {
+            media.remove(index);
+        }
+        Self { media }
+    }
+
+    // The media beyond a surface of `medium` hit from the side `front_face` gives.
+    fn crossed(&self, medium: Medium, front_face: bool) -> Self {
+        if front_face {
+            self.entered(medium)
+        } else {
+            self.exited(medium)
+        }
+    }
+}
+
+// The power heuristic weight for a sample taken with density `pdf` when another strategy
+// could have produced it with density `other_pdf`.
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b == 0.0 {
+        return 0.0;
+    }
+    a / (a + b)
+}
+
+// The fraction of each channel that survives `distance` through `medium` under the
+// Beer-Lambert law. Channels it does not absorb pass whole, even over an infinite distance.
+fn beer_lambert(medium: Option<&Medium>, distance: f64) -> Colour {
+    let survives = |absorption: f64| if absorption == 0.0 { 1.0 } else { (-absorption * distance).exp() };
+    match medium {
+        Some(medium) => Colour::new(
+            survives(medium.absorption.x()),
+            survives(medium.absorption.y()),
+            survives(medium.absorption.z()),
+        ),
+        None => Colour::new(1.0, 1.0, 1.0),
+    }
+}
+
+// Light reaches each hit both through a sample of the lights and through the scattered ray,
+// and multiple importance sampling weights the two so that each dominates where it has the
+// lower variance. `scattered_from` is the point the incoming ray was scattered from and the
+// density with which it was, or None for camera rays and specular bounces, which light
+// sampling could not have produced. It stays with the ray across hidden boundaries, so that
+// the light's density is taken from where the direction was actually chosen. `media` holds
+// the refractive media the ray is travelling through.
+fn trace(
+    ray: Ray,
+    world: &dyn Hittable,
+    lights: &LightList,
+    background: &dyn Background,
+    depth: usize,
+    scattered_from: Option<(Point, f64)>,
+    media: &MediumStack,
+) -> Colour {
+    if depth == 0 {
+        return Colour::new(0.0, 0.0, 0.0);
+    }
+
+    if let Some(mut record) = world.hit(ray, 0.001, f64::INFINITY) {
+        // Beer-Lambert absorption by the medium the ray has just crossed.
+        let transmittance = beer_lambert(media.current(), record.t * ray.direction.length());
+
+        let medium = record.material.medium();
+        if let Some(medium) = medium {
+            if media.hides(&medium) {
+                let inside = media.crossed(medium, record.front_face);
+                let ray = Ray::with_time(record.hit_point, ray.direction, ray.time);
+                return transmittance * trace(ray, world, lights, background, depth, scattered_from, &inside);
+            }
+            record.surrounding_index = if record.front_face {
+                media.refraction_index()
+            } else {
+                media.exited(medium).refraction_index()
+            };
+        } else {
+            record.surrounding_index = media.refraction_index();
+        }
+
+        // Rays leaving through the surface of a medium travel on in the media beyond it.
+        let crossing = |direction: Vec3| match medium {
+            Some(medium) if direction.dot(&record.normal) < 0.0 => Some(media.crossed(medium, record.front_face)),
+            _ => None,
+        };
+
+        let mut colour = record.material.emitted(&record);
+        if let (Some(index), Some((origin, pdf))) = (record.light, scattered_from) {
+            colour *= power_heuristic(pdf, lights.pdf(index, origin, ray.direction, ray.time));
+        }
+
+        if let Some(sample) = lights.sample(record.hit_point, ray.time) {
+            let response = record.material.eval(&ray, &record, sample.direction);
+            if !response.near_zero() {
+                let weight = if sample.hittable {
+                    power_heuristic(sample.pdf, record.material.pdf(&ray, &record, sample.direction))
+                } else {
+                    1.0
+                };
+                let beyond = crossing(sample.direction);
+                let absorbed = beer_lambert(beyond.as_ref().unwrap_or(media).current(), sample.distance);
+                let shadow_ray = Ray::with_time(record.hit_point, sample.direction, ray.time);
+                let transmittance = world.transmittance(shadow_ray, 0.001, sample.distance - 0.001);
+                colour += weight * transmittance / sample.pdf * absorbed * response * sample.radiance;
+            }
+        }
+
+        if let Some(scattered_ray) = record.material.scatter(&ray, &record) {
+            let beyond = crossing(scattered_ray.ray.direction);
+            colour += scattered_ray.attenuation
+                * trace(
+                    scattered_ray.ray,
+                    world,
+                    lights,
+                    background,
+                    depth - 1,
+                    scattered_ray.pdf.map(|pdf| (record.hit_point, pdf)),
+                    beyond.as_ref().unwrap_or(media),
+                );
+        }
+
+        return transmittance * colour;
+    }
+
+    let mut colour = background.colour(&ray);
+    if let (Some(index), Some((origin, pdf))) = (lights.environment(), scattered_from) {
+        colour *= power_heuristic(pdf, lights.pdf(index, origin, ray.direction, ray.time));
+    }
+    colour
+}
+
+fn render_tile(
+    tile: Tile,
+    world: &dyn Hittable,
+    lights: &LightList,
+    background: &dyn Background,
+    camera: &Camera,
+    settings: &RenderSettings,
+) -> Vec<Colour> {
+    let mut pixels = Vec::with_capacity(tile.width * tile.height);
+
+    for y in tile.y..tile.y + tile.height {
+        // Image rows run top to bottom while the camera's vertical coordinate runs bottom to top.
+        let row = settings.image_height - 1 - y;
+        for col in tile.x..tile.x + tile.width {
+            let mut pixel_colour = Colour::new(0.0, 0.0, 0.0);
+            for _ in 0..settings.samples_per_pixel {
+                let u = (col as f64 + rand(0.0, 1.0)) / (settings.image_width - 1) as f64;
+                let v = (row as f64 + rand(0.0, 1.0)) / (settings.image_height - 1) as f64;
+                let ray = camera.get_ray(u, v);
+                pixel_colour += ray_colour(ray, world, lights, background, settings.max_depth);
+            }
+            pixels.push(pixel_colour / settings.samples_per_pixel as f64);
+        }
+    }
+
+    pixels
+}
+
+// Renders the image on a pool of worker threads that pull tiles from a shared queue. The
+// framebuffer holds the averaged linear colour of every pixel, top row first.
+pub fn render(
+    world: &dyn Hittable,
+    lights: &LightList,
+    background: &dyn Background,
+    camera: &Camera,
+    settings: &RenderSettings,
+) -> Framebuffer {
+    let tiles = tiles(settings);
+    let next_tile = AtomicUsize::new(0);
+    let mut framebuffer = Framebuffer::new(settings.image_width, settings.image_height);
+
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::channel();
+
+        for _ in 0..settings.threads.max(1) {
+            let sender = sender.clone();
+            let tiles = &tiles;
+            let next_tile = &next_tile;
+            scope.spawn(move || loop {
+                let index = next_tile.fetch_add(1, Ordering::Relaxed);
+                if index >= tiles.len() {
+                    break;
+                }
+                // Seeding per tile rather than per thread keeps seeded renders identical
+                // whatever the thread count or scheduling order.
+                if let Some(seed) = settings.seed {
+                    random::seed(seed.wrapping_add((index as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)));
+                }
+                let pixels = render_tile(tiles[index], world, lights, background, camera, settings);
+                if sender.send((tiles[index], pixels)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        let mut tiles_remaining = tiles.len();
+        for (tile, pixels) in receiver {
+            for (i, pixel) in pixels.into_iter().enumerate() {
+                let x = tile.x + i % tile.width;
+                let y = tile.y + i / tile.width;
+                framebuffer.set(x, y, pixel);
+            }
+            tiles_remaining -= 1;
+            eprint!("\rTiles remaining: {:<8}", tiles_remaining);
+            stderr().flush().unwrap();
+        }
+    });
+    eprintln!();
+
+    framebuffer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod tile_tests {
+        use super::*;
+
+        #[test]
+        fn tiles_cover_image() {
+            let mut settings = RenderSettings::new(70, 45, 1, 1);
+            settings.tile_size = 32;
+            let tiles = tiles(&settings);
+            let mut covered = vec![0; 70 * 45];
+
+            for tile in &tiles {
+                for y in tile.y..tile.y + tile.height {
+                    for x in tile.x..tile.x + tile.width {
+                        covered[y * 70 + x] += 1;
+                    }
+                }
+            }
+
+            assert_eq!(tiles.len(), 6);
+            assert!(covered.iter().all(|&count| count == 1));
+        }
+    }
+
+    mod ray_colour_tests {
+        use super::*;
+        use crate::background::SolidBackground;
+        use crate::geometry::{HittableList, Sphere, XZRect};
+        use crate::light::AreaLight;
+        use crate::linear_algebra::{Mat4, Vec3};
+        use crate::transform::Transformed;
+        use crate::materials::{DiffuseLight, Lambertian, Material, Metal};
+        use crate::Point;
+        use std::sync::Arc;
+
+        fn square_lamp() -> Arc<dyn Hittable> {
+            let emitter = Arc::new(DiffuseLight::new(Colour::new(4.0, 4.0, 4.0)));
+            Arc::new(XZRect::new(-0.5, 0.5, -0.5, 0.5, 1.0, emitter))
+        }
+
+        // A floor lit by a light hanging above it, seen from straight above.
+        fn mean_colour(floor: Arc<dyn Material>, lamp: Arc<dyn Hittable>, sample_lights: bool, samples: usize) -> f64 {
+            let mut world = HittableList::new();
+            let mut lights = LightList::new();
+            world.add(Arc::new(XZRect::new(-50.0, 50.0, -50.0, 50.0, 0.0, floor)));
+            if sample_lights {
+                let light = Arc::new(AreaLight::new(lamp, 0));
+                lights.add(light.clone());
+                world.add(light);
+            } else {
+                world.add(lamp);
+            }
+            let background = SolidBackground::new(Colour::new(0.0, 0.0, 0.0));
+            let ray = Ray::new(Point::new(0.7, 0.5, 0.2), Vec3::new(0.0, -1.0, 0.0));
+
+            crate::random::seed(42);
+            let total: f64 = (0..samples)
+                .map(|_| ray_colour(ray, &world, &lights, &background, 4).x())
+                .sum();
+            total / samples as f64
+        }
+
+        // Weighting light samples against scattered rays must not change the expected
+        // colour, only the noise, whether the floor is diffuse or glossy.
+        fn check_unbiased(floor: Arc<dyn Material>, lamp: Arc<dyn Hittable>) {
+            let sampled = mean_colour(floor.clone(), lamp.clone(), true, 20000);
+            let unsampled = mean_colour(floor, lamp, false, 100000);
+
+            assert!(sampled > 0.1);
+            assert!((sampled - unsampled).abs() < 0.03 * sampled, "{} vs {}", sampled, unsampled);
+        }
+
+        #[test]
+        fn light_sampling_is_unbiased() {
+            check_unbiased(Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5))), square_lamp());
+            check_unbiased(Arc::new(Metal::new(Colour::new(0.8, 0.8, 0.8), 0.6)), square_lamp());
+        }
+
+        #[test]
+        fn transformed_light_sampling_is_unbiased() {
+            // A flattened, tilted bulb, whose sampling density has to be carried out of the
+            // instance's object space.
+            let emitter = Arc::new(DiffuseLight::new(Colour::new(4.0, 4.0, 4.0)));
+            let bulb = Arc::new(Sphere::new(Point::new(0.0, 0.0, 0.0), 1.0, emitter));
+            let transform = Mat4::translation(Vec3::new(0.3, 1.2, 0.0))
+                * Mat4::rotation(Vec3::new(0.0, 0.0, 1.0), 30.0)
+                * Mat4::scaling(Vec3::new(0.6, 0.15, 0.3));
+            let lamp = Arc::new(Transformed::new(bulb, transform).unwrap());
+            check_unbiased(Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5))), lamp);
+        }
+    }
+
+    mod medium_tests {
+        use super::*;
+        use crate::background::SolidBackground;
+        use crate::geometry::{HittableList, Sphere, XZRect};
+        use crate::light::{AreaLight, PointLight};
+        use crate::linear_algebra::Vec3;
+        use crate::materials::{Dielectric, DiffuseLight, Lambertian};
+        use crate::Point;
+        use std::sync::Arc;
+
+        fn medium(refraction_index: f64, absorption: f64, priority: u32) -> Medium {
+            Medium {
+                refraction_index,
+                absorption: Colour::new(absorption, absorption, absorption),
+                priority,
+            }
+        }
+
+        // Index-matched spheres of radius 2 centred along the x axis, each given by its centre,
+        // absorption and priority, crossed head on so that nothing reflects, in front of a
+        // white sky.
+        fn transmitted(spheres: &[(f64, Colour, u32)]) -> Colour {
+            let mut world = HittableList::new();
+            for &(centre, absorption, priority) in spheres {
+                let material = Arc::new(Dielectric::with_absorption(1.0, absorption, priority));
+                world.add(Arc::new(Sphere::new(Point::new(centre, 0.0, 0.0), 2.0, material)));
+            }
+            let background = SolidBackground::new(Colour::new(1.0, 1.0, 1.0));
+            let ray = Ray::new(Point::new(-10.0, 0.0, 0.0), Vec3::new(0.5, 0.0, 0.0));
+            ray_colour(ray, &world, &LightList::new(), &background, 10)
+        }
+
+        #[test]
+        fn absorption_follows_beer_lambert() {
+            let colour = transmitted(&[(0.0, Colour::new(0.1, 0.2, 0.4), 0)]);
+            let expected = Colour::new((-0.4f64).exp(), (-0.8f64).exp(), (-1.6f64).exp());
+            assert!((colour - expected).near_zero());
+        }
+
+        #[test]
+        fn overlaps_belong_to_the_higher_priority() {
+            // The glass spans x in [-2, 2] and the liquid [0, 4].
+            let (glass, liquid) = (Colour::new(0.1, 0.1, 0.1), Colour::new(0.5, 0.5, 0.5));
+
+            let colour = transmitted(&[(0.0, glass, 1), (2.0, liquid, 0)]);
+            assert!((colour.x() - (-0.1f64 * 4.0 - 0.5 * 2.0).exp()).abs() < 1e-9);
+
+            // Among equals the last medium entered fills the overlap.
+            let colour = transmitted(&[(0.0, glass, 0), (2.0, liquid, 0)]);
+            assert!((colour.x() - (-0.1f64 * 2.0 - 0.5 * 4.0).exp()).abs() < 1e-9);
+        }
+
+        #[test]
+        fn light_samples_are_absorbed() {
+            // A floor one unit below the eye, lit by a point light three units above it, all
+            // inside a medium.
+            let mut world = HittableList::new();
+            let floor = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+            world.add(Arc::new(XZRect::new(-5.0, 5.0, -5.0, 5.0, 0.0, floor)));
+            let mut lights = LightList::new();
+            lights.add(Arc::new(PointLight::new(Point::new(0.0, 3.0, 0.0), Colour::new(9.0, 9.0, 9.0))));
+            let background = SolidBackground::new(Colour::new(0.0, 0.0, 0.0));
+            let ray = Ray::new(Point::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+
+            let lit = |absorption: f64| {
+                let media = MediumStack::default().entered(medium(1.0, absorption, 0));
+                trace(ray, &world, &lights, &background, 1, None, &media).x()
+            };
+            assert!(lit(0.0) > 0.0);
+            assert!((lit(0.5) / lit(0.0) - (-0.5f64 * 4.0).exp()).abs() < 1e-9);
+        }
+
+        #[test]
+        fn hidden_boundaries_keep_the_scattering_origin() {
+            // A ray scattered from the origin towards a lamp, with a boundary on the way that the
+            // surrounding medium hides. The light's weight must be the same as without it.
+            let emitter = Arc::new(DiffuseLight::new(Colour::new(4.0, 4.0, 4.0)));
+            let lamp = Arc::new(AreaLight::new(Arc::new(XZRect::new(-0.5, 0.5, -0.5, 0.5, 1.0, emitter)), 0));
+            let mut lights = LightList::new();
+            lights.add(lamp.clone());
+            let background = SolidBackground::new(Colour::new(0.0, 0.0, 0.0));
+            let ray = Ray::new(Point::new(0.0, 0.0, 0.0), Vec3::new(0.1, 1.0, 0.0));
+            let media = MediumStack::default().entered(medium(1.0, 0.0, 1));
+
+            let radiance = |world: &HittableList| {
+                crate::random::seed(42);
+                trace(ray, world, &lights, &background, 1, Some((ray.origin, 0.3)), &media)
+            };
+            let mut open = HittableList::new();
+            open.add(lamp.clone());
+            let mut hidden = HittableList::new();
+            hidden.add(lamp);
+            let bubble = Arc::new(Dielectric::with_absorption(1.0, Colour::new(0.0, 0.0, 0.0), 0));
+            hidden.add(Arc::new(Sphere::new(Point::new(0.05, 0.5, 0.0), 0.2, bubble)));
+
+            let expected = radiance(&open);
+            assert!(expected.x() > 0.0);
+            assert!((radiance(&hidden) - expected).near_zero());
+        }
+
+        #[test]
+        fn surrounding_index_skips_the_exited_medium() {
+            let glass = medium(1.5, 0.0, 1);
+            let water = medium(1.33, 0.0, 0);
+            let inside = MediumStack::default().crossed(glass, true).crossed(water, true);
+
+            assert!(inside.hides(&water) && !inside.hides(&glass));
+            assert_eq!(inside.refraction_index(), 1.5);
+            assert_eq!(inside.exited(glass).refraction_index(), 1.33);
+            assert_eq!(inside.crossed(glass, false).crossed(water, false).refraction_index(), 1.0);
+        }
+    }
+}
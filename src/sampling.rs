@@ -0,0 +1,149 @@
+// A piecewise-constant density over [0, 1) proportional to `function`, sampled by inverting
+// its cumulative distribution. A function that is zero everywhere is sampled uniformly.
+#[derive(Debug, Clone)]
+pub struct Distribution1D {
+    function: Vec<f64>,
+    cdf: Vec<f64>,
+    integral: f64,
+}
+
+impl Distribution1D {
+    pub fn new(function: &[f64]) -> Self {
+        let count = function.len() as f64;
+        let mut cdf = Vec::with_capacity(function.len() + 1);
+        cdf.push(0.0);
+        for (i, value) in function.iter().enumerate() {
+            cdf.push(cdf[i] + value.max(0.0) / count);
+        }
+
+        let integral = cdf[function.len()];
+        for (i, value) in cdf.iter_mut().enumerate() {
+            *value = if integral > 0.0 { *value / integral } else { i as f64 / count };
+        }
+
+        Self {
+            function: function.iter().map(|value| value.max(0.0)).collect(),
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.function.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.function.is_empty()
+    }
+
+    // The integral of the function over [0, 1).
+    pub fn integral(&self) -> f64 {
+        self.integral
+    }
+
+    // Maps `u` in [0, 1) to a point x in [0, 1), returning x, its density and the index of
+    // the piece it falls in.
+    pub fn sample(&self, u: f64) -> (f64, f64, usize) {
+        let offset = (self.cdf.partition_point(|&c| c <= u).max(1) - 1).min(self.len() - 1);
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        let along = if width > 0.0 { (u - self.cdf[offset]) / width } else { 0.0 };
+        let x = ((offset as f64 + along.clamp(0.0, 1.0)) / self.len() as f64).min(1.0 - f64::EPSILON);
+        (x, self.pdf(offset), offset)
+    }
+
+    // The density of every point in the piece at `index`.
+    pub fn pdf(&self, index: usize) -> f64 {
+        if self.integral > 0.0 {
+            self.function[index] / self.integral
+        } else {
+            1.0
+        }
+    }
+}
+
+// A piecewise-constant density over [0, 1)^2 given by a row-major grid of `width` by `height`
+// values. The row is picked first from the marginal density, then the column from that
+// row's conditional density.
+#[derive(Debug, Clone)]
+pub struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(values: &[f64], width: usize, height: usize) -> Self {
+        let rows: Vec<Distribution1D> = values.chunks(width).take(height).map(Distribution1D::new).collect();
+        let integrals: Vec<f64> = rows.iter().map(|row| row.integral()).collect();
+        Self {
+            marginal: Distribution1D::new(&integrals),
+            rows,
+        }
+    }
+
+    // Maps (u1, u2) in [0, 1)^2 to a point (x, y) in [0, 1)^2, with x along a row and y across
+    // the rows, and returns it with its density.
+    pub fn sample(&self, u1: f64, u2: f64) -> ((f64, f64), f64) {
+        let (y, row_pdf, row) = self.marginal.sample(u2);
+        let (x, column_pdf, _) = self.rows[row].sample(u1);
+        ((x, y), row_pdf * column_pdf)
+    }
+
+    pub fn pdf(&self, x: f64, y: f64) -> f64 {
+        let row = ((y * self.rows.len() as f64) as usize).min(self.rows.len() - 1);
+        let column = ((x * self.rows[row].len() as f64) as usize).min(self.rows[row].len() - 1);
+        self.marginal.pdf(row) * self.rows[row].pdf(column)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod distribution_1d_tests {
+        use super::*;
+
+        #[test]
+        fn inverts_cdf() {
+            let distribution = Distribution1D::new(&[1.0, 0.0, 3.0]);
+            assert!(approx_eq(distribution.integral(), 4.0 / 3.0));
+
+            // A quarter of the mass lies in the first piece and the rest in the last.
+            let (x, pdf, index) = distribution.sample(0.125);
+            assert!(approx_eq(x, 1.0 / 6.0) && approx_eq(pdf, 0.75) && index == 0);
+            let (x, pdf, index) = distribution.sample(0.625);
+            assert!(approx_eq(x, 2.0 / 3.0 + 1.0 / 6.0) && approx_eq(pdf, 2.25) && index == 2);
+            assert_eq!(distribution.pdf(1), 0.0);
+        }
+
+        #[test]
+        fn zero_function_is_uniform() {
+            let distribution = Distribution1D::new(&[0.0, 0.0]);
+            let (x, pdf, index) = distribution.sample(0.75);
+            assert!(approx_eq(x, 0.75) && approx_eq(pdf, 1.0) && index == 1);
+        }
+    }
+
+    mod distribution_2d_tests {
+        use super::*;
+
+        #[test]
+        fn samples_match_density() {
+            let values = [1.0, 2.0, 0.0, 5.0, 0.5, 3.5];
+            let distribution = Distribution2D::new(&values, 3, 2);
+            let total: f64 = values.iter().sum();
+
+            for i in 0..50 {
+                let (u1, u2) = ((i as f64 * 0.618).fract(), (i as f64 * 0.414).fract());
+                let ((x, y), pdf) = distribution.sample(u1, u2);
+                let cell = (y * 2.0) as usize * 3 + (x * 3.0) as usize;
+                assert!(values[cell] > 0.0);
+                assert!(approx_eq(pdf, values[cell] * 6.0 / total));
+                assert!(approx_eq(distribution.pdf(x, y), pdf));
+            }
+        }
+    }
+
+    fn approx_eq(x: f64, y: f64) -> bool {
+        (x - y).abs() < 1e-12
+    }
+}
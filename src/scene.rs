@@ -0,0 +1,1790 @@
+use crate::background::{Background, EnvironmentMap, GradientBackground, SolidBackground};
+use crate::bvh::BvhNode;
+use crate::camera::Camera;
+use crate::geometry::{Cuboid, Hittable, HittableList, MovingSphere, Plane, Sphere, XYRect, XZRect, YZRect};
+use crate::image::load_image;
+use crate::light::{AreaLight, DirectionalLight, Light, LightList, PointLight, SpotLight};
+use crate::linear_algebra::Vec3;
+use crate::materials::{
+    Conductor, Dielectric, DiffuseLight, EmptyMaterial, Lambertian, Material, Metal, Principled, PrincipledParameters,
+    RoughDielectric,
+};
+use crate::mesh::Triangle;
+use crate::noise::Perlin;
+use crate::obj::load_obj;
+use crate::render::RenderSettings;
+use crate::sky::PreethamSky;
+use crate::texture::{CheckerTexture, ImageTexture, NoisePattern, NoiseTexture, SolidColour, Texture, WrapMode};
+use crate::transform::{compose, AnimatedTransform, TransformStep, Transformed};
+use crate::volume::{load_voxel_grid, ConstantMedium, DensityField, HeterogeneousMedium, NoiseDensity};
+use crate::Colour;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub struct Scene {
+    pub settings: RenderSettings,
+    pub camera: Camera,
+    pub world: HittableList,
+    // Emissive shapes in the world and the scene's other lights, all sampled directly.
+    pub lights: LightList,
+    pub background: Arc<dyn Background>,
+}
+
+#[derive(Debug)]
+pub struct SceneError {
+    pub path: PathBuf,
+    pub message: String,
+}
+
+impl SceneError {
+    fn new(path: &Path, message: impl Into<String>) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.message)
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDescription {
+    #[serde(default)]
+    image: ImageDescription,
+    camera: CameraDescription,
+    #[serde(default)]
+    background: BackgroundDescription,
+    #[serde(default)]
+    textures: BTreeMap<String, TextureDescription>,
+    #[serde(default)]
+    materials: BTreeMap<String, MaterialDescription>,
+    #[serde(default)]
+    prototypes: BTreeMap<String, PrototypeDescription>,
+    #[serde(default)]
+    objects: Vec<ObjectDescription>,
+    #[serde(default)]
+    lights: Vec<LightDescription>,
+}
+
+// A group of objects that is built once and then placed any number of times by `instance`
+// objects.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PrototypeDescription {
+    objects: Vec<ObjectDescription>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, default)]
+struct ImageDescription {
+    width: usize,
+    height: Option<usize>,
+    aspect_ratio: Option<f64>,
+    samples_per_pixel: usize,
+    max_depth: usize,
+}
+
+impl Default for ImageDescription {
+    fn default() -> Self {
+        Self {
+            width: 1200,
+            height: None,
+            aspect_ratio: None,
+            samples_per_pixel: 500,
+            max_depth: 50,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDescription {
+    look_from: [f64; 3],
+    look_at: [f64; 3],
+    #[serde(default = "default_vec_up")]
+    vec_up: [f64; 3],
+    vertical_fov: f64,
+    #[serde(default)]
+    aperture: f64,
+    focus_dist: Option<f64>,
+    #[serde(default)]
+    shutter_open: f64,
+    #[serde(default)]
+    shutter_close: f64,
+}
+
+// Lights that are not geometry, so are never seen directly and only light surfaces through
+// shadow rays. Directions give the way the light travels.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum LightDescription {
+    Point {
+        position: [f64; 3],
+        intensity: [f64; 3],
+    },
+    Spot {
+        position: [f64; 3],
+        direction: [f64; 3],
+        intensity: [f64; 3],
+        // Half-angle of the cone in degrees, of which the outer `falloff` degrees fade out.
+        cone_angle: f64,
+        #[serde(default)]
+        falloff: f64,
+    },
+    Directional {
+        direction: [f64; 3],
+        irradiance: [f64; 3],
+        #[serde(default)]
+        angular_diameter: f64,
+    },
+}
+
+impl LightDescription {
+    fn kind(&self) -> &'static str {
+        match self {
+            LightDescription::Point { .. } => "point",
+            LightDescription::Spot { .. } => "spot",
+            LightDescription::Directional { .. } => "directional",
+        }
+    }
+}
+
+fn default_vec_up() -> [f64; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum BackgroundDescription {
+    Gradient {
+        #[serde(default = "default_horizon")]
+        horizon: [f64; 3],
+        #[serde(default = "default_zenith")]
+        zenith: [f64; 3],
+    },
+    Solid {
+        colour: [f64; 3],
+    },
+    // An equirectangular HDR or PFM image that also lights the scene.
+    Environment {
+        file: String,
+        #[serde(default)]
+        rotation: f64,
+        #[serde(default = "default_intensity")]
+        intensity: f64,
+    },
+    // A daylight sky, with a sun in the direction given that also lights the scene.
+    Sky {
+        sun_direction: [f64; 3],
+        #[serde(default = "default_turbidity")]
+        turbidity: f64,
+        #[serde(default = "default_ground_albedo")]
+        ground_albedo: [f64; 3],
+        #[serde(default = "default_intensity")]
+        intensity: f64,
+    },
+}
+
+fn default_intensity() -> f64 {
+    1.0
+}
+
+fn default_turbidity() -> f64 {
+    3.0
+}
+
+fn default_ground_albedo() -> [f64; 3] {
+    [0.3, 0.3, 0.3]
+}
+
+impl Default for BackgroundDescription {
+    fn default() -> Self {
+        BackgroundDescription::Gradient {
+            horizon: default_horizon(),
+            zenith: default_zenith(),
+        }
+    }
+}
+
+fn default_horizon() -> [f64; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+fn default_zenith() -> [f64; 3] {
+    [0.5, 0.7, 1.0]
+}
+
+// Wherever a colour is expected, the name of a texture may be given instead.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColourSource {
+    Colour([f64; 3]),
+    Texture(String),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDescription {
+    Solid {
+        colour: [f64; 3],
+    },
+    Checker {
+        even: ColourSource,
+        odd: ColourSource,
+        #[serde(default = "default_checker_scale")]
+        scale: f64,
+    },
+    Image {
+        file: PathBuf,
+        #[serde(default)]
+        wrap: WrapDescription,
+    },
+    Noise {
+        #[serde(default)]
+        pattern: PatternDescription,
+        #[serde(default = "default_noise_scale")]
+        scale: f64,
+        #[serde(default)]
+        seed: u64,
+        #[serde(default = "default_noise_low")]
+        low: ColourSource,
+        #[serde(default = "default_noise_high")]
+        high: ColourSource,
+    },
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+enum PatternDescription {
+    #[default]
+    Turbulence,
+    Marble,
+    Wood,
+    Cloud,
+}
+
+impl PatternDescription {
+    fn build(&self) -> NoisePattern {
+        match self {
+            PatternDescription::Turbulence => NoisePattern::Turbulence,
+            PatternDescription::Marble => NoisePattern::Marble,
+            PatternDescription::Wood => NoisePattern::Wood,
+            PatternDescription::Cloud => NoisePattern::Cloud,
+        }
+    }
+}
+
+fn default_noise_scale() -> f64 {
+    1.0
+}
+
+fn default_noise_low() -> ColourSource {
+    ColourSource::Colour([0.0, 0.0, 0.0])
+}
+
+fn default_noise_high() -> ColourSource {
+    ColourSource::Colour([1.0, 1.0, 1.0])
+}
+
+fn default_base_colour() -> ColourSource {
+    ColourSource::Colour([0.8, 0.8, 0.8])
+}
+
+fn default_half() -> ScalarSource {
+    ScalarSource::Value(0.5)
+}
+
+fn default_refraction_index() -> f64 {
+    1.5
+}
+
+// Like `ColourSource`, for parameters that take a single number.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScalarSource {
+    Value(f64),
+    Texture(String),
+}
+
+impl Default for ScalarSource {
+    fn default() -> Self {
+        ScalarSource::Value(0.0)
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+enum WrapDescription {
+    #[default]
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+fn default_checker_scale() -> f64 {
+    1.0
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDescription {
+    Lambertian {
+        albedo: ColourSource,
+    },
+    Metal {
+        albedo: ColourSource,
+        #[serde(default)]
+        fuzz: ScalarSource,
+    },
+    // Coloured glass or liquid absorbs `absorption` of the light per unit distance inside it.
+    Dielectric {
+        refraction_index: f64,
+        #[serde(default)]
+        absorption: [f64; 3],
+        #[serde(default)]
+        priority: u32,
+    },
+    // A rough metal given by its complex refractive index, eta + ik, per channel.
+    Conductor {
+        eta: [f64; 3],
+        k: [f64; 3],
+        #[serde(default)]
+        roughness: ScalarSource,
+        #[serde(default)]
+        anisotropy: f64,
+    },
+    RoughDielectric {
+        refraction_index: f64,
+        #[serde(default)]
+        roughness: ScalarSource,
+        #[serde(default)]
+        anisotropy: f64,
+        #[serde(default)]
+        absorption: [f64; 3],
+        #[serde(default)]
+        priority: u32,
+    },
+    Principled {
+        #[serde(default = "default_base_colour")]
+        base_colour: ColourSource,
+        #[serde(default)]
+        metallic: ScalarSource,
+        #[serde(default = "default_half")]
+        roughness: ScalarSource,
+        #[serde(default = "default_half")]
+        specular: ScalarSource,
+        #[serde(default)]
+        sheen: ScalarSource,
+        #[serde(default)]
+        clearcoat: ScalarSource,
+        #[serde(default)]
+        transmission: ScalarSource,
+        #[serde(default)]
+        subsurface: ScalarSource,
+        #[serde(default)]
+        anisotropy: f64,
+        #[serde(default = "default_refraction_index")]
+        refraction_index: f64,
+    },
+    DiffuseLight {
+        emit: ColourSource,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ObjectDescription {
+    Sphere {
+        centre: [f64; 3],
+        radius: f64,
+        material: String,
+    },
+    MovingSphere {
+        centre0: [f64; 3],
+        centre1: [f64; 3],
+        #[serde(default)]
+        time0: f64,
+        #[serde(default = "default_time1")]
+        time1: f64,
+        radius: f64,
+        material: String,
+    },
+    Triangle {
+        vertices: [[f64; 3]; 3],
+        material: String,
+    },
+    Mesh {
+        file: PathBuf,
+        material: Option<String>,
+    },
+    XyRect {
+        x: [f64; 2],
+        y: [f64; 2],
+        z: f64,
+        material: String,
+    },
+    XzRect {
+        x: [f64; 2],
+        z: [f64; 2],
+        y: f64,
+        material: String,
+    },
+    YzRect {
+        y: [f64; 2],
+        z: [f64; 2],
+        x: f64,
+        material: String,
+    },
+    Box {
+        min: [f64; 3],
+        max: [f64; 3],
+        material: String,
+    },
+    Plane {
+        point: [f64; 3],
+        normal: [f64; 3],
+        material: String,
+    },
+    // Fills the closed `boundary` object with a uniform scattering medium such as smoke or
+    // fog. The boundary's own material is not used.
+    ConstantMedium {
+        boundary: Box<ObjectDescription>,
+        density: f64,
+        #[serde(default = "default_medium_albedo")]
+        albedo: ColourSource,
+    },
+    // A scattering medium whose density varies through space. Grid densities default to
+    // filling their own box, while noise needs an explicit `boundary`.
+    HeterogeneousMedium {
+        boundary: Option<Box<ObjectDescription>>,
+        density: DensityDescription,
+        #[serde(default = "default_medium_albedo")]
+        albedo: ColourSource,
+    },
+    Instance {
+        prototype: String,
+        #[serde(default)]
+        transform: Vec<TransformDescription>,
+        // When given, the instance moves from `transform` at `time0` to `end_transform` at
+        // `time1`.
+        end_transform: Option<Vec<TransformDescription>>,
+        #[serde(default)]
+        time0: f64,
+        #[serde(default = "default_time1")]
+        time1: f64,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum DensityDescription {
+    Noise {
+        #[serde(default)]
+        pattern: PatternDescription,
+        #[serde(default = "default_noise_scale")]
+        scale: f64,
+        #[serde(default)]
+        seed: u64,
+        // The density where the pattern is at its peak.
+        density: f64,
+    },
+    // Densities read from a voxel file and stretched over the box from `min` to `max`.
+    Grid {
+        file: PathBuf,
+        min: [f64; 3],
+        max: [f64; 3],
+    },
+}
+
+fn default_medium_albedo() -> ColourSource {
+    ColourSource::Colour([1.0, 1.0, 1.0])
+}
+
+fn default_time1() -> f64 {
+    1.0
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum TransformDescription {
+    Translate([f64; 3]),
+    Scale(ScaleDescription),
+    Rotate { axis: [f64; 3], degrees: f64 },
+    RotateX(f64),
+    RotateY(f64),
+    RotateZ(f64),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScaleDescription {
+    Uniform(f64),
+    PerAxis([f64; 3]),
+}
+
+fn vec3(v: [f64; 3]) -> Vec3 {
+    Vec3::new(v[0], v[1], v[2])
+}
+
+pub fn load_scene(path: impl AsRef<Path>) -> Result<Scene, SceneError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|e| SceneError::new(path, e.to_string()))?;
+    parse_scene(&source, path)
+}
+
+pub fn parse_scene(source: &str, path: &Path) -> Result<Scene, SceneError> {
+    let description: SceneDescription =
+        toml::from_str(source).map_err(|e| SceneError::new(path, e.to_string()))?;
+
+    let settings = build_settings(&description.image).map_err(|message| SceneError::new(path, message))?;
+    let aspect_ratio = settings.image_width as f64 / settings.image_height as f64;
+    let camera = build_camera(&description.camera, aspect_ratio).map_err(|message| SceneError::new(path, message))?;
+
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let (background, environment) =
+        build_background(&description.background, directory).map_err(|message| SceneError::new(path, message))?;
+
+    let mut textures = BTreeMap::<&str, Arc<dyn Texture>>::new();
+    for name in description.textures.keys() {
+        build_texture(name, &description.textures, directory, &mut textures, &mut Vec::new())
+            .map_err(|message| SceneError::new(path, message))?;
+    }
+
+    let mut materials = BTreeMap::<&str, Arc<dyn Material>>::new();
+    for (name, material) in &description.materials {
+        let material = build_material(material, &textures).map_err(|message| {
+            SceneError::new(path, format!("materials.{}: {}", name, message))
+        })?;
+        materials.insert(name, material);
+    }
+
+    let mut prototypes = BTreeMap::<&str, Prototype>::new();
+    for (name, prototype) in &description.prototypes {
+        let mut objects = HittableList::new();
+        let mut emitters = HittableList::new();
+        for (index, object) in prototype.objects.iter().enumerate() {
+            // Instances are resolved against the finished prototype table, so prototypes
+            // cannot refer to one another.
+            add_object(object, &textures, &materials, &BTreeMap::new(), directory, &mut objects, Some(&mut emitters))
+                .map_err(|message| {
+                    SceneError::new(
+                        path,
+                        format!("prototypes.{}.objects[{}] ({}): {}", name, index, object.kind(), message),
+                    )
+                })?;
+        }
+        if objects.is_empty() && emitters.is_empty() {
+            return Err(SceneError::new(path, format!("prototypes.{}: no objects given", name)));
+        }
+        let objects: Option<Arc<dyn Hittable>> = if objects.is_empty() {
+            None
+        } else {
+            Some(Arc::new(BvhNode::new(&objects)))
+        };
+        let emitters: Option<Arc<dyn Hittable>> = match emitters.objects() {
+            [] => None,
+            [emitter] => Some(emitter.clone()),
+            _ => Some(Arc::new(emitters)),
+        };
+        prototypes.insert(name, Prototype { objects, emitters });
+    }
+
+    let mut world = HittableList::new();
+    let mut lights = LightList::new();
+    for (index, object) in description.objects.iter().enumerate() {
+        let mut emitters = HittableList::new();
+        add_object(object, &textures, &materials, &prototypes, directory, &mut world, Some(&mut emitters)).map_err(
+            |message| SceneError::new(path, format!("objects[{}] ({}): {}", index, object.kind(), message)),
+        )?;
+        for emitter in emitters.objects() {
+            let light = Arc::new(AreaLight::new(emitter.clone(), lights.len()));
+            lights.add(light.clone());
+            world.add(light);
+        }
+    }
+
+    for (index, light) in description.lights.iter().enumerate() {
+        let light = build_light(light)
+            .map_err(|message| SceneError::new(path, format!("lights[{}] ({}): {}", index, light.kind(), message)))?;
+        lights.add(light);
+    }
+    if let Some(environment) = environment {
+        lights.add_environment(environment);
+    }
+
+    Ok(Scene {
+        settings,
+        camera,
+        world,
+        lights,
+        background,
+    })
+}
+
+fn build_light(light: &LightDescription) -> Result<Arc<dyn Light>, String> {
+    let check_colour = |name: &str, colour: [f64; 3]| {
+        if colour.iter().any(|c| *c < 0.0) {
+            Err(format!("{} must not be negative", name))
+        } else {
+            Ok(vec3(colour))
+        }
+    };
+    let check_direction = |direction: [f64; 3]| {
+        if vec3(direction).near_zero() {
+            Err(String::from("direction must not be zero"))
+        } else {
+            Ok(vec3(direction))
+        }
+    };
+
+    Ok(match light {
+        LightDescription::Point { position, intensity } => {
+            Arc::new(PointLight::new(vec3(*position), check_colour("intensity", *intensity)?))
+        }
+        LightDescription::Spot {
+            position,
+            direction,
+            intensity,
+            cone_angle,
+            falloff,
+        } => {
+            if *cone_angle <= 0.0 || *cone_angle >= 180.0 {
+                return Err(String::from("cone_angle must be between 0 and 180 degrees"));
+            }
+            if *falloff < 0.0 || falloff > cone_angle {
+                return Err(String::from("falloff must be between 0 and cone_angle"));
+            }
+            Arc::new(SpotLight::new(
+                vec3(*position),
+                check_direction(*direction)?,
+                check_colour("intensity", *intensity)?,
+                *cone_angle,
+                *falloff,
+            ))
+        }
+        LightDescription::Directional {
+            direction,
+            irradiance,
+            angular_diameter,
+        } => {
+            if *angular_diameter < 0.0 || *angular_diameter >= 180.0 {
+                return Err(String::from("angular_diameter must be at least 0 and less than 180 degrees"));
+            }
+            Arc::new(DirectionalLight::new(
+                check_direction(*direction)?,
+                check_colour("irradiance", *irradiance)?,
+                *angular_diameter,
+            ))
+        }
+    })
+}
+
+fn build_settings(image: &ImageDescription) -> Result<RenderSettings, String> {
+    if image.width < 2 {
+        return Err(String::from("image.width must be at least 2"));
+    }
+    if image.samples_per_pixel == 0 {
+        return Err(String::from("image.samples_per_pixel must be at least 1"));
+    }
+    if image.max_depth == 0 {
+        return Err(String::from("image.max_depth must be at least 1"));
+    }
+
+    let height = match (image.height, image.aspect_ratio) {
+        (Some(_), Some(_)) => {
+            return Err(String::from("image.height and image.aspect_ratio cannot both be given"));
+        }
+        (Some(height), None) => height,
+        (None, Some(aspect_ratio)) if aspect_ratio > 0.0 => (image.width as f64 / aspect_ratio) as usize,
+        (None, Some(_)) => return Err(String::from("image.aspect_ratio must be positive")),
+        (None, None) => (image.width as f64 / (3.0 / 2.0)) as usize,
+    };
+    if height < 2 {
+        return Err(String::from("image height must be at least 2"));
+    }
+
+    Ok(RenderSettings::new(image.width, height, image.samples_per_pixel, image.max_depth))
+}
+
+fn build_camera(camera: &CameraDescription, aspect_ratio: f64) -> Result<Camera, String> {
+    let look_from = vec3(camera.look_from);
+    let look_at = vec3(camera.look_at);
+    let vec_up = vec3(camera.vec_up);
+
+    if (look_from - look_at).near_zero() {
+        return Err(String::from("camera.look_from and camera.look_at must differ"));
+    }
+    if vec_up.cross(&(look_from - look_at)).near_zero() {
+        return Err(String::from("camera.vec_up must not be parallel to the viewing direction"));
+    }
+    if camera.vertical_fov <= 0.0 || camera.vertical_fov >= 180.0 {
+        return Err(String::from("camera.vertical_fov must be between 0 and 180 degrees"));
+    }
+    if camera.aperture < 0.0 {
+        return Err(String::from("camera.aperture must not be negative"));
+    }
+
+    let focus_dist = camera.focus_dist.unwrap_or_else(|| (look_from - look_at).length());
+    if focus_dist <= 0.0 {
+        return Err(String::from("camera.focus_dist must be positive"));
+    }
+
+    if camera.shutter_close < camera.shutter_open {
+        return Err(String::from("camera.shutter_close must not be before camera.shutter_open"));
+    }
+
+    let mut result = Camera::new(
+        look_from,
+        look_at,
+        vec_up,
+        camera.vertical_fov,
+        aspect_ratio,
+        camera.aperture,
+        focus_dist,
+    );
+    result.set_shutter(camera.shutter_open, camera.shutter_close);
+    Ok(result)
+}
+
+fn non_negative(colour: [f64; 3]) -> bool {
+    colour.iter().all(|&c| c >= 0.0)
+}
+
+// A background along with the light it casts, for those that are sampled directly.
+type LitBackground = (Arc<dyn Background>, Option<Arc<dyn Light>>);
+
+fn build_background(background: &BackgroundDescription, directory: &Path) -> Result<LitBackground, String> {
+    match background {
+        BackgroundDescription::Gradient { horizon, zenith } => {
+            if !non_negative(*horizon) || !non_negative(*zenith) {
+                return Err(String::from("background colours must not be negative"));
+            }
+            Ok((Arc::new(GradientBackground::new(vec3(*horizon), vec3(*zenith))), None))
+        }
+        BackgroundDescription::Solid { colour } => {
+            if !non_negative(*colour) {
+                return Err(String::from("background colours must not be negative"));
+            }
+            Ok((Arc::new(SolidBackground::new(vec3(*colour))), None))
+        }
+        BackgroundDescription::Environment {
+            file,
+            rotation,
+            intensity,
+        } => {
+            if *intensity < 0.0 {
+                return Err(String::from("background intensity must not be negative"));
+            }
+            let image = load_image(&directory.join(file)).map_err(|e| format!("background: {}", e))?;
+            if image.width() == 0 || image.height() == 0 {
+                return Err(format!("background: {} is empty", file));
+            }
+            let map = Arc::new(EnvironmentMap::new(Arc::new(image), *rotation, *intensity));
+            Ok((map.clone(), Some(map)))
+        }
+        BackgroundDescription::Sky {
+            sun_direction,
+            turbidity,
+            ground_albedo,
+            intensity,
+        } => {
+            let sun_direction = vec3(*sun_direction);
+            if sun_direction.y() <= 0.0 {
+                return Err(String::from("background sun_direction must point above the horizon"));
+            }
+            if !(1.7..=10.0).contains(turbidity) {
+                return Err(String::from("background turbidity must be between 1.7 and 10"));
+            }
+            if !ground_albedo.iter().all(|a| (0.0..=1.0).contains(a)) {
+                return Err(String::from("background ground_albedo must be between 0 and 1"));
+            }
+            if *intensity < 0.0 {
+                return Err(String::from("background intensity must not be negative"));
+            }
+            let sky = Arc::new(PreethamSky::new(sun_direction, *turbidity, vec3(*ground_albedo), *intensity));
+            Ok((sky.clone(), Some(sky)))
+        }
+    }
+}
+
+// Builds the named (and existing) texture after any textures it refers to. `stack` holds the
+// textures currently being built, so that reference cycles are reported rather than followed
+// forever.
+fn build_texture<'a>(
+    name: &'a str,
+    descriptions: &'a BTreeMap<String, TextureDescription>,
+    directory: &Path,
+    textures: &mut BTreeMap<&'a str, Arc<dyn Texture>>,
+    stack: &mut Vec<&'a str>,
+) -> Result<Arc<dyn Texture>, String> {
+    if let Some(texture) = textures.get(name) {
+        return Ok(texture.clone());
+    }
+
+    let description = &descriptions[name];
+    stack.push(name);
+
+    let mut source = |source: &'a ColourSource| match source {
+        ColourSource::Colour(colour) => Ok(Arc::new(SolidColour::new(vec3(*colour))) as Arc<dyn Texture>),
+        ColourSource::Texture(other) => {
+            if !descriptions.contains_key(other) {
+                return Err(format!("textures.{}: unknown texture '{}'", name, other));
+            }
+            if stack.contains(&other.as_str()) {
+                return Err(format!("textures.{}: reference to '{}' forms a cycle", name, other));
+            }
+            build_texture(other, descriptions, directory, textures, stack)
+        }
+    };
+    let texture: Arc<dyn Texture> = match description {
+        TextureDescription::Solid { colour } => Arc::new(SolidColour::new(vec3(*colour))),
+        TextureDescription::Checker { even, odd, scale } => {
+            if *scale <= 0.0 {
+                return Err(format!("textures.{}: scale must be positive", name));
+            }
+            Arc::new(CheckerTexture::new(source(even)?, source(odd)?, *scale))
+        }
+        TextureDescription::Image { file, wrap } => {
+            let image = load_image(&directory.join(file)).map_err(|e| format!("textures.{}: {}", name, e))?;
+            let wrap = match wrap {
+                WrapDescription::Repeat => WrapMode::Repeat,
+                WrapDescription::Clamp => WrapMode::Clamp,
+                WrapDescription::Mirror => WrapMode::Mirror,
+            };
+            Arc::new(ImageTexture::new(Arc::new(image), wrap))
+        }
+        TextureDescription::Noise {
+            pattern,
+            scale,
+            seed,
+            low,
+            high,
+        } => {
+            if *scale <= 0.0 {
+                return Err(format!("textures.{}: scale must be positive", name));
+            }
+            let noise = Arc::new(Perlin::new(*seed));
+            Arc::new(NoiseTexture::new(noise, pattern.build(), *scale, source(low)?, source(high)?))
+        }
+    };
+
+    stack.pop();
+    textures.insert(name, texture.clone());
+    Ok(texture)
+}
+
+fn lookup_texture(textures: &BTreeMap<&str, Arc<dyn Texture>>, name: &str) -> Result<Arc<dyn Texture>, String> {
+    textures
+        .get(name)
+        .cloned()
+        .ok_or_else(|| format!("unknown texture '{}'", name))
+}
+
+fn lookup_colour_source(
+    source: &ColourSource,
+    textures: &BTreeMap<&str, Arc<dyn Texture>>,
+) -> Result<Arc<dyn Texture>, String> {
+    match source {
+        ColourSource::Colour(colour) => Ok(Arc::new(SolidColour::new(vec3(*colour)))),
+        ColourSource::Texture(name) => lookup_texture(textures, name),
+    }
+}
+
+// Values in a scalar texture are clamped where they are used, so only constants are checked.
+fn lookup_unit_scalar_source(
+    source: &ScalarSource,
+    name: &str,
+    textures: &BTreeMap<&str, Arc<dyn Texture>>,
+) -> Result<Arc<dyn Texture>, String> {
+    match source {
+        ScalarSource::Value(value) => {
+            if !(0.0..=1.0).contains(value) {
+                return Err(format!("{} must be between 0 and 1", name));
+            }
+            Ok(Arc::new(SolidColour::new(Colour::new(*value, *value, *value))))
+        }
+        ScalarSource::Texture(texture) => lookup_texture(textures, texture),
+    }
+}
+
+fn build_material(
+    material: &MaterialDescription,
+    textures: &BTreeMap<&str, Arc<dyn Texture>>,
+) -> Result<Arc<dyn Material>, String> {
+    match material {
+        MaterialDescription::Lambertian { albedo } => {
+            Ok(Arc::new(Lambertian::with_texture(lookup_colour_source(albedo, textures)?)))
+        }
+        MaterialDescription::Metal { albedo, fuzz } => {
+            let albedo = lookup_colour_source(albedo, textures)?;
+            match fuzz {
+                ScalarSource::Value(fuzz) => {
+                    if !(0.0..=1.0).contains(fuzz) {
+                        return Err(String::from("fuzz must be between 0 and 1"));
+                    }
+                    Ok(Arc::new(Metal::with_texture(albedo, *fuzz)))
+                }
+                ScalarSource::Texture(name) => Ok(Arc::new(Metal::with_textures(albedo, lookup_texture(textures, name)?))),
+            }
+        }
+        MaterialDescription::Dielectric {
+            refraction_index,
+            absorption,
+            priority,
+        } => {
+            if *refraction_index <= 0.0 {
+                return Err(String::from("refraction_index must be positive"));
+            }
+            if !non_negative(*absorption) {
+                return Err(String::from("absorption must not be negative"));
+            }
+            Ok(Arc::new(Dielectric::with_absorption(*refraction_index, vec3(*absorption), *priority)))
+        }
+        MaterialDescription::Conductor {
+            eta,
+            k,
+            roughness,
+            anisotropy,
+        } => {
+            if !eta.iter().all(|&e| e > 0.0) {
+                return Err(String::from("eta must be positive"));
+            }
+            if !non_negative(*k) {
+                return Err(String::from("k must not be negative"));
+            }
+            if !(0.0..=1.0).contains(anisotropy) {
+                return Err(String::from("anisotropy must be between 0 and 1"));
+            }
+            let roughness = lookup_unit_scalar_source(roughness, "roughness", textures)?;
+            Ok(Arc::new(Conductor::with_texture(vec3(*eta), vec3(*k), roughness, *anisotropy)))
+        }
+        MaterialDescription::RoughDielectric {
+            refraction_index,
+            roughness,
+            anisotropy,
+            absorption,
+            priority,
+        } => {
+            if *refraction_index <= 0.0 {
+                return Err(String::from("refraction_index must be positive"));
+            }
+            if !(0.0..=1.0).contains(anisotropy) {
+                return Err(String::from("anisotropy must be between 0 and 1"));
+            }
+            if !non_negative(*absorption) {
+                return Err(String::from("absorption must not be negative"));
+            }
+            let roughness = lookup_unit_scalar_source(roughness, "roughness", textures)?;
+            Ok(Arc::new(RoughDielectric::with_absorption(
+                *refraction_index,
+                roughness,
+                *anisotropy,
+                vec3(*absorption),
+                *priority,
+            )))
+        }
+        MaterialDescription::Principled {
+            base_colour,
+            metallic,
+            roughness,
+            specular,
+            sheen,
+            clearcoat,
+            transmission,
+            subsurface,
+            anisotropy,
+            refraction_index,
+        } => {
+            if *refraction_index <= 0.0 {
+                return Err(String::from("refraction_index must be positive"));
+            }
+            if !(0.0..=1.0).contains(anisotropy) {
+                return Err(String::from("anisotropy must be between 0 and 1"));
+            }
+            let scalar = |source, name| lookup_unit_scalar_source(source, name, textures);
+            Ok(Arc::new(Principled::with_parameters(PrincipledParameters {
+                base_colour: lookup_colour_source(base_colour, textures)?,
+                metallic: scalar(metallic, "metallic")?,
+                roughness: scalar(roughness, "roughness")?,
+                specular: scalar(specular, "specular")?,
+                sheen: scalar(sheen, "sheen")?,
+                clearcoat: scalar(clearcoat, "clearcoat")?,
+                transmission: scalar(transmission, "transmission")?,
+                subsurface: scalar(subsurface, "subsurface")?,
+                anisotropy: *anisotropy,
+                refraction_index: *refraction_index,
+            })))
+        }
+        MaterialDescription::DiffuseLight { emit } => {
+            if let ColourSource::Colour(colour) = emit {
+                if !non_negative(*colour) {
+                    return Err(String::from("emit must not be negative"));
+                }
+            }
+            Ok(Arc::new(DiffuseLight::with_texture(lookup_colour_source(emit, textures)?)))
+        }
+    }
+}
+
+impl ObjectDescription {
+    fn kind(&self) -> &'static str {
+        match self {
+            ObjectDescription::Sphere { .. } => "sphere",
+            ObjectDescription::MovingSphere { .. } => "moving_sphere",
+            ObjectDescription::Triangle { .. } => "triangle",
+            ObjectDescription::Mesh { .. } => "mesh",
+            ObjectDescription::XyRect { .. } => "xy_rect",
+            ObjectDescription::XzRect { .. } => "xz_rect",
+            ObjectDescription::YzRect { .. } => "yz_rect",
+            ObjectDescription::Box { .. } => "box",
+            ObjectDescription::Plane { .. } => "plane",
+            ObjectDescription::ConstantMedium { .. } => "constant_medium",
+            ObjectDescription::HeterogeneousMedium { .. } => "heterogeneous_medium",
+            ObjectDescription::Instance { .. } => "instance",
+        }
+    }
+}
+
+fn add_shape(
+    shape: Arc<dyn Hittable>,
+    material: &Arc<dyn Material>,
+    emitters: Option<&mut HittableList>,
+    world: &mut HittableList,
+) {
+    match emitters {
+        Some(emitters) if material.is_emissive() => emitters.add(shape),
+        _ => world.add(shape),
+    }
+}
+
+fn lookup_material(materials: &BTreeMap<&str, Arc<dyn Material>>, name: &str) -> Result<Arc<dyn Material>, String> {
+    materials
+        .get(name)
+        .cloned()
+        .ok_or_else(|| format!("unknown material '{}'", name))
+}
+
+fn check_range(name: &str, range: [f64; 2]) -> Result<(), String> {
+    if range[0] < range[1] {
+        Ok(())
+    } else {
+        Err(format!("{} must be given as [min, max] with min < max", name))
+    }
+}
+
+fn build_steps(transforms: &[TransformDescription]) -> Result<Vec<TransformStep>, String> {
+    transforms
+        .iter()
+        .map(|transform| match transform {
+            TransformDescription::Translate(offset) => Ok(TransformStep::Translate(vec3(*offset))),
+            TransformDescription::Scale(ScaleDescription::Uniform(factor)) => {
+                Ok(TransformStep::Scale(Vec3::new(*factor, *factor, *factor)))
+            }
+            TransformDescription::Scale(ScaleDescription::PerAxis(factors)) => Ok(TransformStep::Scale(vec3(*factors))),
+            TransformDescription::Rotate { axis, degrees } => {
+                if vec3(*axis).near_zero() {
+                    return Err(String::from("rotation axis must not be zero"));
+                }
+                Ok(TransformStep::Rotate {
+                    axis: vec3(*axis),
+                    degrees: *degrees,
+                })
+            }
+            TransformDescription::RotateX(degrees) => Ok(TransformStep::Rotate {
+                axis: Vec3::new(1.0, 0.0, 0.0),
+                degrees: *degrees,
+            }),
+            TransformDescription::RotateY(degrees) => Ok(TransformStep::Rotate {
+                axis: Vec3::new(0.0, 1.0, 0.0),
+                degrees: *degrees,
+            }),
+            TransformDescription::RotateZ(degrees) => Ok(TransformStep::Rotate {
+                axis: Vec3::new(0.0, 0.0, 1.0),
+                degrees: *degrees,
+            }),
+        })
+        .collect()
+}
+
+// A built prototype, with its emissive shapes kept apart from the rest so that its instances
+// can be sampled as lights.
+struct Prototype {
+    objects: Option<Arc<dyn Hittable>>,
+    emitters: Option<Arc<dyn Hittable>>,
+}
+
+// The closed surface enclosing a participating medium, as a single hittable.
+fn build_boundary(
+    boundary: &ObjectDescription,
+    textures: &BTreeMap<&str, Arc<dyn Texture>>,
+    materials: &BTreeMap<&str, Arc<dyn Material>>,
+    prototypes: &BTreeMap<&str, Prototype>,
+    directory: &Path,
+) -> Result<Arc<dyn Hittable>, String> {
+    let mut objects = HittableList::new();
+    add_object(boundary, textures, materials, prototypes, directory, &mut objects, None)
+        .map_err(|message| format!("boundary ({}): {}", boundary.kind(), message))?;
+    Ok(match objects.objects() {
+        [object] => object.clone(),
+        _ => Arc::new(BvhNode::new(&objects)),
+    })
+}
+
+// Emissive shapes go to `emitters` instead of `world`, when given, so that they can be
+// sampled as lights. Planes are unbounded and cannot be sampled, so emissive planes are only
+// found by scattering into them, as are emitters inside a medium's boundary.
+fn add_object(
+    object: &ObjectDescription,
+    textures: &BTreeMap<&str, Arc<dyn Texture>>,
+    materials: &BTreeMap<&str, Arc<dyn Material>>,
+    prototypes: &BTreeMap<&str, Prototype>,
+    directory: &Path,
+    world: &mut HittableList,
+    emitters: Option<&mut HittableList>,
+) -> Result<(), String> {
+    match object {
+        ObjectDescription::Sphere {
+            centre,
+            radius,
+            material,
+        } => {
+            if *radius <= 0.0 {
+                return Err(String::from("radius must be positive"));
+            }
+            let material = lookup_material(materials, material)?;
+            let sphere = Arc::new(Sphere::new(vec3(*centre), *radius, material.clone()));
+            add_shape(sphere, &material, emitters, world);
+        }
+        ObjectDescription::MovingSphere {
+            centre0,
+            centre1,
+            time0,
+            time1,
+            radius,
+            material,
+        } => {
+            if *radius <= 0.0 {
+                return Err(String::from("radius must be positive"));
+            }
+            if time1 < time0 {
+                return Err(String::from("time1 must not be before time0"));
+            }
+            let material = lookup_material(materials, material)?;
+            let sphere = Arc::new(MovingSphere::new(
+                vec3(*centre0),
+                vec3(*centre1),
+                *time0,
+                *time1,
+                *radius,
+                material.clone(),
+            ));
+            add_shape(sphere, &material, emitters, world);
+        }
+        ObjectDescription::Triangle { vertices, material } => {
+            let material = lookup_material(materials, material)?;
+            let triangle = Arc::new(Triangle::new(
+                vec3(vertices[0]),
+                vec3(vertices[1]),
+                vec3(vertices[2]),
+                material.clone(),
+            ));
+            add_shape(triangle, &material, emitters, world);
+        }
+        ObjectDescription::Mesh { file, material } => {
+            let material = match material {
+                Some(name) => lookup_material(materials, name)?,
+                None => Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5))),
+            };
+            let model = load_obj(directory.join(file), material).map_err(|e| e.to_string())?;
+            let mut emitters = emitters;
+            for group in &model.groups {
+                for triangle in group.mesh.triangles().objects() {
+                    add_shape(triangle.clone(), group.mesh.material(), emitters.as_deref_mut(), world);
+                }
+            }
+        }
+        ObjectDescription::XyRect { x, y, z, material } => {
+            check_range("x", *x)?;
+            check_range("y", *y)?;
+            let material = lookup_material(materials, material)?;
+            add_shape(Arc::new(XYRect::new(x[0], x[1], y[0], y[1], *z, material.clone())), &material, emitters, world);
+        }
+        ObjectDescription::XzRect { x, z, y, material } => {
+            check_range("x", *x)?;
+            check_range("z", *z)?;
+            let material = lookup_material(materials, material)?;
+            add_shape(Arc::new(XZRect::new(x[0], x[1], z[0], z[1], *y, material.clone())), &material, emitters, world);
+        }
+        ObjectDescription::YzRect { y, z, x, material } => {
+            check_range("y", *y)?;
+            check_range("z", *z)?;
+            let material = lookup_material(materials, material)?;
+            add_shape(Arc::new(YZRect::new(y[0], y[1], z[0], z[1], *x, material.clone())), &material, emitters, world);
+        }
+        ObjectDescription::Box { min, max, material } => {
+            if (0..3).any(|axis| min[axis] >= max[axis]) {
+                return Err(String::from("min must be less than max on every axis"));
+            }
+            let material = lookup_material(materials, material)?;
+            add_shape(Arc::new(Cuboid::new(vec3(*min), vec3(*max), material.clone())), &material, emitters, world);
+        }
+        ObjectDescription::Plane { point, normal, material } => {
+            if vec3(*normal).near_zero() {
+                return Err(String::from("normal must not be zero"));
+            }
+            let material = lookup_material(materials, material)?;
+            world.add(Arc::new(Plane::new(vec3(*point), vec3(*normal), material)));
+        }
+        ObjectDescription::ConstantMedium {
+            boundary,
+            density,
+            albedo,
+        } => {
+            if *density <= 0.0 {
+                return Err(String::from("density must be positive"));
+            }
+            let boundary = build_boundary(boundary, textures, materials, prototypes, directory)?;
+            let albedo = lookup_colour_source(albedo, textures)?;
+            world.add(Arc::new(ConstantMedium::with_texture(boundary, *density, albedo)));
+        }
+        ObjectDescription::HeterogeneousMedium {
+            boundary,
+            density,
+            albedo,
+        } => {
+            let (density, grid_box): (Arc<dyn DensityField>, _) = match density {
+                DensityDescription::Noise {
+                    pattern,
+                    scale,
+                    seed,
+                    density,
+                } => {
+                    if *scale <= 0.0 {
+                        return Err(String::from("density: scale must be positive"));
+                    }
+                    if *density <= 0.0 {
+                        return Err(String::from("density: density must be positive"));
+                    }
+                    let noise = Arc::new(Perlin::new(*seed));
+                    (Arc::new(NoiseDensity::new(noise, pattern.build(), *scale, *density)), None)
+                }
+                DensityDescription::Grid { file, min, max } => {
+                    if (0..3).any(|axis| min[axis] >= max[axis]) {
+                        return Err(String::from("density: min must be less than max on every axis"));
+                    }
+                    let grid = load_voxel_grid(&directory.join(file), vec3(*min), vec3(*max))
+                        .map_err(|e| format!("density: {}", e))?;
+                    let grid_box = grid.bounding_box();
+                    (Arc::new(grid), Some(grid_box))
+                }
+            };
+            let boundary = match (boundary, grid_box) {
+                (Some(boundary), _) => build_boundary(boundary, textures, materials, prototypes, directory)?,
+                (None, Some(grid_box)) => Arc::new(Cuboid::new(grid_box.min, grid_box.max, Arc::new(EmptyMaterial))),
+                (None, None) => return Err(String::from("noise density needs a boundary")),
+            };
+            let albedo = lookup_colour_source(albedo, textures)?;
+            world.add(Arc::new(HeterogeneousMedium::new(boundary, density, albedo)));
+        }
+        ObjectDescription::Instance {
+            prototype,
+            transform,
+            end_transform,
+            time0,
+            time1,
+        } => {
+            let prototype = prototypes
+                .get(prototype.as_str())
+                .ok_or_else(|| format!("unknown prototype '{}'", prototype))?;
+            let steps = build_steps(transform)?;
+            let motion = match end_transform {
+                None => None,
+                Some(end_transform) => {
+                    if time1 < time0 {
+                        return Err(String::from("time1 must not be before time0"));
+                    }
+                    let motion = AnimatedTransform::new(steps.clone(), build_steps(end_transform)?, *time0, *time1)
+                        .ok_or("end_transform must list the same steps as transform, with the same rotation axes")?;
+                    Some(motion)
+                }
+            };
+            let place = |object: &Arc<dyn Hittable>| -> Result<Arc<dyn Hittable>, String> {
+                let instance = match &motion {
+                    None => Transformed::new(object.clone(), compose(&steps)),
+                    Some(motion) => Transformed::animated(object.clone(), motion.clone()),
+                };
+                Ok(Arc::new(instance.ok_or("transform must not be singular")?))
+            };
+            if let Some(objects) = &prototype.objects {
+                world.add(place(objects)?);
+            }
+            // The prototype's emitters are placed as one shape, sampled as a single light.
+            if let Some(prototype_emitters) = &prototype.emitters {
+                match emitters {
+                    Some(emitters) => emitters.add(place(prototype_emitters)?),
+                    None => world.add(place(prototype_emitters)?),
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Hittable;
+    use crate::image::{write_pfm, Framebuffer};
+    use crate::linear_algebra::Ray;
+
+    const SCENE: &str = r#"
+        [image]
+        width = 300
+        aspect_ratio = 1.5
+        samples_per_pixel = 10
+
+        [camera]
+        look_from = [0, 0, 0]
+        look_at = [0, 0, -1]
+        vertical_fov = 90
+
+        [background]
+        type = "solid"
+        colour = [0, 0, 0]
+
+        [materials.red]
+        type = "lambertian"
+        albedo = [0.8, 0.1, 0.1]
+
+        [materials.glass]
+        type = "dielectric"
+        refraction_index = 1.5
+
+        [[objects]]
+        type = "sphere"
+        centre = [0, 0, -2]
+        radius = 0.5
+        material = "red"
+
+        [[objects]]
+        type = "triangle"
+        vertices = [[-1, -1, -3], [1, -1, -3], [0, 1, -3]]
+        material = "glass"
+    "#;
+
+    fn parse(source: &str) -> Result<Scene, SceneError> {
+        parse_scene(source, Path::new("test.toml"))
+    }
+
+    mod parse_scene_tests {
+        use super::*;
+
+        #[test]
+        fn parse_valid_scene() {
+            let scene = parse(SCENE).unwrap();
+            let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+
+            assert_eq!(scene.settings.image_width, 300);
+            assert_eq!(scene.settings.image_height, 200);
+            assert_eq!(scene.settings.samples_per_pixel, 10);
+            assert_eq!(scene.settings.max_depth, 50);
+            assert_eq!(scene.world.len(), 2);
+            assert!((scene.world.hit(ray, 0.0, 100.0).unwrap().t - 1.5).abs() < 1e-12);
+            assert!(scene.background.colour(&ray).near_zero());
+        }
+
+        #[test]
+        fn unknown_material() {
+            let source = SCENE.replace("material = \"glass\"", "material = \"steel\"");
+            let error = parse(&source).err().unwrap();
+
+            assert_eq!(error.to_string(), "test.toml: objects[1] (triangle): unknown material 'steel'");
+        }
+
+        #[test]
+        fn invalid_values() {
+            let source = SCENE.replace("radius = 0.5", "radius = -0.5");
+            let error = parse(&source).err().unwrap();
+            assert_eq!(error.to_string(), "test.toml: objects[0] (sphere): radius must be positive");
+
+            let source = SCENE.replace("refraction_index = 1.5", "refraction_index = 0");
+            let error = parse(&source).err().unwrap();
+            assert_eq!(error.to_string(), "test.toml: materials.glass: refraction_index must be positive");
+
+            let source = SCENE.replace("colour = [0, 0, 0]", "colour = [0, -1, 0]");
+            let error = parse(&source).err().unwrap();
+            assert_eq!(error.to_string(), "test.toml: background colours must not be negative");
+
+            let source = SCENE.replace("aspect_ratio = 1.5", "aspect_ratio = 1.5\nheight = 100");
+            let error = parse(&source).err().unwrap();
+            assert_eq!(error.to_string(), "test.toml: image.height and image.aspect_ratio cannot both be given");
+        }
+
+        #[test]
+        fn syntax_errors() {
+            let source = SCENE.replace("type = \"sphere\"", "type = \"cube\"");
+            let error = parse(&source).err().unwrap();
+            assert!(error.message.contains("unknown variant `cube`"));
+
+            let error = parse("[camera]\nlook_from = [0, 0, 0]\n").err().unwrap();
+            assert!(error.message.contains("missing field `look_at`"));
+        }
+
+        #[test]
+        fn planar_objects() {
+            let source = format!(
+                "{}\n[[objects]]\ntype = \"box\"\nmin = [-1, -1, -6]\nmax = [1, 1, -4]\nmaterial = \"red\"\n\
+                 [[objects]]\ntype = \"plane\"\npoint = [0, -1, 0]\nnormal = [0, 1, 0]\nmaterial = \"red\"\n\
+                 [[objects]]\ntype = \"xz_rect\"\nx = [1, -1]\nz = [0, 1]\ny = 2\nmaterial = \"red\"\n",
+                SCENE
+            );
+            let error = parse(&source).err().unwrap();
+            assert_eq!(
+                error.to_string(),
+                "test.toml: objects[4] (xz_rect): x must be given as [min, max] with min < max"
+            );
+
+            let scene = parse(&source.replace("x = [1, -1]", "x = [-1, 1]")).unwrap();
+            assert_eq!(scene.world.len(), 5);
+        }
+
+        #[test]
+        fn missing_mesh_file() {
+            let source = format!("{}\n[[objects]]\ntype = \"mesh\"\nfile = \"missing.obj\"\n", SCENE);
+            let error = parse(&source).err().unwrap();
+            assert!(error.message.starts_with("objects[2] (mesh): missing.obj: "));
+        }
+
+        #[test]
+        fn textures() {
+            let source = SCENE.replace(
+                "albedo = [0.8, 0.1, 0.1]",
+                "albedo = \"board\"\n\
+                 [textures.board]\ntype = \"checker\"\neven = \"white\"\nodd = [0, 0, 0]\nscale = 0.5\n\
+                 [textures.white]\ntype = \"solid\"\ncolour = [1, 1, 1]",
+            );
+            assert!(parse(&source).is_ok());
+
+            let error = parse(&source.replace("albedo = \"board\"", "albedo = \"plaid\"")).err().unwrap();
+            assert_eq!(error.to_string(), "test.toml: materials.red: unknown texture 'plaid'");
+
+            let error = parse(&source.replace("even = \"white\"", "even = \"grey\"")).err().unwrap();
+            assert_eq!(error.to_string(), "test.toml: textures.board: unknown texture 'grey'");
+
+            let error = parse(&source.replace("scale = 0.5", "scale = 0")).err().unwrap();
+            assert_eq!(error.to_string(), "test.toml: textures.board: scale must be positive");
+
+            let cyclic = source.replace("type = \"solid\"\ncolour = [1, 1, 1]", "type = \"checker\"\neven = \"board\"\nodd = [1, 1, 1]");
+            let error = parse(&cyclic).err().unwrap();
+            assert_eq!(error.to_string(), "test.toml: textures.white: reference to 'board' forms a cycle");
+
+            let noisy = source.replace(
+                "type = \"solid\"\ncolour = [1, 1, 1]",
+                "type = \"noise\"\npattern = \"marble\"\nscale = 4\nseed = 9\nhigh = [0.9, 0.9, 0.8]\n\
+                 [materials.brushed]\ntype = \"metal\"\nalbedo = \"board\"\nfuzz = \"white\"",
+            );
+            assert!(parse(&noisy).is_ok());
+            let error = parse(&noisy.replace("fuzz = \"white\"", "fuzz = \"rough\"")).err().unwrap();
+            assert_eq!(error.to_string(), "test.toml: materials.brushed: unknown texture 'rough'");
+            let error = parse(&noisy.replace("pattern = \"marble\"", "pattern = \"granite\"")).err().unwrap();
+            assert!(error.message.contains("unknown variant `granite`"));
+
+            let missing = source.replace("type = \"solid\"\ncolour = [1, 1, 1]", "type = \"image\"\nfile = \"white.png\"\nwrap = \"clamp\"");
+            let error = parse(&missing).err().unwrap();
+            assert!(error.message.starts_with("textures.white: white.png: "));
+        }
+
+        #[test]
+        fn microfacet_materials() {
+            let source = format!(
+                "{}\n[materials.gold]\ntype = \"conductor\"\neta = [0.18, 0.42, 1.37]\nk = [3.42, 2.35, 1.77]\n\
+                 roughness = 0.3\nanisotropy = 0.5\n\
+                 [materials.frosted]\ntype = \"rough_dielectric\"\nrefraction_index = 1.5\nroughness = \"grain\"\n\
+                 [textures.grain]\ntype = \"noise\"\npattern = \"turbulence\"\nscale = 4\n",
+                SCENE
+            );
+            assert!(parse(&source).is_ok());
+
+            let error = parse(&source.replace("roughness = 0.3", "roughness = 1.3")).err().unwrap();
+            assert_eq!(error.to_string(), "test.toml: materials.gold: roughness must be between 0 and 1");
+            let error = parse(&source.replace("anisotropy = 0.5", "anisotropy = -0.5")).err().unwrap();
+            assert_eq!(error.to_string(), "test.toml: materials.gold: anisotropy must be between 0 and 1");
+            let error = parse(&source.replace("eta = [0.18,", "eta = [0,")).err().unwrap();
+            assert_eq!(error.to_string(), "test.toml: materials.gold: eta must be positive");
+            let error = parse(&source.replace("roughness = \"grain\"", "roughness = \"sand\"")).err().unwrap();
+            assert_eq!(error.to_string(), "test.toml: materials.frosted: unknown texture 'sand'");
+        }
+
+        #[test]
+        fn principled_material() {
+            let source = format!(
+                "{}\n[materials.car_paint]\ntype = \"principled\"\nbase_colour = [0.6, 0.05, 0.05]\nmetallic = 0.3\n\
+                 clearcoat = 1\nroughness = \"grain\"\n\
+                 [materials.default]\ntype = \"principled\"\n\
+                 [textures.grain]\ntype = \"noise\"\npattern = \"turbulence\"\nscale = 4\n",
+                SCENE
+            );
+            assert!(parse(&source).is_ok());
+
+            let error = parse(&source.replace("clearcoat = 1", "clearcoat = 2")).err().unwrap();
+            assert_eq!(error.to_string(), "test.toml: materials.car_paint: clearcoat must be between 0 and 1");
+            let error = parse(&source.replace("metallic = 0.3", "metallic = \"chrome\"")).err().unwrap();
+            assert_eq!(error.to_string(), "test.toml: materials.car_paint: unknown texture 'chrome'");
+            let error = parse(&source.replace("metallic = 0.3", "glossiness = 0.3")).err().unwrap();
+            assert!(error.message.contains("unknown field `glossiness`"));
+        }
+
+        #[test]
+        fn coloured_glass() {
+            let source = format!(
+                "{}\n[materials.wine]\ntype = \"dielectric\"\nrefraction_index = 1.34\nabsorption = [0.5, 4, 3]\n\
+                 [materials.bottle]\ntype = \"dielectric\"\nrefraction_index = 1.5\npriority = 1\n\
+                 [materials.frosted]\ntype = \"rough_dielectric\"\nrefraction_index = 1.5\nroughness = 0.3\n\
+                 absorption = [0.2, 0.1, 0.1]\npriority = 2\n",
+                SCENE
+            );
+            assert!(parse(&source).is_ok());
+
+            let error = parse(&source.replace("absorption = [0.5,", "absorption = [-0.5,")).err().unwrap();
+            assert_eq!(error.to_string(), "test.toml: materials.wine: absorption must not be negative");
+            let error = parse(&source.replace("priority = 1", "priority = -1")).err().unwrap();
+            assert!(error.message.contains("materials.bottle"));
+            let error = parse(&source.replace("absorption = [0.2,", "absorption = [-0.2,")).err().unwrap();
+            assert_eq!(error.to_string(), "test.toml: materials.frosted: absorption must not be negative");
+        }
+
+        #[test]
+        fn constant_medium() {
+            let source = format!(
+                "{}\n[[objects]]\ntype = \"constant_medium\"\ndensity = 0.5\nalbedo = [0.9, 0.9, 0.9]\n\
+                 boundary = {{ type = \"box\", min = [-1, -1, -6], max = [1, 1, -4], material = \"red\" }}\n",
+                SCENE
+            );
+            let scene = parse(&source).unwrap();
+            assert_eq!(scene.world.len(), 3);
+            assert!(scene.world.bounding_box().is_some());
+
+            let error = parse(&source.replace("density = 0.5", "density = 0")).err().unwrap();
+            assert_eq!(error.to_string(), "test.toml: objects[2] (constant_medium): density must be positive");
+
+            let error = parse(&source.replace("max = [1, 1, -4]", "max = [1, 1, -8]")).err().unwrap();
+            assert_eq!(
+                error.to_string(),
+                "test.toml: objects[2] (constant_medium): boundary (box): min must be less than max on every axis"
+            );
+        }
+
+        #[test]
+        fn heterogeneous_medium() {
+            let source = format!(
+                "{}\n[[objects]]\ntype = \"heterogeneous_medium\"\nalbedo = [0.9, 0.9, 0.9]\n\
+                 density = {{ type = \"noise\", pattern = \"cloud\", scale = 2, density = 4 }}\n\
+                 boundary = {{ type = \"sphere\", centre = [0, 0, -5], radius = 1, material = \"red\" }}\n",
+                SCENE
+            );
+            let scene = parse(&source).unwrap();
+            assert_eq!(scene.world.len(), 3);
+
+            let error = parse(&source.replace("density = 4", "density = -1")).err().unwrap();
+            assert_eq!(
+                error.to_string(),
+                "test.toml: objects[2] (heterogeneous_medium): density: density must be positive"
+            );
+
+            let unbounded = source.lines().filter(|line| !line.starts_with("boundary")).collect::<Vec<_>>().join("\n");
+            let error = parse(&unbounded).err().unwrap();
+            assert_eq!(error.to_string(), "test.toml: objects[2] (heterogeneous_medium): noise density needs a boundary");
+
+            // Grid densities fill their own box unless given a boundary.
+            let directory = std::env::temp_dir().join(format!("raytracer-scene-{}", std::process::id()));
+            fs::create_dir_all(&directory).unwrap();
+            fs::write(directory.join("puff.vox"), "voxels 2 1 1\n0 3\n").unwrap();
+            let source = format!(
+                "{}\n[[objects]]\ntype = \"heterogeneous_medium\"\n\
+                 density = {{ type = \"grid\", file = \"puff.vox\", min = [-1, -1, -6], max = [1, 1, -4] }}\n",
+                SCENE
+            );
+            let scene = parse_scene(&source, &directory.join("test.toml"));
+            fs::remove_dir_all(&directory).unwrap();
+            let bounding_box = scene.unwrap().world.objects()[2].bounding_box().unwrap();
+            assert_eq!(bounding_box.min.z(), -6.0);
+
+            let error = parse(&source).err().unwrap();
+            assert!(error.message.starts_with("objects[2] (heterogeneous_medium): density: puff.vox: "));
+        }
+
+        #[test]
+        fn lights() {
+            let source = format!(
+                "{}\n[[objects]]\ntype = \"sphere\"\ncentre = [0, 5, -5]\nradius = 1\nmaterial = \"lamp\"\n\
+                 [materials.lamp]\ntype = \"diffuse_light\"\nemit = [4, 4, 4]\n\
+                 [[lights]]\ntype = \"point\"\nposition = [0, 2, 0]\nintensity = [5, 5, 5]\n\
+                 [[lights]]\ntype = \"spot\"\nposition = [0, 2, 0]\ndirection = [0, -1, 0]\nintensity = [5, 5, 5]\n\
+                 cone_angle = 30\nfalloff = 5\n\
+                 [[lights]]\ntype = \"directional\"\ndirection = [0, -1, -1]\nirradiance = [2, 2, 2]\nangular_diameter = 0.5\n",
+                SCENE
+            );
+            let scene = parse(&source).unwrap();
+            assert_eq!(scene.world.len(), 3);
+            assert_eq!(scene.lights.len(), 4);
+
+            let error = parse(&source.replace("falloff = 5", "falloff = 45")).err().unwrap();
+            assert_eq!(error.to_string(), "test.toml: lights[1] (spot): falloff must be between 0 and cone_angle");
+            let error = parse(&source.replace("direction = [0, -1, -1]", "direction = [0, 0, 0]")).err().unwrap();
+            assert_eq!(error.to_string(), "test.toml: lights[2] (directional): direction must not be zero");
+            let error = parse(&source.replace("intensity = [5, 5, 5]\n[[", "intensity = [5, -5, 5]\n[[")).err().unwrap();
+            assert_eq!(error.to_string(), "test.toml: lights[0] (point): intensity must not be negative");
+        }
+
+        #[test]
+        fn emitters_in_meshes_and_instances() {
+            let directory = std::env::temp_dir().join(format!("raytracer-emitters-{}", std::process::id()));
+            fs::create_dir_all(&directory).unwrap();
+            fs::write(directory.join("panel.mtl"), "newmtl glow\nKe 5 5 5\nnewmtl matte\nKd 0.5 0.5 0.5\n").unwrap();
+            fs::write(
+                directory.join("panel.obj"),
+                "mtllib panel.mtl\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+                 usemtl glow\nf 1 2 3\nf 1 3 4\nusemtl matte\nf 1 2 4\n",
+            )
+            .unwrap();
+            let source = format!(
+                "{}\n[materials.lamp]\ntype = \"diffuse_light\"\nemit = [4, 4, 4]\n\
+                 [prototypes.fitting]\nobjects = [\
+                 {{ type = \"sphere\", centre = [0, 0, 0], radius = 0.2, material = \"lamp\" }}, \
+                 {{ type = \"box\", min = [-1, 0.2, -1], max = [1, 0.3, 1], material = \"red\" }}]\n\
+                 [[objects]]\ntype = \"mesh\"\nfile = \"panel.obj\"\n\
+                 [[objects]]\ntype = \"moving_sphere\"\ncentre0 = [0, 3, -5]\ncentre1 = [1, 3, -5]\n\
+                 radius = 0.5\nmaterial = \"lamp\"\n\
+                 [[objects]]\ntype = \"instance\"\nprototype = \"fitting\"\n\
+                 transform = [{{ scale = [2, 1, 1] }}, {{ translate = [0, 4, -4] }}]\n",
+                SCENE
+            );
+            let scene = parse_scene(&source, &directory.join("test.toml"));
+            fs::remove_dir_all(&directory).unwrap();
+
+            // Two glowing triangles, the moving sphere and the fitting's bulb become lights,
+            // while the matte triangle and the fitting's shade stay ordinary geometry.
+            let scene = scene.unwrap();
+            assert_eq!(scene.lights.len(), 4);
+            assert_eq!(scene.world.len(), 8);
+            let origin = Vec3::new(0.0, 0.0, -4.0);
+            for _ in 0..20 {
+                let sample = scene.lights.sample(origin, 0.5).unwrap();
+                assert!(sample.pdf > 0.0 && sample.radiance.x() > 0.0);
+            }
+        }
+
+        #[test]
+        fn environment() {
+            let directory = std::env::temp_dir().join(format!("raytracer-environment-{}", std::process::id()));
+            fs::create_dir_all(&directory).unwrap();
+            let mut sky = Framebuffer::new(2, 1);
+            sky.set(0, 0, Colour::new(3.0, 3.0, 3.0));
+            write_pfm(&mut fs::File::create(directory.join("sky.pfm")).unwrap(), &sky).unwrap();
+
+            let source = SCENE
+                .replace("type = \"solid\"", "type = \"environment\"")
+                .replace("colour = [0, 0, 0]", "file = \"sky.pfm\"\nintensity = 2");
+            let scene = parse_scene(&source, &directory.join("test.toml"));
+            fs::remove_dir_all(&directory).unwrap();
+
+            // The left half of the map lies towards -x and the right half towards +x.
+            let scene = scene.unwrap();
+            let left = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+            assert_eq!(scene.background.colour(&left).x(), 6.0);
+            assert!(scene.background.colour(&Ray::new(left.origin, -left.direction)).near_zero());
+            assert_eq!(scene.lights.len(), 1);
+            assert_eq!(scene.lights.environment(), Some(0));
+
+            let error = parse(&source.replace("intensity = 2", "intensity = -2")).err().unwrap();
+            assert_eq!(error.to_string(), "test.toml: background intensity must not be negative");
+            let error = parse(&source).err().unwrap();
+            assert!(error.message.starts_with("background: sky.pfm: "));
+        }
+
+        #[test]
+        fn sky() {
+            let source = SCENE
+                .replace("type = \"solid\"", "type = \"sky\"")
+                .replace("colour = [0, 0, 0]", "sun_direction = [1, 1, 0]\nturbidity = 4");
+            let scene = parse(&source).unwrap();
+            let up = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+            assert!(scene.background.colour(&up).z() > scene.background.colour(&up).x());
+            assert_eq!(scene.lights.environment(), Some(0));
+
+            let error = parse(&source.replace("[1, 1, 0]", "[1, -0.1, 0]")).err().unwrap();
+            assert_eq!(error.to_string(), "test.toml: background sun_direction must point above the horizon");
+            let error = parse(&source.replace("turbidity = 4", "turbidity = 12")).err().unwrap();
+            assert_eq!(error.to_string(), "test.toml: background turbidity must be between 1.7 and 10");
+            let error = parse(&source.replace("turbidity = 4", "ground_albedo = [0.2, 1.5, 0.2]")).err().unwrap();
+            assert_eq!(error.to_string(), "test.toml: background ground_albedo must be between 0 and 1");
+        }
+
+        #[test]
+        fn instances() {
+            let source = format!(
+                "{}\n[prototypes.ball]\nobjects = [{{ type = \"sphere\", centre = [0, 0, 0], radius = 1, material = \"red\" }}]\n\
+                 [[objects]]\ntype = \"instance\"\nprototype = \"ball\"\n\
+                 transform = [{{ scale = [1, 1, 0.5] }}, {{ rotate_y = 90 }}, {{ translate = [5, 0, 0] }}]\n",
+                SCENE
+            );
+            let scene = parse(&source).unwrap();
+            assert_eq!(scene.world.len(), 3);
+
+            // Rotating the squashed sphere about y turns its short z axis into the x axis.
+            let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+            let record = scene.world.hit(ray, 0.0, 100.0).unwrap();
+            assert!((record.t - 4.5).abs() < 1e-9);
+
+            let error = parse(&source.replace("prototype = \"ball\"", "prototype = \"cube\"")).err().unwrap();
+            assert_eq!(error.to_string(), "test.toml: objects[2] (instance): unknown prototype 'cube'");
+
+            let error = parse(&source.replace("scale = [1, 1, 0.5]", "scale = 0")).err().unwrap();
+            assert_eq!(error.to_string(), "test.toml: objects[2] (instance): transform must not be singular");
+        }
+
+        #[test]
+        fn motion() {
+            let source = format!(
+                "{}\n[prototypes.ball]\nobjects = [{{ type = \"sphere\", centre = [0, 0, 0], radius = 1, material = \"red\" }}]\n\
+                 [[objects]]\ntype = \"instance\"\nprototype = \"ball\"\n\
+                 transform = [{{ translate = [5, 0, 0] }}]\nend_transform = [{{ translate = [5, 0, 4] }}]\n\
+                 [[objects]]\ntype = \"moving_sphere\"\ncentre0 = [-5, 0, 0]\ncentre1 = [-5, 4, 0]\nradius = 1\nmaterial = \"red\"\n",
+                SCENE.replace("vertical_fov = 90", "vertical_fov = 90\nshutter_close = 1")
+            );
+            let scene = parse(&source).unwrap();
+            assert_eq!(scene.camera.shutter(), (0.0, 1.0));
+
+            let right = Vec3::new(1.0, 0.0, 0.0);
+            let left = Vec3::new(-1.0, 0.0, 0.0);
+            let origin = Vec3::new(0.0, 0.0, 0.0);
+            assert!((scene.world.hit(Ray::with_time(origin, right, 0.0), 0.0, 100.0).unwrap().t - 4.0).abs() < 1e-9);
+            assert!(scene.world.hit(Ray::with_time(origin, right, 1.0), 0.0, 100.0).is_none());
+            assert!((scene.world.hit(Ray::with_time(origin, left, 0.0), 0.0, 100.0).unwrap().t - 4.0).abs() < 1e-9);
+            assert!(scene.world.hit(Ray::with_time(origin, left, 1.0), 0.0, 100.0).is_none());
+
+            let error = parse(&source.replace("end_transform = [{ translate = [5, 0, 4] }]", "end_transform = [{ rotate_y = 4 }]"))
+                .err()
+                .unwrap();
+            assert_eq!(
+                error.to_string(),
+                "test.toml: objects[2] (instance): end_transform must list the same steps as transform, with the same rotation axes"
+            );
+
+            let error = parse(&source.replace("shutter_close = 1", "shutter_open = 1\nshutter_close = 0")).err().unwrap();
+            assert_eq!(error.to_string(), "test.toml: camera.shutter_close must not be before camera.shutter_open");
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use crate::background::Background;
+use crate::light::{Light, LightSample};
+use crate::linear_algebra::{random_in_cone, Onb, Ray, Vec3};
+use crate::{Colour, Point};
+use std::f64::consts::PI;
+
+// Luminance, in cd/m^2, that maps to a radiance of 1, so that a white surface in the midday
+// sun comes out a little under 1.
+const UNIT_LUMINANCE: f64 = 5.0e4;
+// The sun's luminance outside the atmosphere, in cd/m^2, and its angular radius.
+const SUN_LUMINANCE: f64 = 1.88e9;
+const SUN_RADIUS: f64 = 0.2665 * PI / 180.0;
+// Wavelengths, in micrometres, at which the atmosphere's transmittance is evaluated for the
+// red, green and blue channels of the sun.
+const WAVELENGTHS: [f64; 3] = [0.68, 0.55, 0.44];
+
+// The analytic daylight model of Preetham, Shirley and Smits (1999). The sky's luminance and
+// chromaticity in every direction follow from the sun's position and the turbidity, a
+// measure of haze from 2 (very clear) to 10 (hazy), and are converted to linear RGB.
+// Directions below the horizon see flat ground of the given albedo, lit by the sun and sky.
+// As a light it samples the sun's disc.
+pub struct PreethamSky {
+    sun_direction: Vec3,
+    // Perez coefficients A to E for luminance and the two chromaticity coordinates.
+    coefficients: [[f64; 5]; 3],
+    // Luminance and chromaticity at the zenith, divided by the Perez function there.
+    scale: [f64; 3],
+    intensity: f64,
+    sun_radiance: Colour,
+    cos_sun_radius: f64,
+    ground: Colour,
+}
+
+impl PreethamSky {
+    pub fn new(sun_direction: Vec3, turbidity: f64, ground_albedo: Colour, intensity: f64) -> Self {
+        let sun_direction = sun_direction.unit_vector();
+        let theta_s = sun_direction.y().clamp(0.0, 1.0).acos();
+        let t = turbidity;
+
+        let coefficients = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let luminance = 1000.0 * ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192);
+        let cubic = |c: [f64; 4]| ((c[0] * theta_s + c[1]) * theta_s + c[2]) * theta_s + c[3];
+        let x = t * t * cubic([0.00166, -0.00375, 0.00209, 0.0])
+            + t * cubic([-0.02903, 0.06377, -0.03202, 0.00394])
+            + cubic([0.11693, -0.21196, 0.06052, 0.25886]);
+        let y = t * t * cubic([0.00275, -0.00610, 0.00317, 0.0])
+            + t * cubic([-0.04214, 0.08970, -0.04153, 0.00516])
+            + cubic([0.15346, -0.26756, 0.06670, 0.26688]);
+
+        let mut scale = [0.0; 3];
+        for (i, zenith) in [luminance, x, y].iter().enumerate() {
+            scale[i] = zenith / perez(&coefficients[i], 1.0, theta_s.cos());
+        }
+
+        // Sunlight is thinned by Rayleigh scattering off air and by scattering off aerosols,
+        // whose amount grows with turbidity, along a path that lengthens towards the horizon.
+        let elevation = 90.0 - theta_s.to_degrees();
+        let air_mass = 1.0 / (theta_s.cos() + 0.15 * (elevation + 3.885).powf(-1.253));
+        let beta = 0.04608 * t - 0.04586;
+        let transmittance = |lambda: f64| {
+            (-0.008735 * lambda.powf(-4.08) * air_mass).exp() * (-beta * lambda.powf(-1.3) * air_mass).exp()
+        };
+        let sun_radiance = SUN_LUMINANCE / UNIT_LUMINANCE
+            * Colour::new(
+                transmittance(WAVELENGTHS[0]),
+                transmittance(WAVELENGTHS[1]),
+                transmittance(WAVELENGTHS[2]),
+            );
+
+        let mut sky = Self {
+            sun_direction,
+            coefficients,
+            scale,
+            intensity,
+            sun_radiance,
+            cos_sun_radius: SUN_RADIUS.cos(),
+            ground: Colour::new(0.0, 0.0, 0.0),
+        };
+
+        // The ground reflects the irradiance it receives from the sun and the sky diffusely.
+        let solid_angle = 2.0 * PI * (1.0 - sky.cos_sun_radius);
+        let irradiance = sky.sky_irradiance() + solid_angle * sun_direction.y() * sun_radiance;
+        sky.ground = ground_albedo * irradiance / PI;
+        sky
+    }
+
+    // The radiance of the sky alone, without the sun or the intensity applied, towards a unit
+    // direction above the horizon.
+    fn sky_radiance(&self, direction: Vec3) -> Colour {
+        let cos_theta = direction.y().max(1e-3);
+        let cos_gamma = direction.dot(&self.sun_direction).clamp(-1.0, 1.0);
+        let value = |i: usize| self.scale[i] * perez(&self.coefficients[i], cos_theta, cos_gamma);
+        let (luminance, x, y) = (value(0) / UNIT_LUMINANCE, value(1), value(2));
+
+        // xyY to XYZ to linear sRGB.
+        let big_x = x / y * luminance;
+        let big_z = (1.0 - x - y) / y * luminance;
+        Colour::new(
+            (3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z).max(0.0),
+            (-0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z).max(0.0),
+            (0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z).max(0.0),
+        )
+    }
+
+    // The irradiance the sky alone gives to an upward-facing surface, integrated numerically.
+    fn sky_irradiance(&self) -> Colour {
+        let (rings, segments) = (32, 64);
+        let mut irradiance = Colour::new(0.0, 0.0, 0.0);
+        for i in 0..rings {
+            let theta = 0.5 * PI * (i as f64 + 0.5) / rings as f64;
+            for j in 0..segments {
+                let phi = 2.0 * PI * (j as f64 + 0.5) / segments as f64;
+                let direction = Vec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+                irradiance += theta.cos() * theta.sin() * self.sky_radiance(direction);
+            }
+        }
+        irradiance * (0.5 * PI / rings as f64) * (2.0 * PI / segments as f64)
+    }
+
+    fn radiance(&self, direction: Vec3) -> Colour {
+        if direction.y() < 0.0 {
+            return self.intensity * self.ground;
+        }
+        let mut radiance = self.sky_radiance(direction);
+        if direction.dot(&self.sun_direction) >= self.cos_sun_radius {
+            radiance += self.sun_radiance;
+        }
+        self.intensity * radiance
+    }
+}
+
+// The Perez sky function, giving the relative brightness at zenith angle theta and angle
+// gamma from the sun.
+fn perez(c: &[f64; 5], cos_theta: f64, cos_gamma: f64) -> f64 {
+    let gamma = cos_gamma.clamp(-1.0, 1.0).acos();
+    (1.0 + c[0] * (c[1] / cos_theta).exp()) * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * cos_gamma * cos_gamma)
+}
+
+impl Background for PreethamSky {
+    fn colour(&self, ray: &Ray) -> Colour {
+        self.radiance(ray.direction.unit_vector())
+    }
+}
+
+impl Light for PreethamSky {
+    fn sample(&self, _origin: Point, _time: f64) -> Option<LightSample> {
+        let local = random_in_cone(self.cos_sun_radius);
+        let direction = Onb::new(self.sun_direction).local(local.x(), local.y(), local.z());
+        Some(LightSample {
+            direction,
+            distance: f64::INFINITY,
+            radiance: self.radiance(direction),
+            pdf: 1.0 / (2.0 * PI * (1.0 - self.cos_sun_radius)),
+            hittable: true,
+        })
+    }
+
+    fn pdf(&self, _origin: Point, direction: Vec3, _time: f64) -> f64 {
+        if direction.unit_vector().dot(&self.sun_direction) >= self.cos_sun_radius {
+            1.0 / (2.0 * PI * (1.0 - self.cos_sun_radius))
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod preetham_sky_tests {
+        use super::*;
+
+        fn sky(elevation: f64, turbidity: f64) -> PreethamSky {
+            let elevation = elevation.to_radians();
+            let sun = Vec3::new(0.0, elevation.sin(), -elevation.cos());
+            PreethamSky::new(sun, turbidity, Colour::new(0.3, 0.3, 0.3), 1.0)
+        }
+
+        fn luminance(colour: Colour) -> f64 {
+            0.2126 * colour.x() + 0.7152 * colour.y() + 0.0722 * colour.z()
+        }
+
+        #[test]
+        fn zenith_matches_model() {
+            // With the sun at 60 degrees from the zenith and turbidity 3, the model gives a
+            // zenith luminance of 5.14 kcd/m^2 with a blue chromaticity of (0.245, 0.253).
+            let sky = sky(30.0, 3.0);
+            let zenith = sky.colour(&Ray::new(Point::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)));
+            assert!((luminance(zenith) * UNIT_LUMINANCE - 5137.0).abs() < 10.0);
+            assert!(zenith.z() > zenith.y() && zenith.y() > zenith.x());
+        }
+
+        #[test]
+        fn brightens_towards_the_sun() {
+            let sky = sky(20.0, 3.0);
+            let near_sun = Vec3::new(0.0, 0.5, -1.0).unit_vector();
+            let away = Vec3::new(0.0, 0.5, 1.0).unit_vector();
+            let at = |direction| luminance(sky.sky_radiance(direction));
+            assert!(at(near_sun) > 2.0 * at(away));
+        }
+
+        #[test]
+        fn sun_reddens_towards_the_horizon() {
+            let noon = sky(80.0, 3.0).sun_radiance;
+            let sunset = sky(3.0, 3.0).sun_radiance;
+            assert!(noon.x() > sunset.x() && noon.z() > sunset.z());
+            assert!(sunset.z() / sunset.x() < 0.5 * noon.z() / noon.x());
+
+            // Haze dims the sun.
+            assert!(sky(40.0, 8.0).sun_radiance.y() < sky(40.0, 2.0).sun_radiance.y());
+        }
+
+        #[test]
+        fn ground_reflects_sun_and_sky() {
+            // Flat ground of albedo 0.3 reflects 0.3 / pi of the irradiance it receives.
+            let sky = sky(45.0, 3.0);
+            let down = Ray::new(Point::new(0.0, 0.0, 0.0), Vec3::new(0.2, -1.0, 0.0));
+            let sun_irradiance = 2.0 * PI * (1.0 - sky.cos_sun_radius) * sky.sun_direction.y() * sky.sun_radiance;
+            let expected = 0.3 * (sun_irradiance + sky.sky_irradiance()) / PI;
+            assert!((sky.colour(&down) - expected).near_zero());
+            assert!(sky.colour(&down).y() > 0.3 * sun_irradiance.y() / PI);
+        }
+
+        #[test]
+        fn samples_the_sun() {
+            crate::random::seed(3);
+            let sky = sky(35.0, 4.0);
+            let origin = Point::new(0.0, 0.0, 0.0);
+            for _ in 0..100 {
+                let sample = sky.sample(origin, 0.0).unwrap();
+                assert!(sample.direction.dot(&sky.sun_direction) >= sky.cos_sun_radius - 1e-12);
+                assert!((sample.pdf - sky.pdf(origin, sample.direction, 0.0)).abs() < 1e-6 * sample.pdf);
+                assert!(sample.radiance.y() > sky.sun_radiance.y());
+            }
+            assert_eq!(sky.pdf(origin, Vec3::new(0.0, 1.0, 0.0), 0.0), 0.0);
+        }
+    }
+}
@@ -0,0 +1,295 @@
+use crate::image::Framebuffer;
+use crate::noise::Perlin;
+use crate::{Colour, Point};
+use std::sync::Arc;
+
+pub trait Texture: Send + Sync {
+    fn value(&self, u: f64, v: f64, point: Point) -> Colour;
+
+    // Textures driving a single parameter, such as roughness, are read by their luminance.
+    fn scalar(&self, u: f64, v: f64, point: Point) -> f64 {
+        let colour = self.value(u, v, point);
+        0.2126 * colour.x() + 0.7152 * colour.y() + 0.0722 * colour.z()
+    }
+}
+
+pub struct SolidColour {
+    colour: Colour,
+}
+
+impl SolidColour {
+    pub fn new(colour: Colour) -> Self {
+        Self { colour }
+    }
+}
+
+impl Texture for SolidColour {
+    fn value(&self, _u: f64, _v: f64, _point: Point) -> Colour {
+        self.colour
+    }
+}
+
+// A solid 3D checkerboard of cubes with sides of length `scale`, so it needs no surface
+// parameterisation and never stretches across curved surfaces.
+pub struct CheckerTexture {
+    even: Arc<dyn Texture>,
+    odd: Arc<dyn Texture>,
+    scale: f64,
+}
+
+impl CheckerTexture {
+    pub fn new(even: Arc<dyn Texture>, odd: Arc<dyn Texture>, scale: f64) -> Self {
+        Self { even, odd, scale }
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f64, v: f64, point: Point) -> Colour {
+        let cell = (point.x() / self.scale).floor() + (point.y() / self.scale).floor() + (point.z() / self.scale).floor();
+
+        if cell.rem_euclid(2.0) == 0.0 {
+            self.even.value(u, v, point)
+        } else {
+            self.odd.value(u, v, point)
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum NoisePattern {
+    // Creased, cloudy noise.
+    Turbulence,
+    // Veins running along z, distorted by turbulence.
+    Marble,
+    // Growth rings around the y axis, made irregular by noise.
+    Wood,
+    // Soft fractal Brownian motion.
+    Cloud,
+}
+
+const NOISE_OCTAVES: usize = 7;
+
+impl NoisePattern {
+    // Evaluates the pattern at `point`, giving a value in [0, 1].
+    pub fn evaluate(&self, noise: &Perlin, p: Point) -> f64 {
+        let value = match self {
+            NoisePattern::Turbulence => noise.turbulence(p, NOISE_OCTAVES),
+            NoisePattern::Marble => 0.5 * (1.0 + (4.0 * p.z() + 10.0 * noise.turbulence(p, NOISE_OCTAVES)).sin()),
+            NoisePattern::Wood => {
+                let rings = 4.0 * (p.x() * p.x() + p.z() * p.z()).sqrt() + 2.0 * noise.noise(p);
+                rings - rings.floor()
+            }
+            NoisePattern::Cloud => 0.5 * (1.0 + noise.fbm(p, NOISE_OCTAVES, 2.0, 0.5)),
+        };
+        value.clamp(0.0, 1.0)
+    }
+}
+
+// A procedural solid texture that blends from `low` to `high` according to a noise pattern
+// evaluated at `scale` times the hit point.
+pub struct NoiseTexture {
+    noise: Arc<Perlin>,
+    pattern: NoisePattern,
+    scale: f64,
+    low: Arc<dyn Texture>,
+    high: Arc<dyn Texture>,
+}
+
+impl NoiseTexture {
+    pub fn new(
+        noise: Arc<Perlin>,
+        pattern: NoisePattern,
+        scale: f64,
+        low: Arc<dyn Texture>,
+        high: Arc<dyn Texture>,
+    ) -> Self {
+        Self {
+            noise,
+            pattern,
+            scale,
+            low,
+            high,
+        }
+    }
+
+    // The blend factor between `low` and `high`, in [0, 1].
+    pub fn blend(&self, point: Point) -> f64 {
+        self.pattern.evaluate(&self.noise, self.scale * point)
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, u: f64, v: f64, point: Point) -> Colour {
+        let blend = self.blend(point);
+        (1.0 - blend) * self.low.value(u, v, point) + blend * self.high.value(u, v, point)
+    }
+}
+
+// How texel lookups outside the image are brought back inside it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WrapMode {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl WrapMode {
+    fn apply(&self, index: isize, size: usize) -> usize {
+        let size = size as isize;
+        let index = match self {
+            WrapMode::Repeat => index.rem_euclid(size),
+            WrapMode::Clamp => index.clamp(0, size - 1),
+            WrapMode::Mirror => {
+                let index = index.rem_euclid(2 * size);
+                if index < size {
+                    index
+                } else {
+                    2 * size - 1 - index
+                }
+            }
+        };
+        index as usize
+    }
+}
+
+// Samples a linear-colour image with bilinear filtering. (0, 0) is the bottom-left corner of
+// the image and (1, 1) the top-right.
+pub struct ImageTexture {
+    image: Arc<Framebuffer>,
+    wrap: WrapMode,
+}
+
+impl ImageTexture {
+    pub fn new(image: Arc<Framebuffer>, wrap: WrapMode) -> Self {
+        Self { image, wrap }
+    }
+
+    fn texel(&self, x: isize, y: isize) -> Colour {
+        self.image.get(
+            self.wrap.apply(x, self.image.width()),
+            self.wrap.apply(y, self.image.height()),
+        )
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _point: Point) -> Colour {
+        if self.image.width() == 0 || self.image.height() == 0 || !u.is_finite() || !v.is_finite() {
+            return Colour::new(0.0, 0.0, 0.0);
+        }
+
+        // Texel centres sit at half-integer coordinates, and image rows run top to bottom.
+        let x = u * self.image.width() as f64 - 0.5;
+        let y = (1.0 - v) * self.image.height() as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as isize, y0 as isize);
+
+        let top = (1.0 - fx) * self.texel(x0, y0) + fx * self.texel(x0 + 1, y0);
+        let bottom = (1.0 - fx) * self.texel(x0, y0 + 1) + fx * self.texel(x0 + 1, y0 + 1);
+        (1.0 - fy) * top + fy * bottom
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod checker_texture_tests {
+        use super::*;
+
+        #[test]
+        fn alternates_cells() {
+            let white = Arc::new(SolidColour::new(Colour::new(1.0, 1.0, 1.0)));
+            let black = Arc::new(SolidColour::new(Colour::new(0.0, 0.0, 0.0)));
+            let checker = CheckerTexture::new(white, black, 0.5);
+
+            assert!(approx_eq(checker.value(0.0, 0.0, Point::new(0.25, 0.25, 0.25)).x(), 1.0));
+            assert!(approx_eq(checker.value(0.0, 0.0, Point::new(0.75, 0.25, 0.25)).x(), 0.0));
+            assert!(approx_eq(checker.value(0.0, 0.0, Point::new(0.75, 0.75, 0.25)).x(), 1.0));
+            assert!(approx_eq(checker.value(0.0, 0.0, Point::new(-0.25, 0.25, 0.25)).x(), 0.0));
+            assert!(approx_eq(checker.value(0.0, 0.0, Point::new(-0.25, -0.25, -0.25)).x(), 0.0));
+        }
+    }
+
+    mod image_texture_tests {
+        use super::*;
+
+        // A 2x1 image: black on the left, white on the right.
+        fn texture(wrap: WrapMode) -> ImageTexture {
+            let mut image = Framebuffer::new(2, 1);
+            image.set(1, 0, Colour::new(1.0, 1.0, 1.0));
+            ImageTexture::new(Arc::new(image), wrap)
+        }
+
+        fn sample(texture: &ImageTexture, u: f64) -> f64 {
+            texture.value(u, 0.5, Point::new(0.0, 0.0, 0.0)).x()
+        }
+
+        #[test]
+        fn bilinear() {
+            let texture = texture(WrapMode::Clamp);
+
+            assert!(approx_eq(sample(&texture, 0.25), 0.0));
+            assert!(approx_eq(sample(&texture, 0.75), 1.0));
+            assert!(approx_eq(sample(&texture, 0.5), 0.5));
+            assert!(approx_eq(sample(&texture, 0.375), 0.25));
+        }
+
+        #[test]
+        fn wrap_modes() {
+            // Just past the right-hand texel centre, repeat blends in the black texel from the
+            // opposite edge while clamp and mirror reuse the white one.
+            let u = 0.875;
+            assert!(approx_eq(sample(&texture(WrapMode::Clamp), u), 1.0));
+            assert!(approx_eq(sample(&texture(WrapMode::Mirror), u), 1.0));
+            assert!(approx_eq(sample(&texture(WrapMode::Repeat), u), 0.75));
+
+            assert!(approx_eq(sample(&texture(WrapMode::Repeat), 1.25), 0.0));
+            assert!(approx_eq(sample(&texture(WrapMode::Clamp), 1.25), 1.0));
+            assert!(approx_eq(sample(&texture(WrapMode::Mirror), 1.25), 1.0));
+            assert!(approx_eq(sample(&texture(WrapMode::Mirror), 1.75), 0.0));
+        }
+    }
+
+    mod noise_texture_tests {
+        use super::*;
+
+        #[test]
+        fn patterns_blend_within_range() {
+            let noise = Arc::new(Perlin::new(5));
+            let low = Arc::new(SolidColour::new(Colour::new(0.0, 0.0, 1.0)));
+            let high = Arc::new(SolidColour::new(Colour::new(1.0, 1.0, 0.0)));
+            let patterns = [
+                NoisePattern::Turbulence,
+                NoisePattern::Marble,
+                NoisePattern::Wood,
+                NoisePattern::Cloud,
+            ];
+
+            for pattern in patterns {
+                let texture = NoiseTexture::new(noise.clone(), pattern, 4.0, low.clone(), high.clone());
+                let mut blends = Vec::new();
+                for i in 0..100 {
+                    let point = Point::new(i as f64 * 0.137, i as f64 * 0.071, -(i as f64) * 0.093);
+                    let blend = texture.blend(point);
+                    let colour = texture.value(0.0, 0.0, point);
+                    assert!((0.0..=1.0).contains(&blend));
+                    assert!(approx_eq(colour.x(), blend) && approx_eq(colour.z(), 1.0 - blend));
+                    blends.push(blend);
+                }
+                assert!(blends.iter().any(|&b| (b - blends[0]).abs() > 0.05), "{:?} is flat", pattern);
+            }
+        }
+
+        #[test]
+        fn scalar_is_luminance() {
+            let grey = SolidColour::new(Colour::new(0.25, 0.25, 0.25));
+            assert!((grey.scalar(0.0, 0.0, Point::new(0.0, 0.0, 0.0)) - 0.25).abs() < 1e-12);
+        }
+    }
+
+    fn approx_eq(x: f64, y: f64) -> bool {
+        (x - y).abs() < f64::EPSILON
+    }
+}
@@ -0,0 +1,482 @@
+use crate::geometry::{Aabb, HitRecord, Hittable};
+use crate::linear_algebra::{Mat4, Ray, Vec3};
+use crate::Point;
+use std::sync::Arc;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TransformStep {
+    Translate(Vec3),
+    Scale(Vec3),
+    Rotate { axis: Vec3, degrees: f64 },
+}
+
+impl TransformStep {
+    pub fn matrix(&self) -> Mat4 {
+        match *self {
+            TransformStep::Translate(offset) => Mat4::translation(offset),
+            TransformStep::Scale(factors) => Mat4::scaling(factors),
+            TransformStep::Rotate { axis, degrees } => Mat4::rotation(axis, degrees),
+        }
+    }
+
+    // The inverse of `matrix`, built directly rather than by general inversion, or None for a
+    // scale by zero.
+    fn inverse_matrix(&self) -> Option<Mat4> {
+        match *self {
+            TransformStep::Translate(offset) => Some(Mat4::translation(-offset)),
+            TransformStep::Scale(factors) => {
+                if factors.x() == 0.0 || factors.y() == 0.0 || factors.z() == 0.0 {
+                    return None;
+                }
+                Some(Mat4::scaling(Vec3::new(1.0 / factors.x(), 1.0 / factors.y(), 1.0 / factors.z())))
+            }
+            TransformStep::Rotate { axis, degrees } => Some(Mat4::rotation(axis, -degrees)),
+        }
+    }
+
+    // Blends the parameters of two steps of the same kind, so rotations sweep through the
+    // intermediate angles rather than shearing as a blended matrix would.
+    fn lerp(&self, other: &TransformStep, s: f64) -> Option<TransformStep> {
+        match (*self, *other) {
+            (TransformStep::Translate(a), TransformStep::Translate(b)) => {
+                Some(TransformStep::Translate(a + s * (b - a)))
+            }
+            (TransformStep::Scale(a), TransformStep::Scale(b)) => Some(TransformStep::Scale(a + s * (b - a))),
+            (
+                TransformStep::Rotate { axis, degrees: a },
+                TransformStep::Rotate {
+                    axis: other_axis,
+                    degrees: b,
+                },
+            ) if axis.unit_vector().dot(&other_axis.unit_vector()) > 1.0 - 1e-9 => Some(TransformStep::Rotate {
+                axis,
+                degrees: a + s * (b - a),
+            }),
+            _ => None,
+        }
+    }
+}
+
+// Steps are applied in order, so the first step is the innermost matrix.
+pub fn compose(steps: &[TransformStep]) -> Mat4 {
+    steps
+        .iter()
+        .fold(Mat4::identity(), |matrix, step| step.matrix() * matrix)
+}
+
+// Part of an animated transform: either a run of steps that are the same at both ends of
+// the motion, composed once up front along with its inverse (None if singular), or a single
+// step that changes over the motion.
+#[derive(Debug, Clone)]
+enum Segment {
+    Fixed { transform: Mat4, inverse: Option<Mat4> },
+    Moving(TransformStep, TransformStep),
+}
+
+// A transform that changes linearly from `start` at `time0` to `end` at `time1`, holding
+// still outside that interval. Both lists must have the same steps in the same order.
+#[derive(Debug, Clone)]
+pub struct AnimatedTransform {
+    segments: Vec<Segment>,
+    time0: f64,
+    time1: f64,
+}
+
+impl AnimatedTransform {
+    pub fn new(start: Vec<TransformStep>, end: Vec<TransformStep>, time0: f64, time1: f64) -> Option<Self> {
+        let matching = start.len() == end.len() && start.iter().zip(&end).all(|(a, b)| a.lerp(b, 0.0).is_some());
+        if !matching || time1 < time0 {
+            return None;
+        }
+
+        let mut segments = Vec::new();
+        let mut fixed: Option<(Mat4, Option<Mat4>)> = None;
+        for (a, b) in start.into_iter().zip(end) {
+            if a == b {
+                let (transform, inverse) = fixed.unwrap_or((Mat4::identity(), Some(Mat4::identity())));
+                let inverse = inverse.zip(a.inverse_matrix()).map(|(inverse, step)| inverse * step);
+                fixed = Some((a.matrix() * transform, inverse));
+            } else {
+                if let Some((transform, inverse)) = fixed.take() {
+                    segments.push(Segment::Fixed { transform, inverse });
+                }
+                segments.push(Segment::Moving(a, b));
+            }
+        }
+        if let Some((transform, inverse)) = fixed {
+            segments.push(Segment::Fixed { transform, inverse });
+        }
+
+        Some(Self {
+            segments,
+            time0,
+            time1,
+        })
+    }
+
+    fn progress(&self, time: f64) -> f64 {
+        if self.time1 > self.time0 {
+            ((time - self.time0) / (self.time1 - self.time0)).clamp(0.0, 1.0)
+        } else {
+            0.0
+        }
+    }
+
+    pub fn at(&self, time: f64) -> Mat4 {
+        let s = self.progress(time);
+        self.segments.iter().fold(Mat4::identity(), |matrix, segment| match segment {
+            Segment::Fixed { transform, .. } => *transform * matrix,
+            Segment::Moving(a, b) => a.lerp(b, s).unwrap().matrix() * matrix,
+        })
+    }
+
+    // The transform at the given time and its inverse, or None if it is singular then. The
+    // inverse is assembled from the inverses of the steps in reverse order.
+    pub fn matrices_at(&self, time: f64) -> Option<(Mat4, Mat4)> {
+        let s = self.progress(time);
+        self.segments
+            .iter()
+            .try_fold((Mat4::identity(), Mat4::identity()), |(matrix, inverse), segment| {
+                let (step, step_inverse) = match segment {
+                    Segment::Fixed { transform, inverse } => (*transform, (*inverse)?),
+                    Segment::Moving(a, b) => {
+                        let step = a.lerp(b, s).unwrap();
+                        (step.matrix(), step.inverse_matrix()?)
+                    }
+                };
+                Some((step * matrix, inverse * step_inverse))
+            })
+    }
+
+    fn total_rotation_degrees(&self) -> f64 {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Moving(TransformStep::Rotate { degrees: a, .. }, TransformStep::Rotate { degrees: b, .. }) => {
+                    (b - a).abs()
+                }
+                _ => 0.0,
+            })
+            .sum()
+    }
+}
+
+// Static instances are by far the common case, so their matrices are kept inline rather than
+// boxed to match the size of the animated variant.
+#[allow(clippy::large_enum_variant)]
+enum Motion {
+    Static { transform: Mat4, inverse: Mat4 },
+    Animated(AnimatedTransform),
+}
+
+// An instance of a shared object placed in the world by an affine transform. Rays are taken
+// into object space, so the wrapped object (and any BVH inside it) is never duplicated.
+pub struct Transformed {
+    object: Arc<dyn Hittable>,
+    motion: Motion,
+    bounding_box: Option<Aabb>,
+}
+
+// Number of instants at which an animated instance's bounding box is sampled.
+const MOTION_SAMPLES: usize = 32;
+
+impl Transformed {
+    // Returns None when the transform is singular and so cannot be inverted.
+    pub fn new(object: Arc<dyn Hittable>, transform: Mat4) -> Option<Self> {
+        let inverse = transform.inverse()?;
+        let bounding_box = object
+            .bounding_box()
+            .map(|bounding_box| transform_box(&bounding_box, &transform));
+
+        Some(Self {
+            object,
+            motion: Motion::Static { transform, inverse },
+            bounding_box,
+        })
+    }
+
+    // Returns None when the transform becomes singular at any sampled time.
+    pub fn animated(object: Arc<dyn Hittable>, motion: AnimatedTransform) -> Option<Self> {
+        let mut bounding_box = object.bounding_box().map(|_| Aabb::empty());
+        for i in 0..MOTION_SAMPLES {
+            let time = motion.time0 + (motion.time1 - motion.time0) * i as f64 / (MOTION_SAMPLES - 1) as f64;
+            let (transform, _) = motion.matrices_at(time)?;
+            if let (Some(swept), Some(object_box)) = (bounding_box, object.bounding_box()) {
+                bounding_box = Some(swept.union(&transform_box(&object_box, &transform)));
+            }
+        }
+
+        // Between samples a rotating corner bulges past the chord joining its sampled
+        // positions by at most r(1 - cos(θ/2)), so pad the swept box by that much.
+        let step_angle = motion.total_rotation_degrees().to_radians() / (MOTION_SAMPLES - 1) as f64;
+        let bounding_box = bounding_box.map(|swept| {
+            let radius = swept.min.length().max(swept.max.length());
+            let pad = radius * (1.0 - (step_angle / 2.0).cos()) + 1e-9;
+            let pad = Vec3::new(pad, pad, pad);
+            Aabb::new(swept.min - pad, swept.max + pad)
+        });
+
+        Some(Self {
+            object,
+            motion: Motion::Animated(motion),
+            bounding_box,
+        })
+    }
+
+    // The object-to-world transform and its inverse at the given time, or None if the
+    // transform is singular then.
+    fn matrices(&self, time: f64) -> Option<(Mat4, Mat4)> {
+        match &self.motion {
+            Motion::Static { transform, inverse } => Some((*transform, *inverse)),
+            Motion::Animated(motion) => motion.matrices_at(time),
+        }
+    }
+}
+
+fn transform_box(bounding_box: &Aabb, transform: &Mat4) -> Aabb {
+    let mut result = Aabb::empty();
+    for corner in 0..8 {
+        let x = if corner & 1 == 0 { bounding_box.min.x() } else { bounding_box.max.x() };
+        let y = if corner & 2 == 0 { bounding_box.min.y() } else { bounding_box.max.y() };
+        let z = if corner & 4 == 0 { bounding_box.min.z() } else { bounding_box.max.z() };
+        result = result.include(transform.transform_point(Point::new(x, y, z)));
+    }
+    result
+}
+
+impl Hittable for Transformed {
+    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let (transform, inverse) = self.matrices(ray.time)?;
+        let local_ray = local_ray(&inverse, ray);
+
+        let mut record = self.object.hit(local_ray, t_min, t_max)?;
+        record.hit_point = transform.transform_point(record.hit_point);
+        // Normals transform by the inverse transpose, which also preserves the sign of their
+        // dot product with the ray direction, so front_face carries over unchanged.
+        record.normal = inverse.transpose().transform_vector(record.normal).unit_vector();
+        record.tangent = transform.transform_vector(record.tangent);
+
+        Some(record)
+    }
+
+    fn transmittance(&self, ray: Ray, t_min: f64, t_max: f64) -> f64 {
+        match self.matrices(ray.time) {
+            Some((_, inverse)) => self.object.transmittance(local_ray(&inverse, ray), t_min, t_max),
+            None => 1.0,
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bounding_box
+    }
+
+    fn random_direction(&self, origin: Point, time: f64) -> Option<Vec3> {
+        let (transform, inverse) = self.matrices(time)?;
+        let direction = self.object.random_direction(inverse.transform_point(origin), time)?;
+        Some(transform.transform_vector(direction))
+    }
+
+    // Directions are carried into object space by the inverse's linear part A, which scales
+    // solid angle around a unit direction w by |det A| / |A w|^3, so the object's density is
+    // scaled by the same factor.
+    fn pdf_value(&self, origin: Point, direction: Vec3, time: f64) -> f64 {
+        let Some((_, inverse)) = self.matrices(time) else {
+            return 0.0;
+        };
+        let local = inverse.transform_vector(direction.unit_vector());
+        let length = local.length();
+        let column = |x: f64, y: f64, z: f64| inverse.transform_vector(Vec3::new(x, y, z));
+        let determinant = column(1.0, 0.0, 0.0).dot(&column(0.0, 1.0, 0.0).cross(&column(0.0, 0.0, 1.0)));
+
+        let pdf = self.object.pdf_value(inverse.transform_point(origin), local / length, time);
+        pdf * determinant.abs() / (length * length * length)
+    }
+}
+
+// The direction is deliberately left unnormalised so that t means the same thing in both
+// spaces.
+fn local_ray(inverse: &Mat4, ray: Ray) -> Ray {
+    Ray::with_time(
+        inverse.transform_point(ray.origin),
+        inverse.transform_vector(ray.direction),
+        ray.time,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Sphere;
+    use crate::linear_algebra::Vec3;
+    use crate::materials::EmptyMaterial;
+
+    mod transformed_tests {
+        use super::*;
+
+        fn unit_sphere() -> Arc<dyn Hittable> {
+            Arc::new(Sphere::new(Point::new(0.0, 0.0, 0.0), 1.0, Arc::new(EmptyMaterial)))
+        }
+
+        #[test]
+        fn translated_and_scaled() {
+            let transform = Mat4::translation(Vec3::new(0.0, 0.0, -10.0)) * Mat4::scaling(Vec3::new(2.0, 2.0, 2.0));
+            let instance = Transformed::new(unit_sphere(), transform).unwrap();
+            let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+            let record = instance.hit(ray, 0.0, 100.0).unwrap();
+
+            assert!(approx_eq(record.t, 8.0, 1e-12));
+            assert!(approx_eq(record.hit_point.z(), -8.0, 1e-12));
+            assert!(approx_eq(record.normal.z(), 1.0, 1e-12));
+            assert!(record.front_face);
+
+            let bounding_box = instance.bounding_box().unwrap();
+            assert!(approx_eq(bounding_box.min.z(), -12.0, 1e-12));
+            assert!(approx_eq(bounding_box.max.x(), 2.0, 1e-12));
+        }
+
+        #[test]
+        fn non_uniform_scale_normals() {
+            let transform = Mat4::scaling(Vec3::new(4.0, 1.0, 1.0));
+            let instance = Transformed::new(unit_sphere(), transform).unwrap();
+            let direction = Vec3::new(-1.0, -1.0, 0.0).unit_vector();
+            let ray = Ray::new(Vec3::new(10.0, 10.0, 0.0), direction);
+            let record = instance.hit(ray, 0.0, 100.0).unwrap();
+
+            // On the ellipsoid x^2/16 + y^2 = 1 the normal is proportional to (x/16, y, 0).
+            let p = record.hit_point;
+            let expected = Vec3::new(p.x() / 16.0, p.y(), 0.0).unit_vector();
+            assert!(approx_eq(record.normal.dot(&expected), 1.0, 1e-9));
+            assert!(approx_eq(p.x() * p.x() / 16.0 + p.y() * p.y(), 1.0, 1e-9));
+        }
+
+        #[test]
+        fn rotated_from_inside() {
+            let transform = Mat4::rotation(Vec3::new(0.0, 1.0, 0.0), 45.0);
+            let instance = Transformed::new(unit_sphere(), transform).unwrap();
+            let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+            let record = instance.hit(ray, 0.0, 100.0).unwrap();
+
+            assert!(!record.front_face);
+            assert!(approx_eq(record.normal.x(), -1.0, 1e-12));
+        }
+
+        #[test]
+        fn animated() {
+            let motion = AnimatedTransform::new(
+                vec![TransformStep::Rotate {
+                    axis: Vec3::new(0.0, 1.0, 0.0),
+                    degrees: 0.0,
+                }],
+                vec![TransformStep::Rotate {
+                    axis: Vec3::new(0.0, 1.0, 0.0),
+                    degrees: 90.0,
+                }],
+                0.0,
+                1.0,
+            )
+            .unwrap();
+            let ball: Arc<dyn Hittable> = Arc::new(Sphere::new(Point::new(0.0, 0.0, -4.0), 1.0, Arc::new(EmptyMaterial)));
+            let instance = Transformed::animated(ball, motion).unwrap();
+
+            // A quarter turn about y carries the sphere from -z round to -x.
+            let start_ray = Ray::with_time(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+            let end_ray = Ray::with_time(Vec3::new(0.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0), 1.0);
+            let diagonal = Vec3::new(-1.0, 0.0, -1.0).unit_vector();
+            let middle_ray = Ray::with_time(Vec3::new(0.0, 0.0, 0.0), diagonal, 0.5);
+            assert!(approx_eq(instance.hit(start_ray, 0.0, 100.0).unwrap().t, 3.0, 1e-9));
+            assert!(approx_eq(instance.hit(end_ray, 0.0, 100.0).unwrap().t, 3.0, 1e-9));
+            assert!(approx_eq(instance.hit(middle_ray, 0.0, 100.0).unwrap().t, 3.0, 1e-9));
+
+            // The swept box must contain the sphere at every instant, including the
+            // diagonal position between the sampled times.
+            let bounding_box = instance.bounding_box().unwrap();
+            let extreme = 5.0 * diagonal;
+            assert!(bounding_box.min.x() <= extreme.x() && bounding_box.min.z() <= extreme.z());
+            assert!(bounding_box.max.x() >= 1.0 && bounding_box.max.z() >= 1.0 - 1e-9);
+        }
+
+        #[test]
+        fn animated_inverse() {
+            let axis = Vec3::new(1.0, 2.0, -1.0);
+            let motion = AnimatedTransform::new(
+                vec![
+                    TransformStep::Scale(Vec3::new(1.0, 2.0, 3.0)),
+                    TransformStep::Rotate { axis, degrees: 30.0 },
+                    TransformStep::Translate(Vec3::new(1.0, 0.0, 0.0)),
+                    TransformStep::Translate(Vec3::new(0.0, 0.0, 5.0)),
+                ],
+                vec![
+                    TransformStep::Scale(Vec3::new(1.0, 2.0, 3.0)),
+                    TransformStep::Rotate { axis, degrees: 120.0 },
+                    TransformStep::Translate(Vec3::new(1.0, 0.0, 0.0)),
+                    TransformStep::Translate(Vec3::new(0.0, 4.0, 5.0)),
+                ],
+                0.0,
+                2.0,
+            )
+            .unwrap();
+
+            for &time in &[-1.0, 0.0, 0.7, 1.5, 3.0] {
+                let (transform, inverse) = motion.matrices_at(time).unwrap();
+                let expected = transform.inverse().unwrap();
+                let p = Point::new(0.3, -1.2, 2.5);
+                assert!((transform.transform_point(p) - motion.at(time).transform_point(p)).near_zero());
+                assert!((inverse.transform_point(p) - expected.transform_point(p)).near_zero());
+                assert!((inverse.transform_point(transform.transform_point(p)) - p).near_zero());
+            }
+
+            let flatten = AnimatedTransform::new(
+                vec![TransformStep::Scale(Vec3::new(1.0, 1.0, 1.0))],
+                vec![TransformStep::Scale(Vec3::new(1.0, -1.0, 1.0))],
+                0.0,
+                1.0,
+            )
+            .unwrap();
+            assert!(flatten.matrices_at(0.5).is_none());
+        }
+
+        #[test]
+        fn mismatched_keyframes() {
+            let translate = TransformStep::Translate(Vec3::new(1.0, 0.0, 0.0));
+            let scale = TransformStep::Scale(Vec3::new(2.0, 2.0, 2.0));
+            assert!(AnimatedTransform::new(vec![translate], vec![scale], 0.0, 1.0).is_none());
+            assert!(AnimatedTransform::new(vec![translate], vec![], 0.0, 1.0).is_none());
+            assert!(AnimatedTransform::new(vec![translate], vec![translate], 1.0, 0.0).is_none());
+        }
+
+        #[test]
+        fn sampled_density_integrates_to_one() {
+            // A squashed, turned and moved sphere seen from close by covers a large solid
+            // angle, over which the density its samples are given must integrate to one.
+            let transform = Mat4::translation(Vec3::new(0.5, 0.0, -2.0))
+                * Mat4::rotation(Vec3::new(1.0, 1.0, 0.0), 40.0)
+                * Mat4::scaling(Vec3::new(1.5, 0.5, 1.0));
+            let instance = Transformed::new(unit_sphere(), transform).unwrap();
+            let origin = Point::new(0.0, 0.0, 0.0);
+
+            crate::random::seed(11);
+            let samples = 200_000;
+            let total: f64 = (0..samples)
+                .map(|_| instance.pdf_value(origin, crate::linear_algebra::random_unit_vector(), 0.0))
+                .sum();
+            let integral = 4.0 * std::f64::consts::PI * total / samples as f64;
+            assert!(approx_eq(integral, 1.0, 0.02), "{}", integral);
+
+            for _ in 0..100 {
+                let direction = instance.random_direction(origin, 0.0).unwrap();
+                assert!(instance.hit(Ray::new(origin, direction), 0.001, f64::INFINITY).is_some());
+                assert!(instance.pdf_value(origin, direction, 0.0) > 0.0);
+            }
+        }
+
+        #[test]
+        fn singular() {
+            let transform = Mat4::scaling(Vec3::new(1.0, 0.0, 1.0));
+            assert!(Transformed::new(unit_sphere(), transform).is_none());
+        }
+    }
+
+    fn approx_eq(x: f64, y: f64, tolerance: f64) -> bool {
+        (x - y).abs() < tolerance
+    }
+}
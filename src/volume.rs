@@ -0,0 +1,577 @@
+use crate::geometry::{Aabb, HitRecord, Hittable};
+use crate::linear_algebra::{Ray, Vec3};
+use crate::materials::{Isotropic, Material};
+use crate::noise::Perlin;
+use crate::random::rand;
+use crate::texture::{NoisePattern, SolidColour, Texture};
+use crate::{Colour, Point};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// Gap left after a boundary crossing before searching for the next one.
+const BOUNDARY_EPSILON: f64 = 1e-4;
+
+// The stretches of a ray between t_min and t_max that lie inside a closed boundary, in order.
+// Walking every entry/exit pair means boundaries need not be convex, and a ray starting inside
+// is handled by searching back from minus infinity for the first crossing.
+struct Segments<'a> {
+    boundary: &'a dyn Hittable,
+    ray: Ray,
+    t_min: f64,
+    t_max: f64,
+    search_from: f64,
+}
+
+impl<'a> Segments<'a> {
+    fn new(boundary: &'a dyn Hittable, ray: Ray, t_min: f64, t_max: f64) -> Self {
+        Self {
+            boundary,
+            ray,
+            t_min,
+            t_max,
+            search_from: f64::NEG_INFINITY,
+        }
+    }
+}
+
+impl Iterator for Segments<'_> {
+    type Item = (f64, f64);
+
+    fn next(&mut self) -> Option<(f64, f64)> {
+        while self.search_from < self.t_max {
+            let entry = self.boundary.hit(self.ray, self.search_from, f64::INFINITY)?;
+            let exit = self.boundary.hit(self.ray, entry.t + BOUNDARY_EPSILON, f64::INFINITY)?;
+            self.search_from = exit.t + BOUNDARY_EPSILON;
+
+            let t0 = entry.t.max(self.t_min);
+            let t1 = exit.t.min(self.t_max);
+            if t0 < t1 {
+                return Some((t0, t1));
+            }
+        }
+        None
+    }
+}
+
+// Scattering inside a volume has no surface, so the normal is arbitrary.
+fn volume_hit(t: f64, ray: Ray, phase_function: &Arc<dyn Material>) -> HitRecord {
+    let mut record = HitRecord::new(t, ray, Vec3::new(1.0, 0.0, 0.0), phase_function.clone());
+    record.normal = Vec3::new(1.0, 0.0, 0.0);
+    record.front_face = true;
+    record
+}
+
+// A free-flight distance, in units of t, through a medium of the given density.
+fn sample_distance(density: f64, ray_length: f64) -> f64 {
+    -(1.0 - rand(0.0, 1.0)).ln() / (density * ray_length)
+}
+
+// A homogeneous participating medium filling a closed boundary. Rays scatter at exponentially
+// distributed distances inside it, so thin regions are mostly transparent and thick ones
+// mostly opaque.
+pub struct ConstantMedium {
+    boundary: Arc<dyn Hittable>,
+    density: f64,
+    phase_function: Arc<dyn Material>,
+}
+
+impl ConstantMedium {
+    pub fn new(boundary: Arc<dyn Hittable>, density: f64, albedo: Colour) -> Self {
+        Self::with_texture(boundary, density, Arc::new(SolidColour::new(albedo)))
+    }
+
+    pub fn with_texture(boundary: Arc<dyn Hittable>, density: f64, albedo: Arc<dyn Texture>) -> Self {
+        Self {
+            boundary,
+            density,
+            phase_function: Arc::new(Isotropic::with_texture(albedo)),
+        }
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let ray_length = ray.direction.length();
+
+        // The exponential distribution is memoryless, so sampling each segment afresh is the
+        // same as sampling the total distance once.
+        for (t0, t1) in Segments::new(&*self.boundary, ray, t_min, t_max) {
+            let t = t0 + sample_distance(self.density, ray_length);
+            if t < t1 {
+                return Some(volume_hit(t, ray, &self.phase_function));
+            }
+        }
+        None
+    }
+
+    fn transmittance(&self, ray: Ray, t_min: f64, t_max: f64) -> f64 {
+        let ray_length = ray.direction.length();
+        let inside: f64 = Segments::new(&*self.boundary, ray, t_min, t_max)
+            .map(|(t0, t1)| (t1 - t0) * ray_length)
+            .sum();
+        (-self.density * inside).exp()
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.boundary.bounding_box()
+    }
+}
+
+// A density that varies through space, with a known upper bound (the majorant) used to
+// sample it by tracking.
+pub trait DensityField: Send + Sync {
+    fn density(&self, point: Point) -> f64;
+    fn max_density(&self) -> f64;
+}
+
+// Density following one of the procedural noise patterns, scaled to [0, `density`].
+pub struct NoiseDensity {
+    noise: Arc<Perlin>,
+    pattern: NoisePattern,
+    scale: f64,
+    density: f64,
+}
+
+impl NoiseDensity {
+    pub fn new(noise: Arc<Perlin>, pattern: NoisePattern, scale: f64, density: f64) -> Self {
+        Self {
+            noise,
+            pattern,
+            scale,
+            density,
+        }
+    }
+}
+
+impl DensityField for NoiseDensity {
+    fn density(&self, point: Point) -> f64 {
+        self.density * self.pattern.evaluate(&self.noise, self.scale * point)
+    }
+
+    fn max_density(&self) -> f64 {
+        self.density
+    }
+}
+
+// Densities sampled on a regular grid of voxels filling the box from `min` to `max`, with x
+// varying fastest and z slowest. Between voxel centres the density is trilinearly
+// interpolated; outside the box it is zero.
+#[derive(Debug)]
+pub struct VoxelGrid {
+    resolution: [usize; 3],
+    values: Vec<f64>,
+    min: Point,
+    max: Point,
+    max_density: f64,
+}
+
+impl VoxelGrid {
+    pub fn new(resolution: [usize; 3], values: Vec<f64>, min: Point, max: Point) -> Self {
+        assert_eq!(Some(values.len()), voxel_count(resolution), "voxel grid has the wrong size");
+        let max_density = values.iter().cloned().fold(0.0, f64::max);
+
+        Self {
+            resolution,
+            values,
+            min,
+            max,
+            max_density,
+        }
+    }
+
+    pub fn resolution(&self) -> [usize; 3] {
+        self.resolution
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        Aabb::new(self.min, self.max)
+    }
+
+    fn voxel(&self, index: [usize; 3]) -> f64 {
+        let [nx, ny, _] = self.resolution;
+        self.values[(index[2] * ny + index[1]) * nx + index[0]]
+    }
+}
+
+impl DensityField for VoxelGrid {
+    fn density(&self, point: Point) -> f64 {
+        let mut lower = [0; 3];
+        let mut upper = [0; 3];
+        let mut weight = [0.0; 3];
+
+        for axis in 0..3 {
+            if point[axis] < self.min[axis] || point[axis] > self.max[axis] {
+                return 0.0;
+            }
+            let n = self.resolution[axis];
+            // Voxel centres sit at half-integer grid coordinates.
+            let g = (point[axis] - self.min[axis]) / (self.max[axis] - self.min[axis]) * n as f64 - 0.5;
+            let g = g.clamp(0.0, (n - 1) as f64);
+            lower[axis] = g.floor() as usize;
+            upper[axis] = (lower[axis] + 1).min(n - 1);
+            weight[axis] = g - lower[axis] as f64;
+        }
+
+        let mut density = 0.0;
+        for corner in 0..8 {
+            let mut index = [0; 3];
+            let mut corner_weight = 1.0;
+            for axis in 0..3 {
+                if corner >> axis & 1 == 1 {
+                    index[axis] = upper[axis];
+                    corner_weight *= weight[axis];
+                } else {
+                    index[axis] = lower[axis];
+                    corner_weight *= 1.0 - weight[axis];
+                }
+            }
+            density += corner_weight * self.voxel(index);
+        }
+        density
+    }
+
+    fn max_density(&self) -> f64 {
+        self.max_density
+    }
+}
+
+#[derive(Debug)]
+pub struct VolumeError {
+    pub path: PathBuf,
+    pub message: String,
+}
+
+impl fmt::Display for VolumeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.message)
+    }
+}
+
+impl std::error::Error for VolumeError {}
+
+pub fn load_voxel_grid(path: &Path, min: Point, max: Point) -> Result<VoxelGrid, VolumeError> {
+    let error = |message: String| VolumeError {
+        path: path.to_path_buf(),
+        message,
+    };
+    let bytes = fs::read(path).map_err(|e| error(e.to_string()))?;
+    parse_voxel_grid(&bytes, min, max).map_err(error)
+}
+
+// The number of voxels in a grid of the given resolution, or None if it overflows.
+fn voxel_count(resolution: [usize; 3]) -> Option<usize> {
+    resolution.iter().try_fold(1usize, |count, &n| count.checked_mul(n))
+}
+
+// Voxel files come in two forms. The text form is the word `voxels`, the resolution along x,
+// y and z, then one density per voxel, all separated by whitespace, with `#` starting a
+// comment. The binary form is the bytes `VOXB`, the resolution as three little-endian u32s
+// and the densities as little-endian f32s.
+pub fn parse_voxel_grid(bytes: &[u8], min: Point, max: Point) -> Result<VoxelGrid, String> {
+    let (resolution, values) = if let Some(data) = bytes.strip_prefix(b"VOXB") {
+        let header: Vec<usize> = data
+            .chunks_exact(4)
+            .take(3)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+            .collect();
+        if header.len() < 3 {
+            return Err(String::from("truncated voxel header"));
+        }
+        let resolution = [header[0], header[1], header[2]];
+        let values: Vec<f64> = data[12..]
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64)
+            .collect();
+        (resolution, values)
+    } else {
+        let text = std::str::from_utf8(bytes).map_err(|_| String::from("voxel file is neither text nor VOXB binary"))?;
+        let mut tokens = text
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or(""))
+            .flat_map(str::split_whitespace);
+        if tokens.next() != Some("voxels") {
+            return Err(String::from("voxel file must start with 'voxels' or 'VOXB'"));
+        }
+        let mut resolution = [0; 3];
+        for n in &mut resolution {
+            let token = tokens.next().ok_or("truncated voxel header")?;
+            *n = token
+                .parse()
+                .map_err(|_| format!("invalid voxel resolution '{}'", token))?;
+        }
+        let values = tokens
+            .map(|token| token.parse::<f64>().map_err(|_| format!("invalid density '{}'", token)))
+            .collect::<Result<Vec<_>, _>>()?;
+        (resolution, values)
+    };
+
+    if resolution.contains(&0) {
+        return Err(String::from("voxel resolution must be at least 1 on every axis"));
+    }
+    let count = voxel_count(resolution).ok_or("voxel resolution is too large")?;
+    if values.len() != count {
+        return Err(format!("expected {} densities but found {}", count, values.len()));
+    }
+    if values.iter().any(|value| !value.is_finite() || *value < 0.0) {
+        return Err(String::from("densities must be finite and not negative"));
+    }
+
+    Ok(VoxelGrid::new(resolution, values, min, max))
+}
+
+// A participating medium whose density varies within its boundary. Collisions are sampled
+// by delta tracking and shadow-ray transmittance is estimated by ratio tracking, both of which
+// are unbiased however the density varies below the field's majorant.
+pub struct HeterogeneousMedium {
+    boundary: Arc<dyn Hittable>,
+    density: Arc<dyn DensityField>,
+    phase_function: Arc<dyn Material>,
+}
+
+impl HeterogeneousMedium {
+    pub fn new(boundary: Arc<dyn Hittable>, density: Arc<dyn DensityField>, albedo: Arc<dyn Texture>) -> Self {
+        Self {
+            boundary,
+            density,
+            phase_function: Arc::new(Isotropic::with_texture(albedo)),
+        }
+    }
+}
+
+impl Hittable for HeterogeneousMedium {
+    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let majorant = self.density.max_density();
+        if majorant <= 0.0 {
+            return None;
+        }
+        let ray_length = ray.direction.length();
+
+        // Delta tracking: step through a fictitious homogeneous medium of the majorant's
+        // density and accept each tentative collision with probability density / majorant.
+        for (t0, t1) in Segments::new(&*self.boundary, ray, t_min, t_max) {
+            let mut t = t0;
+            loop {
+                t += sample_distance(majorant, ray_length);
+                if t >= t1 {
+                    break;
+                }
+                if rand(0.0, majorant) < self.density.density(ray.at(t)) {
+                    return Some(volume_hit(t, ray, &self.phase_function));
+                }
+            }
+        }
+        None
+    }
+
+    fn transmittance(&self, ray: Ray, t_min: f64, t_max: f64) -> f64 {
+        let majorant = self.density.max_density();
+        if majorant <= 0.0 {
+            return 1.0;
+        }
+        let ray_length = ray.direction.length();
+
+        // Ratio tracking: take the same tentative collisions as delta tracking, but weight by
+        // the probability of passing each one rather than stopping at random.
+        let mut transmittance = 1.0;
+        for (t0, t1) in Segments::new(&*self.boundary, ray, t_min, t_max) {
+            let mut t = t0;
+            loop {
+                t += sample_distance(majorant, ray_length);
+                if t >= t1 {
+                    break;
+                }
+                transmittance *= 1.0 - self.density.density(ray.at(t)) / majorant;
+            }
+        }
+        transmittance
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.boundary.bounding_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::{HittableList, Sphere};
+    use crate::materials::EmptyMaterial;
+
+    mod constant_medium_tests {
+        use super::*;
+
+        fn sphere(centre: Point) -> Arc<dyn Hittable> {
+            Arc::new(Sphere::new(centre, 1.0, Arc::new(EmptyMaterial)))
+        }
+
+        fn hits(medium: &ConstantMedium, ray: Ray, t_min: f64, t_max: f64) -> Vec<f64> {
+            crate::random::seed(42);
+            (0..4000).filter_map(|_| medium.hit(ray, t_min, t_max)).map(|record| record.t).collect()
+        }
+
+        #[test]
+        fn transmittance() {
+            // Through a unit sphere the ray travels 2 units inside, so Beer's law gives a
+            // scattering probability of 1 - exp(-2 * density).
+            let medium = ConstantMedium::new(sphere(Point::new(0.0, 0.0, -3.0)), 0.5, Colour::new(1.0, 1.0, 1.0));
+            let ray = Ray::new(Point::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -2.0));
+            let hits = hits(&medium, ray, 0.001, f64::INFINITY);
+            let expected = 1.0 - (-1.0f64).exp();
+
+            assert!((hits.len() as f64 / 4000.0 - expected).abs() < 0.03);
+            assert!(hits.iter().all(|&t| (1.0..=2.0).contains(&t)));
+        }
+
+        #[test]
+        fn starts_inside() {
+            let medium = ConstantMedium::new(sphere(Point::new(0.0, 0.0, 0.0)), 1.0, Colour::new(1.0, 1.0, 1.0));
+            let ray = Ray::new(Point::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+            let hits = hits(&medium, ray, 0.001, f64::INFINITY);
+            let expected = 1.0 - (-1.0f64).exp();
+
+            assert!((hits.len() as f64 / 4000.0 - expected).abs() < 0.03);
+            assert!(hits.iter().all(|&t| t <= 1.0));
+        }
+
+        #[test]
+        fn non_convex_boundary() {
+            // Two separate spheres along the ray: each contributes a segment of length 2.
+            let mut spheres = HittableList::new();
+            spheres.add(sphere(Point::new(0.0, 0.0, -3.0)));
+            spheres.add(sphere(Point::new(0.0, 0.0, -7.0)));
+            let medium = ConstantMedium::new(Arc::new(spheres), 0.25, Colour::new(1.0, 1.0, 1.0));
+            let ray = Ray::new(Point::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+            let hits = hits(&medium, ray, 0.001, f64::INFINITY);
+            let expected = 1.0 - (-1.0f64).exp();
+
+            assert!((hits.len() as f64 / 4000.0 - expected).abs() < 0.03);
+            assert!(hits.iter().any(|&t| t > 6.0));
+            assert!(hits.iter().all(|&t| (2.0..=4.0).contains(&t) || (6.0..=8.0).contains(&t)));
+
+            // Nothing is scattered beyond t_max.
+            assert!(medium.hit(ray, 0.001, 2.0).is_none());
+        }
+
+        #[test]
+        fn analytic_transmittance() {
+            let medium = ConstantMedium::new(sphere(Point::new(0.0, 0.0, -3.0)), 0.5, Colour::new(1.0, 1.0, 1.0));
+            let ray = Ray::new(Point::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -2.0));
+
+            assert!((medium.transmittance(ray, 0.001, f64::INFINITY) - (-1.0f64).exp()).abs() < 1e-9);
+            // Stopping halfway through the sphere halves the optical depth.
+            assert!((medium.transmittance(ray, 0.001, 1.5) - (-0.5f64).exp()).abs() < 1e-9);
+            assert_eq!(medium.transmittance(ray, 0.001, 0.5), 1.0);
+        }
+    }
+
+    mod voxel_grid_tests {
+        use super::*;
+
+        fn grid(text: &str) -> Result<VoxelGrid, String> {
+            parse_voxel_grid(text.as_bytes(), Point::new(0.0, 0.0, 0.0), Point::new(2.0, 1.0, 1.0))
+        }
+
+        #[test]
+        fn trilinear() {
+            let grid = grid("voxels 2 1 1\n# left, right\n0.0 4.0\n").unwrap();
+
+            assert_eq!(grid.resolution(), [2, 1, 1]);
+            assert!(approx_eq(grid.max_density(), 4.0));
+            // Voxel centres sit at x = 0.5 and 1.5; beyond them the edge value holds.
+            assert!(approx_eq(grid.density(Point::new(0.5, 0.5, 0.5)), 0.0));
+            assert!(approx_eq(grid.density(Point::new(1.0, 0.2, 0.7)), 2.0));
+            assert!(approx_eq(grid.density(Point::new(1.25, 0.5, 0.5)), 3.0));
+            assert!(approx_eq(grid.density(Point::new(1.9, 0.5, 0.5)), 4.0));
+            assert_eq!(grid.density(Point::new(2.1, 0.5, 0.5)), 0.0);
+            assert_eq!(grid.density(Point::new(1.0, -0.1, 0.5)), 0.0);
+        }
+
+        #[test]
+        fn binary() {
+            let mut bytes = b"VOXB".to_vec();
+            for n in [1u32, 2, 1] {
+                bytes.extend_from_slice(&n.to_le_bytes());
+            }
+            for value in [1.0f32, 3.0] {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            let grid = parse_voxel_grid(&bytes, Point::new(0.0, 0.0, 0.0), Point::new(1.0, 2.0, 1.0)).unwrap();
+
+            assert_eq!(grid.resolution(), [1, 2, 1]);
+            assert!(approx_eq(grid.density(Point::new(0.5, 1.0, 0.5)), 2.0));
+        }
+
+        #[test]
+        fn errors() {
+            assert!(grid("VOXELS 1 1 1 0").unwrap_err().contains("must start with"));
+            assert!(grid("voxels 2 1").unwrap_err().contains("truncated"));
+            assert!(grid("voxels 2 1 1 1.0").unwrap_err().contains("expected 2 densities but found 1"));
+            assert!(grid("voxels 0 1 1").unwrap_err().contains("at least 1"));
+            assert!(grid("voxels 1 1 1 -1").unwrap_err().contains("not negative"));
+            assert!(grid("voxels 1 1 1 fog").unwrap_err().contains("invalid density 'fog'"));
+            assert_eq!(grid("voxels 99999999999 99999999999 9").unwrap_err(), "voxel resolution is too large");
+            assert!(parse_voxel_grid(b"VOXB\x01\x00", Point::new(0.0, 0.0, 0.0), Point::new(1.0, 1.0, 1.0)).is_err());
+            let huge = [b"VOXB".as_slice(), &[0xff; 12]].concat();
+            let error = parse_voxel_grid(&huge, Point::new(0.0, 0.0, 0.0), Point::new(1.0, 1.0, 1.0)).unwrap_err();
+            assert_eq!(error, "voxel resolution is too large");
+
+            let error = load_voxel_grid(Path::new("missing.vox"), Point::new(0.0, 0.0, 0.0), Point::new(1.0, 1.0, 1.0));
+            assert!(error.unwrap_err().to_string().starts_with("missing.vox: "));
+        }
+    }
+
+    mod heterogeneous_medium_tests {
+        use super::*;
+
+        fn unit_cube_grid(values: Vec<f64>, resolution: [usize; 3]) -> Arc<VoxelGrid> {
+            Arc::new(VoxelGrid::new(resolution, values, Point::new(0.0, 0.0, 0.0), Point::new(1.0, 1.0, 1.0)))
+        }
+
+        fn medium(density: Arc<dyn DensityField>) -> HeterogeneousMedium {
+            let boundary = Arc::new(Sphere::new(Point::new(0.5, 0.5, 0.5), 0.5, Arc::new(EmptyMaterial)));
+            HeterogeneousMedium::new(boundary, density, Arc::new(SolidColour::new(Colour::new(1.0, 1.0, 1.0))))
+        }
+
+        #[test]
+        fn ratio_tracking_matches_beer_lambert() {
+            // A uniform grid behaves like a constant medium: a diameter of 1 at density 1.5.
+            let medium = medium(unit_cube_grid(vec![1.5; 8], [2, 2, 2]));
+            let ray = Ray::new(Point::new(0.5, 0.5, 3.0), Vec3::new(0.0, 0.0, -1.0));
+            crate::random::seed(42);
+            let mean = (0..4000).map(|_| medium.transmittance(ray, 0.001, f64::INFINITY)).sum::<f64>() / 4000.0;
+
+            assert!((mean - (-1.5f64).exp()).abs() < 0.02);
+        }
+
+        #[test]
+        fn delta_tracking_agrees_with_ratio_tracking() {
+            let density = NoiseDensity::new(Arc::new(Perlin::new(3)), NoisePattern::Cloud, 4.0, 3.0);
+            let medium = medium(Arc::new(density));
+            let ray = Ray::new(Point::new(0.4, 0.6, 3.0), Vec3::new(0.1, -0.1, -2.0));
+            crate::random::seed(42);
+            let hits: Vec<f64> = (0..4000).filter_map(|_| medium.hit(ray, 0.001, f64::INFINITY)).map(|r| r.t).collect();
+            let transmitted = 1.0 - hits.len() as f64 / 4000.0;
+            let ratio = (0..4000).map(|_| medium.transmittance(ray, 0.001, f64::INFINITY)).sum::<f64>() / 4000.0;
+
+            assert!((transmitted - ratio).abs() < 0.03);
+            assert!(transmitted > 0.05 && transmitted < 0.95);
+            // The sphere spans z in [0, 1], reached between t = 1 and t = 1.5.
+            assert!(hits.iter().all(|&t| (1.0..=1.5).contains(&t)));
+        }
+
+        #[test]
+        fn empty_field_is_transparent() {
+            let medium = medium(unit_cube_grid(vec![0.0], [1, 1, 1]));
+            let ray = Ray::new(Point::new(0.5, 0.5, 3.0), Vec3::new(0.0, 0.0, -1.0));
+
+            assert!(medium.hit(ray, 0.001, f64::INFINITY).is_none());
+            assert_eq!(medium.transmittance(ray, 0.001, f64::INFINITY), 1.0);
+        }
+    }
+
+    fn approx_eq(x: f64, y: f64) -> bool {
+        (x - y).abs() < 1e-9
+    }
+}